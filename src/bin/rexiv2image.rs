@@ -0,0 +1,353 @@
+extern crate image;
+extern crate rexiv2image;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+use image::{ColorType, ImageDecoder};
+use rexiv2image::metadata::{DecoderWithMetadata, Rexiv2ImageError};
+use rexiv2image::metadata::backend::MetadataBackend;
+use rexiv2image::metadata::copy::{copy_metadata, CopyPolicy};
+use rexiv2image::metadata::strip::StripProfile;
+use rexiv2image::orientation::Orientation;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage:
+    rexiv2image dump [--json] FILE
+    rexiv2image get FILE TAG
+    rexiv2image set FILE TAG VALUE
+    rexiv2image strip [--profile all|location|personal|copyright] FILE
+    rexiv2image copy-from SOURCE FILE
+    rexiv2image info FILE";
+
+//Exit codes, 1 is left to panics
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_FORMAT: i32 = 4;
+const EXIT_DECODER: i32 = 5;
+const EXIT_METADATA: i32 = 6;
+const EXIT_TAG_NOT_FOUND: i32 = 7;
+const EXIT_UNSUPPORTED_TAG: i32 = 8;
+const EXIT_INVALID_VALUE: i32 = 9;
+const EXIT_NOT_FOUND: i32 = 10;
+
+enum CliError {
+    Usage(String),
+    Failed(Rexiv2ImageError),
+}
+
+impl From<Rexiv2ImageError> for CliError {
+    fn from(err: Rexiv2ImageError) -> CliError {
+        CliError::Failed(err)
+    }
+}
+
+fn exit_code(err: &Rexiv2ImageError) -> i32 {
+    match *err {
+        Rexiv2ImageError::MetadataError(_) => EXIT_METADATA,
+        Rexiv2ImageError::DecoderError(_) => EXIT_DECODER,
+        Rexiv2ImageError::Io(_) => EXIT_IO,
+        Rexiv2ImageError::UnsupportedFormat(_) | Rexiv2ImageError::UnknownFormat => EXIT_FORMAT,
+        Rexiv2ImageError::TagNotFound(_) => EXIT_TAG_NOT_FOUND,
+        Rexiv2ImageError::UnsupportedTag(_) => EXIT_UNSUPPORTED_TAG,
+        Rexiv2ImageError::InvalidTagValue(..) => EXIT_INVALID_VALUE,
+        Rexiv2ImageError::PreviewNotFound(_) | Rexiv2ImageError::PageNotFound(_) | Rexiv2ImageError::IconNotFound(_) => {
+            EXIT_NOT_FOUND
+        },
+        Rexiv2ImageError::WithPath(_, ref err) => exit_code(err),
+    }
+}
+
+//Options are never taken for file names, `dump --json` without a file is a usage error
+fn file(arg: &str) -> Result<&str, CliError> {
+    if arg.starts_with("--") {
+        return Err(CliError::Usage(format!("Expected a file, found the option {}", arg)));
+    }
+    Ok(arg)
+}
+
+fn open(path: &str) -> Result<DecoderWithMetadata<File>, CliError> {
+    Ok(DecoderWithMetadata::open(Path::new(path))?)
+}
+
+fn save(decoder: &mut DecoderWithMetadata<File>, path: &str) -> Result<(), CliError> {
+    Ok(decoder.save_metadata(Path::new(path))?)
+}
+
+//The JSON output is the serialized MetadataSnapshot, the text output has one line per readable tag
+fn dump(path: &str, json: bool) -> Result<(), CliError> {
+    let decoder = open(path)?;
+
+    if json {
+        return dump_json(&decoder, &mut io::stdout());
+    }
+    for tag in decoder.metadata.list_tags()? {
+        if let Ok(value) = decoder.metadata.read_tag(&tag) {
+            println!("{:<45} {}", tag, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn dump_json<W: Write>(decoder: &DecoderWithMetadata<File>, output: &mut W) -> Result<(), CliError> {
+    let json = serde_json::to_string_pretty(&decoder.snapshot()?)
+        .map_err(|err| Rexiv2ImageError::Io(err.into()))?;
+
+    writeln!(output, "{}", json).map_err(Rexiv2ImageError::from)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn dump_json<W: Write>(_decoder: &DecoderWithMetadata<File>, _output: &mut W) -> Result<(), CliError> {
+    Err(CliError::Usage("JSON output needs the serde feature".to_string()))
+}
+
+fn strip_profile(name: &str) -> Result<StripProfile, CliError> {
+    match name {
+        "all" => Ok(StripProfile::strip_all()),
+        "location" => Ok(StripProfile::strip_location()),
+        "personal" => Ok(StripProfile::strip_personal()),
+        "copyright" => Ok(StripProfile::keep_copyright_only()),
+        _ => Err(CliError::Usage(format!("Unknown strip profile: {}", name))),
+    }
+}
+
+fn strip(path: &str, profile: StripProfile) -> Result<(), CliError> {
+    let mut decoder = open(path)?;
+    let report = decoder.strip_metadata(&profile)?;
+
+    if !report.is_empty() {
+        save(&mut decoder, path)?;
+    }
+    for tag in report.removed {
+        println!("Removed {}", tag);
+    }
+    Ok(())
+}
+
+fn color_type(color: ColorType) -> String {
+    match color {
+        ColorType::Gray(bits) => format!("gray, {} bits", bits),
+        ColorType::RGB(bits) => format!("RGB, {} bits", bits),
+        ColorType::Palette(bits) => format!("palette, {} bits", bits),
+        ColorType::GrayA(bits) => format!("gray with alpha, {} bits", bits),
+        ColorType::RGBA(bits) => format!("RGBA, {} bits", bits),
+    }
+}
+
+fn orientation_name(orientation: Orientation) -> &'static str {
+    match orientation {
+        Orientation::Unspecified => "none",
+        Orientation::Normal => "normal",
+        Orientation::HorizontalFlip => "flipped horizontally",
+        Orientation::Rotate180 => "rotated 180°",
+        Orientation::VerticalFlip => "flipped vertically",
+        Orientation::Rotate90HorizontalFlip => "rotated 90° clockwise and flipped horizontally",
+        Orientation::Rotate90 => "rotated 90° clockwise",
+        Orientation::Rotate90VerticalFlip => "rotated 90° clockwise and flipped vertically",
+        Orientation::Rotate270 => "rotated 270° clockwise",
+    }
+}
+
+//Dimensions as stored, before any orientation is applied
+fn info(path: &str) -> Result<(), CliError> {
+    let mut decoder = open(path)?;
+    let (width, height) = decoder.dimensions().map_err(Rexiv2ImageError::from)?;
+    let color = decoder.colortype().map_err(Rexiv2ImageError::from)?;
+
+    println!("Format:      {:?}", decoder.format());
+    println!("Dimensions:  {}x{}", width, height);
+    println!("Color type:  {}", color_type(color));
+    println!("Orientation: {}", orientation_name(decoder.metadata.read_orientation()));
+    println!("Tags:        {}", decoder.metadata.list_tags()?.len());
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["dump", path] => dump(file(path)?, false),
+        ["dump", "--json", path] => dump(file(path)?, true),
+        ["get", path, tag] => {
+            println!("{}", open(file(path)?)?.metadata.read_tag(tag)?);
+            Ok(())
+        },
+        ["set", path, tag, value] => {
+            let mut decoder = open(file(path)?)?;
+
+            decoder.metadata.write_tag(tag, value)?;
+            save(&mut decoder, path)
+        },
+        ["strip", path] => strip(file(path)?, StripProfile::strip_all()),
+        ["strip", "--profile", profile, path] => strip(file(path)?, strip_profile(profile)?),
+        ["copy-from", source, path] => {
+            let mut decoder = open(file(path)?)?;
+
+            copy_metadata(&open(file(source)?)?, &mut decoder, &CopyPolicy::new())?;
+            save(&mut decoder, path)
+        },
+        ["info", path] => info(file(path)?),
+        [] => Err(CliError::Usage("Missing command".to_string())),
+        _ => Err(CliError::Usage(format!("Invalid arguments: {}", args.join(" ")))),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(()) => (),
+        Err(CliError::Usage(message)) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        },
+        Err(CliError::Failed(err)) => {
+            eprintln!("rexiv2image: {}", err);
+            process::exit(exit_code(&err));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rexiv2;
+
+    use image::{ColorType, ImageError, ImageFormat};
+    use image::png::PNGEncoder;
+    use rexiv2image::metadata::{DecoderWithMetadata, Rexiv2ImageError};
+    use rexiv2image::metadata::backend::MetadataBackend;
+    use std::env;
+    use std::fs;
+    use std::io::{self, ErrorKind};
+    use std::path::PathBuf;
+    use std::process;
+    use self::rexiv2::Rexiv2Error;
+    use super::*;
+
+    //Removed when dropped
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str) -> TestFile {
+            let path = env::temp_dir().join(format!("rexiv2image-cli-{}-{}.png", process::id(), name));
+            let mut png = Vec::new();
+
+            PNGEncoder::new(&mut png).encode(&[0, 80, 160, 240], 2, 2, ColorType::Gray(8)).unwrap();
+            fs::write(&path, png).unwrap();
+            TestFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        fn tag(&self, tag: &str) -> Option<String> {
+            DecoderWithMetadata::open(&self.0).unwrap().metadata.read_tag(tag).ok()
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    //Exit code of the process for these arguments
+    fn status(args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        match run(&args) {
+            Ok(()) => 0,
+            Err(CliError::Usage(_)) => EXIT_USAGE,
+            Err(CliError::Failed(err)) => exit_code(&err),
+        }
+    }
+
+    #[test]
+    fn exit_codes() {
+        let codes = vec![
+            (Rexiv2ImageError::MetadataError(Rexiv2Error::Internal(None)), EXIT_METADATA),
+            (Rexiv2ImageError::DecoderError(ImageError::DimensionError), EXIT_DECODER),
+            (Rexiv2ImageError::Io(io::Error::from(ErrorKind::NotFound)), EXIT_IO),
+            (Rexiv2ImageError::UnsupportedFormat(ImageFormat::WEBP), EXIT_FORMAT),
+            (Rexiv2ImageError::UnknownFormat, EXIT_FORMAT),
+            (Rexiv2ImageError::TagNotFound("Exif.Image.Make".to_string()), EXIT_TAG_NOT_FOUND),
+            (Rexiv2ImageError::UnsupportedTag("Exif.Image.Make".to_string()), EXIT_UNSUPPORTED_TAG),
+            (Rexiv2ImageError::InvalidTagValue("Exif.Photo.FNumber".to_string(), "2".to_string()), EXIT_INVALID_VALUE),
+            (Rexiv2ImageError::PreviewNotFound(1), EXIT_NOT_FOUND),
+            (Rexiv2ImageError::PageNotFound(1), EXIT_NOT_FOUND),
+            (Rexiv2ImageError::IconNotFound(1), EXIT_NOT_FOUND),
+            (Rexiv2ImageError::UnknownFormat.with_path(Path::new("image")), EXIT_FORMAT),
+        ];
+
+        for (err, code) in codes {
+            assert_eq!(exit_code(&err), code, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn usage() {
+        assert_eq!(status(&[]), EXIT_USAGE);
+        assert_eq!(status(&["resize", "image.png"]), EXIT_USAGE);
+        assert_eq!(status(&["dump", "--json"]), EXIT_USAGE);
+        assert_eq!(status(&["info", "--verbose"]), EXIT_USAGE);
+        assert_eq!(status(&["strip", "--profile", "everything", "image.png"]), EXIT_USAGE);
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = env::temp_dir().join(format!("rexiv2image-cli-{}-missing.png", process::id()));
+
+        assert_eq!(status(&["info", path.to_str().unwrap()]), EXIT_IO);
+    }
+
+    #[test]
+    fn set_get_strip() {
+        let image = TestFile::new("set");
+
+        assert_eq!(status(&["info", image.path()]), 0);
+        assert_eq!(status(&["get", image.path(), "Exif.Image.Make"]), EXIT_TAG_NOT_FOUND);
+        assert_eq!(status(&["set", image.path(), "Exif.Image.Make", "Camera"]), 0);
+        assert_eq!(image.tag("Exif.Image.Make"), Some("Camera".to_string()));
+        assert_eq!(status(&["get", image.path(), "Exif.Image.Make"]), 0);
+        assert_eq!(status(&["dump", image.path()]), 0);
+        assert_eq!(status(&["strip", image.path()]), 0);
+        assert_eq!(image.tag("Exif.Image.Make"), None);
+    }
+
+    #[test]
+    fn copy_from() {
+        let source = TestFile::new("source");
+        let destination = TestFile::new("destination");
+
+        assert_eq!(status(&["set", source.path(), "Exif.Image.Artist", "Someone"]), 0);
+        assert_eq!(status(&["copy-from", source.path(), destination.path()]), 0);
+        assert_eq!(destination.tag("Exif.Image.Artist"), Some("Someone".to_string()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn dump_json() {
+        let image = TestFile::new("json");
+
+        let mut output = Vec::new();
+
+        assert_eq!(status(&["set", image.path(), "Exif.Image.Make", "Camera"]), 0);
+        assert_eq!(status(&["dump", "--json", image.path()]), 0);
+        assert!(super::dump_json(&DecoderWithMetadata::open(&image.0).unwrap(), &mut output).is_ok());
+
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["tags"]["Exif.Image.Make"]["value"], serde_json::json!({"kind": "text", "data": "Camera"}));
+    }
+
+    #[test]
+    fn orientation_names() {
+        assert_eq!(orientation_name(Orientation::Unspecified), "none");
+        assert_eq!(orientation_name(Orientation::Rotate90), "rotated 90° clockwise");
+    }
+}
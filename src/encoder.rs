@@ -0,0 +1,347 @@
+use rexiv2::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use image::*;
+use image::png::PNGEncoder;
+use image::jpeg::JPEGEncoder;
+use metadata::Rexiv2ImageError;
+pub use metadata::tags::LAYOUT_TAGS;
+use metadata::pages::{self, TiffPageImage, TiffValue};
+use tiff_writer::{self, TiffPage};
+use raw::RawMetadata;
+
+//Bounding box of generated EXIF thumbnails, the usual size written by cameras
+pub const EXIF_THUMBNAIL_SIZE: (u32, u32) = (160, 120);
+
+pub struct EncoderWithMetadata<'a> {
+    metadata: &'a Metadata,
+    format: ImageFormat,
+    jpeg_quality: u8,
+    exif_thumbnail: Option<Vec<u8>>,
+}
+
+impl<'a> EncoderWithMetadata<'a> {
+    pub fn new(metadata: &'a Metadata, format: ImageFormat) -> Result<EncoderWithMetadata<'a>, Rexiv2ImageError> {
+        match format {
+            ImageFormat::PNG | ImageFormat::JPEG | ImageFormat::TIFF => Ok(EncoderWithMetadata {
+                metadata,
+                format,
+                jpeg_quality: 75,
+                exif_thumbnail: None,
+            }),
+            format => Err(Rexiv2ImageError::UnsupportedFormat(format)),
+        }
+    }
+
+    //Only used for JPEG output, in the range 1..100, other values are refused with InvalidTagValue
+    pub fn jpeg_quality(mut self, quality: u8) -> Result<EncoderWithMetadata<'a>, Rexiv2ImageError> {
+        if !(1..=100).contains(&quality) {
+            return Err(Rexiv2ImageError::InvalidTagValue("jpeg_quality".to_string(), quality.to_string()));
+        }
+        self.jpeg_quality = quality;
+        Ok(self)
+    }
+
+    //JPEG data embedded as EXIF thumbnail, PNG files can not hold one
+    pub fn exif_thumbnail(mut self, jpeg: Vec<u8>) -> EncoderWithMetadata<'a> {
+        self.exif_thumbnail = Some(jpeg);
+        self
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    pub fn encode_image_to_file(&self, image: &DynamicImage, path: &Path) -> Result<(), Rexiv2ImageError> {
+        let (width, height) = image.dimensions();
+
+        self.encode_to_file(&image.raw_pixels(), width, height, image.color(), path)
+    }
+
+    pub fn encode_image_to_buffer(&self, image: &DynamicImage) -> Result<Vec<u8>, Rexiv2ImageError> {
+        let (width, height) = image.dimensions();
+
+        self.encode_to_buffer(&image.raw_pixels(), width, height, image.color())
+    }
+
+    pub fn encode_to_file(&self, data: &[u8], width: u32, height: u32, color: ColorType, path: &Path)
+                          -> Result<(), Rexiv2ImageError> {
+        self.write_file(data, width, height, color, path).map_err(|err| err.with_path(path))
+    }
+
+    fn write_file(&self, data: &[u8], width: u32, height: u32, color: ColorType, path: &Path)
+                  -> Result<(), Rexiv2ImageError> {
+        let pixels = self.encode_pixels(data, width, height, color)?;
+
+        File::create(path)?.write_all(&pixels)?;
+        self.embed_metadata(path, width, height)?;
+        self.embed_thumbnail(path)
+    }
+
+    //TIFF only. The metadata of the encoder goes to the first page, the other ones only get their own tags.
+    //No EXIF thumbnail is written since readers would take it for a page
+    pub fn encode_pages_to_file(&self, pages: &[TiffPageImage], path: &Path) -> Result<(), Rexiv2ImageError> {
+        self.write_pages_file(pages, path).map_err(|err| err.with_path(path))
+    }
+
+    pub fn encode_pages_to_buffer(&self, pages: &[TiffPageImage]) -> Result<Vec<u8>, Rexiv2ImageError> {
+        let temporary = TemporaryFile::new()?;
+
+        self.write_pages_file(pages, &temporary.path)?;
+        Ok(fs::read(&temporary.path)?)
+    }
+
+    //exiv2 only keeps the first IFDs when it rewrites a TIFF file,
+    //so the metadata is embedded with the first page alone and the other pages are appended after
+    fn write_pages_file(&self, pages: &[TiffPageImage], path: &Path) -> Result<(), Rexiv2ImageError> {
+        if self.format != ImageFormat::TIFF {
+            return Err(Rexiv2ImageError::UnsupportedFormat(self.format));
+        }
+        let pixels: Vec<Vec<u8>> = pages.iter().map(|page| page.image.raw_pixels()).collect();
+        let tags: Vec<BTreeMap<u16, TiffValue>> = pages.iter().enumerate().map(|(index, page)| {
+            let mut tags = page.tags.clone();
+
+            if pages.len() > 1 {
+                tags.entry(pages::PAGE_NUMBER).or_insert_with(|| TiffValue::Unsigned(vec![index as u32, pages.len() as u32]));
+                tags.entry(pages::NEW_SUBFILE_TYPE).or_insert_with(|| TiffValue::Unsigned(vec![2]));
+            }
+            tags
+        }).collect();
+        let tiff_pages: Vec<TiffPage> = pages.iter().zip(pixels.iter()).zip(tags.iter()).map(|((page, data), tags)| {
+            let (width, height) = page.image.dimensions();
+
+            TiffPage { data, width, height, color: page.image.color(), tags }
+        }).collect();
+
+        let mut output = Vec::new();
+        tiff_writer::write_tiff(&mut output, &tiff_pages[..tiff_pages.len().min(1)])?;
+        File::create(path)?.write_all(&output)?;
+        self.embed_metadata(path, tiff_pages[0].width, tiff_pages[0].height)?;
+
+        if tiff_pages.len() > 1 {
+            let mut output = fs::read(path)?;
+
+            tiff_writer::append_pages(&mut output, &tiff_pages[1..])?;
+            fs::write(path, output)?;
+        }
+        Ok(())
+    }
+
+    //rexiv2 can only save metadata to a file, so the buffer goes through a temporary file
+    pub fn encode_to_buffer(&self, data: &[u8], width: u32, height: u32, color: ColorType)
+                            -> Result<Vec<u8>, Rexiv2ImageError> {
+        let temporary = TemporaryFile::new()?;
+
+        self.write_file(data, width, height, color, &temporary.path)?;
+        Ok(fs::read(&temporary.path)?)
+    }
+
+    fn encode_pixels(&self, data: &[u8], width: u32, height: u32, color: ColorType)
+                     -> Result<Vec<u8>, Rexiv2ImageError> {
+        let mut output = Vec::new();
+
+        match self.format {
+            ImageFormat::PNG => PNGEncoder::new(&mut output).encode(data, width, height, color)?,
+            ImageFormat::JPEG => JPEGEncoder::new_with_quality(&mut output, self.jpeg_quality)
+                .encode(data, width, height, color)?,
+            ImageFormat::TIFF => tiff_writer::write_tiff(&mut output, &[TiffPage {
+                data, width, height, color, tags: &BTreeMap::new(),
+            }])?,
+            format => return Err(Rexiv2ImageError::UnsupportedFormat(format)),
+        }
+        Ok(output)
+    }
+
+    fn embed_metadata(&self, path: &Path, width: u32, height: u32) -> Result<(), Rexiv2ImageError> {
+        let destination = Metadata::new_from_path(path)?;
+        let exif_tags = self.metadata.get_exif_tags()?;
+        let mut skip = LAYOUT_TAGS.to_vec();
+
+        //Without a new thumbnail, the thumbnail IFD would be left pointing to nothing
+        if self.exif_thumbnail.is_none() {
+            skip.extend(exif_tags.iter().map(String::as_str).filter(|tag| tag.starts_with("Exif.Thumbnail.")));
+        }
+        copy_tags(self.metadata, &destination, &skip)?;
+        if destination.has_tag("Exif.Photo.PixelXDimension") {
+            destination.set_tag_numeric("Exif.Photo.PixelXDimension", width as i32)?;
+        }
+        if destination.has_tag("Exif.Photo.PixelYDimension") {
+            destination.set_tag_numeric("Exif.Photo.PixelYDimension", height as i32)?;
+        }
+        Ok(destination.save_to_file(path)?)
+    }
+
+    fn embed_thumbnail(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        if let Some(ref jpeg) = self.exif_thumbnail {
+            let raw = RawMetadata::new_from_path(path)?;
+
+            raw.set_exif_thumbnail(jpeg);
+            raw.save_to_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn make_exif_thumbnail(image: &DynamicImage) -> Result<Vec<u8>, Rexiv2ImageError> {
+    let thumbnail = image.resize(EXIF_THUMBNAIL_SIZE.0, EXIF_THUMBNAIL_SIZE.1, FilterType::Triangle).to_rgb();
+    let mut jpeg = Vec::new();
+
+    JPEGEncoder::new(&mut jpeg).encode(&thumbnail, thumbnail.width(), thumbnail.height(), ColorType::RGB(8))?;
+    Ok(jpeg)
+}
+
+//Repeatable IPTC datasets and XMP arrays hold several values which get_tag_string would join
+pub(crate) fn is_multiple_valued(tag: &str) -> bool {
+    if is_iptc_tag(tag) {
+        return true;
+    }
+    matches!(get_tag_type(tag), Ok(TagType::XmpBag) | Ok(TagType::XmpSeq))
+}
+
+pub fn copy_tag(from: &Metadata, to: &Metadata, tag: &str) -> Result<(), Rexiv2ImageError> {
+    if is_multiple_valued(tag) {
+        let values = from.get_tag_multiple_strings(tag)?;
+        let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+
+        to.clear_tag(tag);
+        Ok(to.set_tag_multiple_strings(tag, &values)?)
+    } else {
+        Ok(to.set_tag_string(tag, &from.get_tag_string(tag)?)?)
+    }
+}
+
+pub fn copy_tags(from: &Metadata, to: &Metadata, skip: &[&str]) -> Result<(), Rexiv2ImageError> {
+    let mut tags = from.get_exif_tags()?;
+
+    tags.extend(from.get_iptc_tags()?);
+    tags.extend(from.get_xmp_tags()?);
+    for tag in tags.iter().filter(|tag| !skip.contains(&tag.as_str())) {
+        copy_tag(from, to, tag)?;
+    }
+    Ok(())
+}
+
+static TEMPORARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+//Names tried before giving up, another process may have taken the predictable ones
+const TEMPORARY_ATTEMPTS: usize = 100;
+
+//Removed when dropped, so an early return does not leave it behind
+pub(crate) struct TemporaryFile {
+    pub(crate) path: PathBuf,
+}
+
+impl TemporaryFile {
+    //The file is created empty and never opened through an existing file or symlink
+    pub(crate) fn new() -> Result<TemporaryFile, Rexiv2ImageError> {
+        let mut attempts = 0;
+
+        loop {
+            let name = format!("rexiv2image-{}-{}", process::id(), TEMPORARY_COUNTER.fetch_add(1, Ordering::SeqCst));
+            let path = env::temp_dir().join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(TemporaryFile { path }),
+                Err(ref err) if err.kind() == ErrorKind::AlreadyExists && attempts < TEMPORARY_ATTEMPTS => attempts += 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, DynamicImage, GrayImage, ImageDecoder, ImageFormat};
+    use image::png::PNGEncoder;
+    use rexiv2::Metadata;
+    use metadata::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::{EncoderWithMetadata, TemporaryFile};
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(3, 2, vec![0, 50, 100, 150, 200, 250]).unwrap())
+    }
+
+    //Source metadata parsed from an encoded image, as it would come from a decoder
+    fn metadata() -> Metadata {
+        let mut png = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&image().raw_pixels(), 3, 2, ColorType::Gray(8)).unwrap();
+        let metadata = Metadata::new_from_buffer(&png).unwrap();
+
+        metadata.set_tag_string("Exif.Image.Make", "Camera").unwrap();
+        metadata.set_tag_string("Exif.Image.ImageWidth", "999").unwrap();
+        metadata.set_tag_numeric("Exif.Photo.PixelXDimension", 999).unwrap();
+        metadata.set_tag_string("Xmp.dc.subject", "keyword").unwrap();
+        metadata
+    }
+
+    fn encode(format: ImageFormat) {
+        let metadata = metadata();
+        let data = EncoderWithMetadata::new(&metadata, format).unwrap().encode_image_to_buffer(&image()).unwrap();
+        let mut decoder = DecoderWithMetadata::from_buffer(data, format).unwrap();
+
+        assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+        assert_eq!(decoder.get_tag_string("Exif.Image.Make").unwrap(), "Camera");
+        assert_eq!(decoder.get_tag_string("Exif.Photo.PixelXDimension").unwrap(), "3");
+        assert_eq!(decoder.metadata.get_tag_multiple_strings("Xmp.dc.subject").unwrap(), vec!["keyword"]);
+        assert_ne!(decoder.get_tag_string("Exif.Image.ImageWidth").ok(), Some("999".to_string()));
+    }
+
+    #[test]
+    fn encode_png() {
+        encode(ImageFormat::PNG);
+    }
+
+    #[test]
+    fn encode_jpeg() {
+        encode(ImageFormat::JPEG);
+    }
+
+    #[test]
+    fn encode_tiff() {
+        encode(ImageFormat::TIFF);
+    }
+
+    #[test]
+    fn jpeg_quality() {
+        let metadata = metadata();
+
+        for &quality in [0, 101, 255].iter() {
+            match EncoderWithMetadata::new(&metadata, ImageFormat::JPEG).unwrap().jpeg_quality(quality) {
+                Err(Rexiv2ImageError::InvalidTagValue(ref name, ref value)) => {
+                    assert_eq!(name, "jpeg_quality");
+                    assert_eq!(value, &quality.to_string());
+                },
+                result => panic!("unexpected {:?}", result.map(|encoder| encoder.format())),
+            }
+        }
+        let low = EncoderWithMetadata::new(&metadata, ImageFormat::JPEG).unwrap().jpeg_quality(1).unwrap();
+        let high = EncoderWithMetadata::new(&metadata, ImageFormat::JPEG).unwrap().jpeg_quality(100).unwrap();
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 64, |x, y| ::image::Luma([(x * y) as u8])));
+
+        assert!(low.encode_image_to_buffer(&image).unwrap().len() < high.encode_image_to_buffer(&image).unwrap().len());
+    }
+
+    #[test]
+    fn temporary_files() {
+        let first = TemporaryFile::new().unwrap();
+        let second = TemporaryFile::new().unwrap();
+        let path = first.path.clone();
+
+        assert_ne!(first.path, second.path);
+        assert!(path.is_file());
+        drop(first);
+        assert!(!path.exists());
+    }
+}
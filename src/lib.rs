@@ -1,4 +1,27 @@
 extern crate image;
+#[cfg(feature = "exiv2")]
 extern crate rexiv2;
+#[cfg(feature = "exiv2")]
+extern crate gexiv2_sys;
+#[cfg(feature = "exiv2")]
+extern crate libc;
+extern crate num_rational;
+extern crate gif;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[cfg(not(any(feature = "exiv2", feature = "exif")))]
+compile_error!("A metadata backend is needed, enable the exiv2 or the exif feature");
 
 pub mod metadata;
+#[cfg(feature = "exiv2")]
+pub mod encoder;
+#[cfg(feature = "exiv2")]
+mod tiff_writer;
+#[cfg(feature = "exiv2")]
+pub mod transform;
+pub mod orientation;
+#[cfg(feature = "exiv2")]
+mod raw;
@@ -1,6 +1,10 @@
+#[cfg(feature = "exiv2")]
 use rexiv2::*;
 use std::fs::File;
-use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "exiv2")]
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 use std::convert::From;
 use std::result::Result;
 use std::error::Error;
@@ -24,61 +28,437 @@ use self::gif::*;
 use self::gif;
 use image::*;
 use image::ColorType;
+use orientation::{self, Orientation};
+#[cfg(feature = "exiv2")]
+use raw::RawMetadata;
+
+pub mod tags;
+pub mod gps;
+#[cfg(feature = "exiv2")]
+pub mod preview;
+pub mod xmp;
+pub mod iptc;
+pub mod sidecar;
+pub mod strip;
+pub mod copy;
+pub mod diff;
+pub mod datetime;
+pub mod animation;
+pub mod pages;
+pub mod icon;
+pub mod backend;
+pub mod memory;
+#[cfg(feature = "exif")]
+pub mod exif;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+
+#[cfg(feature = "exiv2")]
+use self::preview::ThumbnailUpdate;
+use self::backend::{DefaultBackend, MetadataBackend};
 
 #[derive(Debug)]
 pub enum Rexiv2ImageError {
     //Error from rexiv2 crate
+    #[cfg(feature = "exiv2")]
     MetadataError(Rexiv2Error),
     //Error from image crate
     DecoderError(ImageError),
-    //Internal error: described by String
-    Internal(String),
+    //Error while reading or writing a file, the ErrorKind is kept
+    Io(std::io::Error),
+    //Format known by the image crate but not handled here
+    UnsupportedFormat(ImageFormat),
+    //Neither the content nor the extension identify the format
+    UnknownFormat,
+    //Tag requested but absent from the metadata
+    TagNotFound(String),
+    //Tag the metadata backend can not read or write
+    UnsupportedTag(String),
+    //Index of an embedded preview which does not exist
+    PreviewNotFound(usize),
+    //Index of a TIFF page which does not exist
+    PageNotFound(usize),
+    //Index of an ICO entry which does not exist
+    IconNotFound(usize),
+    //Tag whose value could not be converted: tag and raw value
+    InvalidTagValue(String, String),
+    //Error that occurred while working on the given file
+    WithPath(PathBuf, Box<Rexiv2ImageError>),
+}
+
+impl Rexiv2ImageError {
+    //Attaches the file being processed, an error already carrying a path keeps the innermost one
+    pub fn with_path(self, path: &Path) -> Rexiv2ImageError {
+        match self {
+            Rexiv2ImageError::WithPath(..) => self,
+            error => Rexiv2ImageError::WithPath(path.to_path_buf(), Box::new(error)),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            Rexiv2ImageError::WithPath(ref path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    //The error without its path, to match on what went wrong
+    pub fn kind(&self) -> &Rexiv2ImageError {
+        match *self {
+            Rexiv2ImageError::WithPath(_, ref error) => error.kind(),
+            ref error => error,
+        }
+    }
 }
 
-pub enum DecoderType {
-    PNG(PNGDecoder<File>),
-    JPEG(JPEGDecoder<File>),
-    PNM(PNMDecoder<File>),
-    ICO(ICODecoder<File>),
-    TIFF(TIFFDecoder<File>),
-    TGA(TGADecoder<File>),
-    BMP(BMPDecoder<File>),
-    GIF(Decoder<File>),
+pub enum DecoderType<R: Read + Seek> {
+    PNG(PNGDecoder<R>),
+    JPEG(JPEGDecoder<R>),
+    PNM(PNMDecoder<R>),
+    ICO(ICODecoder<R>),
+    TIFF(TIFFDecoder<R>),
+    TGA(TGADecoder<R>),
+    BMP(BMPDecoder<R>),
+    GIF(Decoder<R>),
 }
 
-pub struct DecoderWithMetadata {
+pub struct DecoderWithMetadata<R: Read + Seek = File, M: MetadataBackend = DefaultBackend> {
     //Could be private but would force to implement as the methods of the Metadata type to this container
-    pub metadata: Metadata,
-    decoder: DecoderType,
+    pub metadata: M,
+    decoder: DecoderType<R>,
+    format: ImageFormat,
+    //Orientation applied to the decoded pixels when the auto orientation mode is enabled
+    orientation: Option<Orientation>,
+    //Upright pixels and next row, scanlines can only be served once the whole image is turned
+    scanlines: Option<(Vec<u8>, u32)>,
+    //Second handle on the same metadata, for the previews rexiv2 does not expose,
+    //only set when the metadata was parsed with rexiv2
+    #[cfg(feature = "exiv2")]
+    raw: Option<RawMetadata>,
+    //EXIF thumbnail change applied by save_metadata
+    #[cfg(feature = "exiv2")]
+    thumbnail_update: Option<ThumbnailUpdate>,
+    //Sidecar the metadata was merged from, see load_sidecar
+    sidecar: Option<PathBuf>,
+    //Content of GIF, TIFF and ICO files, their image decoders only give the first frame, page or the largest icon
+    content: Option<Vec<u8>>,
 }
 
-impl DecoderWithMetadata {
+//Signatures of the formats handled by DecoderType, checked in order
+static MAGIC_BYTES: [(&[u8], ImageFormat); 14] = [
+    (b"\x89PNG\r\n\x1a\n", ImageFormat::PNG),
+    (&[0xff, 0xd8, 0xff], ImageFormat::JPEG),
+    (b"GIF87a", ImageFormat::GIF),
+    (b"GIF89a", ImageFormat::GIF),
+    (b"MM\x00*", ImageFormat::TIFF),
+    (b"II*\x00", ImageFormat::TIFF),
+    (b"BM", ImageFormat::BMP),
+    (&[0, 0, 1, 0], ImageFormat::ICO),
+    (b"P1", ImageFormat::PNM),
+    (b"P2", ImageFormat::PNM),
+    (b"P3", ImageFormat::PNM),
+    (b"P4", ImageFormat::PNM),
+    (b"P5", ImageFormat::PNM),
+    (b"P6", ImageFormat::PNM),
+];
+
+//Samples wider than 8 bits are truncated to their high byte
+pub(crate) fn decoder_to_dynamic_image<D: ImageDecoder>(decoder: &mut D) -> Result<DynamicImage, Rexiv2ImageError> {
+    let (width, height) = decoder.dimensions()?;
+    let color = decoder.colortype()?;
+    let wide = match color {
+        ColorType::Gray(bits) | ColorType::GrayA(bits) | ColorType::RGB(bits) | ColorType::RGBA(bits) => bits == 16,
+        ColorType::Palette(_) => false,
+    };
+    let pixels = match decoder.read_image()? {
+        DecodingResult::U8(pixels) if wide => pixels.iter().step_by(2).cloned().collect(),
+        DecodingResult::U8(pixels) => pixels,
+        DecodingResult::U16(pixels) => pixels.iter().map(|&sample| (sample >> 8) as u8).collect(),
+    };
+
+    let image = match color {
+        ColorType::Gray(_) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        ColorType::GrayA(_) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        ColorType::RGB(_) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        ColorType::RGBA(_) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        ColorType::Palette(_) => None,
+    };
+    image.ok_or(Rexiv2ImageError::DecoderError(ImageError::UnsupportedColor(color)))
+}
+
+//Detects the format from the leading bytes of an image
+pub fn guess_format_from_bytes(buffer: &[u8]) -> Option<ImageFormat> {
+    MAGIC_BYTES.iter()
+        .find(|&&(signature, _)| buffer.starts_with(signature))
+        .map(|&(_, format)| format)
+}
+
+//Detects the format from the file extension, TGA has no magic bytes so this is the only way to find it
+pub fn guess_format_from_extension(path: &Path) -> Option<ImageFormat> {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => Some(ImageFormat::PNG),
+        Some("jpg") | Some("jpeg") | Some("jpe") => Some(ImageFormat::JPEG),
+        Some("gif") => Some(ImageFormat::GIF),
+        Some("tif") | Some("tiff") => Some(ImageFormat::TIFF),
+        Some("tga") => Some(ImageFormat::TGA),
+        Some("bmp") => Some(ImageFormat::BMP),
+        Some("ico") => Some(ImageFormat::ICO),
+        Some("pbm") | Some("pgm") | Some("ppm") | Some("pam") | Some("pnm") => Some(ImageFormat::PNM),
+        _ => None,
+    }
+}
+
+//Magic bytes take precedence, the extension is only used when the content is not recognized
+pub fn guess_format(path: &Path) -> Result<ImageFormat, Rexiv2ImageError> {
+    read_header(path)
+        .map_err(|err| Rexiv2ImageError::from(err).with_path(path))
+        .and_then(|header| guess_format_from_bytes(&header)
+                  .or_else(|| guess_format_from_extension(path))
+                  .ok_or_else(|| Rexiv2ImageError::UnknownFormat.with_path(path)))
+}
+
+fn read_header(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(16);
+
+    File::open(path)?.take(16).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+#[cfg(feature = "exiv2")]
+impl DecoderWithMetadata<File> {
     pub fn new(path: &Path, format: ImageFormat)
-                                        -> Result<DecoderWithMetadata, Rexiv2ImageError> {
+                                        -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
+        DecoderWithMetadata::new_from_file(path, format).map_err(|err| err.with_path(path))
+    }
+
+    fn new_from_file(path: &Path, format: ImageFormat) -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
         let metadata = Metadata::new_from_path(path)?;
+        let raw = RawMetadata::new_from_path(path)?;
         let input_file = File::open(path)?;
         
+        DecoderWithMetadata::from_parts(metadata, raw, input_file, format, None)
+    }
+
+    pub fn open(path: &Path) -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
+        DecoderWithMetadata::new(path, guess_format(path)?)
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl DecoderWithMetadata<Cursor<Vec<u8>>> {
+    pub fn from_buffer(data: Vec<u8>, format: ImageFormat)
+                       -> Result<DecoderWithMetadata<Cursor<Vec<u8>>>, Rexiv2ImageError> {
+        let metadata = Metadata::new_from_buffer(&data)?;
+        let raw = RawMetadata::new_from_buffer(&data)?;
+
+        DecoderWithMetadata::from_parts(metadata, raw, Cursor::new(data), format, None)
+    }
+
+    //There is no extension to fall back on, so only the magic bytes are used
+    pub fn open_buffer(data: Vec<u8>) -> Result<DecoderWithMetadata<Cursor<Vec<u8>>>, Rexiv2ImageError> {
+        let format = guess_format_from_bytes(&data)
+            .ok_or(Rexiv2ImageError::UnknownFormat)?;
+
+        DecoderWithMetadata::from_buffer(data, format)
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<'a> DecoderWithMetadata<Cursor<&'a [u8]>> {
+    pub fn from_slice(data: &'a [u8], format: ImageFormat)
+                      -> Result<DecoderWithMetadata<Cursor<&'a [u8]>>, Rexiv2ImageError> {
+        let metadata = Metadata::new_from_buffer(data)?;
+        let raw = RawMetadata::new_from_buffer(data)?;
+
+        DecoderWithMetadata::from_parts(metadata, raw, Cursor::new(data), format, None)
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    //The metadata parser needs the whole content, the reader is rewound to where it was for the decoder
+    pub fn from_reader(mut reader: R, format: ImageFormat) -> Result<DecoderWithMetadata<R>, Rexiv2ImageError> {
+        let start = reader.stream_position()?;
+        let mut data = Vec::new();
+
+        reader.read_to_end(&mut data)?;
+        let metadata = Metadata::new_from_buffer(&data)?;
+        let raw = RawMetadata::new_from_buffer(&data)?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        DecoderWithMetadata::from_parts(metadata, raw, reader, format, Some(data))
+    }
+
+    fn from_parts(metadata: Metadata, raw: RawMetadata, input: R, format: ImageFormat, data: Option<Vec<u8>>)
+                  -> Result<DecoderWithMetadata<R>, Rexiv2ImageError> {
+        let mut decoder = DecoderWithMetadata::from_backend_parts(metadata, input, format, data)?;
+
+        decoder.raw = Some(raw);
+        if let Some(ref data) = decoder.content {
+            if format == ImageFormat::GIF {
+                animation::read_gif_xmp(data, &decoder.metadata)?;
+            }
+        }
+        Ok(decoder)
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //Wraps metadata parsed or built separately, like a mock in tests
+    pub fn with_backend(metadata: M, input: R, format: ImageFormat) -> Result<DecoderWithMetadata<R, M>, Rexiv2ImageError> {
+        DecoderWithMetadata::from_backend_parts(metadata, input, format, None)
+    }
+
+    pub fn from_reader_with_backend(mut reader: R, format: ImageFormat) -> Result<DecoderWithMetadata<R, M>, Rexiv2ImageError> {
+        let start = reader.stream_position()?;
+        let mut data = Vec::new();
+
+        reader.read_to_end(&mut data)?;
+        let metadata = M::from_buffer(&data)?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        DecoderWithMetadata::from_backend_parts(metadata, reader, format, Some(data))
+    }
+
+    //`data` is the rest of the input when the caller has already read it, so that it is not read twice
+    fn from_backend_parts(metadata: M, mut input: R, format: ImageFormat, data: Option<Vec<u8>>)
+                          -> Result<DecoderWithMetadata<R, M>, Rexiv2ImageError> {
+        let content = match data {
+            _ if format != ImageFormat::GIF && format != ImageFormat::TIFF && format != ImageFormat::ICO => None,
+            Some(data) => Some(data),
+            None => {
+                let start = input.stream_position()?;
+                let mut data = Vec::new();
+
+                input.read_to_end(&mut data)?;
+                input.seek(SeekFrom::Start(start))?;
+                Some(data)
+            },
+        };
+
         Ok(DecoderWithMetadata {
             metadata,
-            decoder: DecoderWithMetadata::get_new_decoder(format, input_file)?,
+            decoder: DecoderWithMetadata::<R, M>::get_new_decoder(format, input)?,
+            format,
+            orientation: None,
+            scanlines: None,
+            #[cfg(feature = "exiv2")]
+            raw: None,
+            #[cfg(feature = "exiv2")]
+            thumbnail_update: None,
+            sidecar: None,
+            content,
         })
     }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    //When enabled, the pixels are turned upright according to the EXIF orientation,
+    //which is reset to normal once the image is decoded so that it is not applied twice on save
+    pub fn set_auto_orient(&mut self, enabled: bool) {
+        self.orientation = if enabled {
+            Some(self.metadata.read_orientation())
+        } else {
+            None
+        };
+        self.scanlines = None;
+    }
+
+    pub fn auto_orient(&self) -> bool {
+        self.orientation.is_some()
+    }
+
+    fn pending_orientation(&self) -> Option<Orientation> {
+        match self.orientation {
+            None | Some(Orientation::Unspecified) | Some(Orientation::Normal) => None,
+            orientation => orientation,
+        }
+    }
+
+    fn bytes_per_pixel(&mut self) -> ImageResult<usize> {
+        let color = self.decoder.colortype()?;
+        let bits = match color {
+            ColorType::Gray(bits) | ColorType::Palette(bits) => bits as usize,
+            ColorType::GrayA(bits) => bits as usize * 2,
+            ColorType::RGB(bits) => bits as usize * 3,
+            ColorType::RGBA(bits) => bits as usize * 4,
+        };
+
+        if bits % 8 != 0 {
+            return Err(ImageError::UnsupportedColor(color));
+        }
+        Ok(bits / 8)
+    }
+
+    fn read_oriented_image(&mut self, orientation: Orientation) -> ImageResult<DecodingResult> {
+        let (width, height) = self.decoder.dimensions()?;
+        let bytes_per_pixel = self.bytes_per_pixel()?;
+        let oriented = match self.decoder.read_image()? {
+            DecodingResult::U8(pixels) => DecodingResult::U8(
+                orientation::apply_to_buffer(&pixels, width, height, bytes_per_pixel, orientation)),
+            DecodingResult::U16(pixels) => DecodingResult::U16(
+                orientation::apply_to_buffer(&pixels, width, height, bytes_per_pixel / 2, orientation)),
+        };
+
+        self.metadata.write_orientation(Orientation::Normal)
+            .map_err(|err| ImageError::FormatError(err.to_string()))?;
+        if orientation::swaps_dimensions(orientation) {
+            let (oriented_width, oriented_height) = orientation::oriented_dimensions(width, height, orientation);
+
+            for &(tag, value) in &[("Exif.Photo.PixelXDimension", oriented_width),
+                                   ("Exif.Photo.PixelYDimension", oriented_height)] {
+                if self.metadata.has_tag(tag) {
+                    self.metadata.write_tag(tag, &value.to_string())
+                        .map_err(|err| ImageError::FormatError(err.to_string()))?;
+                }
+            }
+        }
+        Ok(oriented)
+    }
+
+    fn oriented_scanlines(&mut self, orientation: Orientation) -> ImageResult<&mut (Vec<u8>, u32)> {
+        if self.scanlines.is_none() {
+            let pixels = match self.read_oriented_image(orientation)? {
+                DecodingResult::U8(pixels) => pixels,
+                DecodingResult::U16(pixels) => pixels.iter().flat_map(|sample| sample.to_be_bytes().to_vec()).collect(),
+            };
+
+            self.scanlines = Some((pixels, 0));
+        }
+        Ok(self.scanlines.as_mut().unwrap())
+    }
+
+    //Decodes the whole image, samples wider than 8 bits are truncated to their high byte
+    pub fn read_dynamic_image(&mut self) -> Result<DynamicImage, Rexiv2ImageError> {
+        decoder_to_dynamic_image(self)
+    }
     
-    pub fn save_metadata(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
-        Ok(self.metadata.save_to_file(path)?)
+    //The IIM datasets and their IPTC Core properties are synchronized first, see sync_iptc
+    pub fn save_metadata(&mut self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        self.sync_iptc().and_then(|_| M::save_decoder(self, path)).map_err(|err| err.with_path(path))
+    }
+
+    pub fn get_tag_string(&self, tag: &str) -> Result<String, Rexiv2ImageError> {
+        self.metadata.read_tag(tag)
     }
     
-    fn get_new_decoder(format: ImageFormat, input_file: File) -> Result<DecoderType, Rexiv2ImageError> {
+    fn get_new_decoder(format: ImageFormat, input: R) -> Result<DecoderType<R>, Rexiv2ImageError> {
         Ok(match format {
-            ImageFormat::PNG => DecoderType::PNG(png::PNGDecoder::new(input_file)),
-            ImageFormat::JPEG => DecoderType::JPEG(jpeg::JPEGDecoder::new(input_file)),
-            ImageFormat::PNM => DecoderType::PNM(pnm::PNMDecoder::new(input_file)?),
-            ImageFormat::ICO => DecoderType::ICO(ico::ICODecoder::new(input_file)?),
-            ImageFormat::TIFF => DecoderType::TIFF(tiff::TIFFDecoder::new(input_file)?),
-            ImageFormat::TGA => DecoderType::TGA(tga::TGADecoder::new(input_file)),
-            ImageFormat::BMP => DecoderType::BMP(bmp::BMPDecoder::new(input_file)),
-            ImageFormat::GIF => DecoderType::GIF(gif::Decoder::new(input_file)),
-            _ => return Err(Rexiv2ImageError::Internal("Unsupported file format".to_string())),
+            ImageFormat::PNG => DecoderType::PNG(png::PNGDecoder::new(input)),
+            ImageFormat::JPEG => DecoderType::JPEG(jpeg::JPEGDecoder::new(input)),
+            ImageFormat::PNM => DecoderType::PNM(pnm::PNMDecoder::new(input)?),
+            ImageFormat::ICO => DecoderType::ICO(ico::ICODecoder::new(input)?),
+            ImageFormat::TIFF => DecoderType::TIFF(tiff::TIFFDecoder::new(input)?),
+            ImageFormat::TGA => DecoderType::TGA(tga::TGADecoder::new(input)),
+            ImageFormat::BMP => DecoderType::BMP(bmp::BMPDecoder::new(input)),
+            ImageFormat::GIF => DecoderType::GIF(gif::Decoder::new(input)),
+            format => return Err(Rexiv2ImageError::UnsupportedFormat(format)),
         })
     }
 }
@@ -88,26 +468,41 @@ macro_rules! select_decoder_variant {
         match *$enumeration {
             DecoderType::PNG(ref mut decoder) => decoder.$method(),
             DecoderType::JPEG(ref mut decoder) => decoder.$method(),
-            _ => Err(ImageError::FormatError("Unsupported file format".to_string())),
+            DecoderType::PNM(ref mut decoder) => decoder.$method(),
+            DecoderType::ICO(ref mut decoder) => decoder.$method(),
+            DecoderType::TIFF(ref mut decoder) => decoder.$method(),
+            DecoderType::TGA(ref mut decoder) => decoder.$method(),
+            DecoderType::BMP(ref mut decoder) => decoder.$method(),
+            DecoderType::GIF(ref mut decoder) => decoder.$method(),
         }
     };
     (*$enumeration:expr, $method:ident, $($args:expr),* ) => {
         match *$enumeration {
             DecoderType::PNG(ref mut decoder) => decoder.$method($($args),*),
             DecoderType::JPEG(ref mut decoder) => decoder.$method($($args),*),
-            _ => Err(ImageError::FormatError("Unsupported file format".to_string())),
+            DecoderType::PNM(ref mut decoder) => decoder.$method($($args),*),
+            DecoderType::ICO(ref mut decoder) => decoder.$method($($args),*),
+            DecoderType::TIFF(ref mut decoder) => decoder.$method($($args),*),
+            DecoderType::TGA(ref mut decoder) => decoder.$method($($args),*),
+            DecoderType::BMP(ref mut decoder) => decoder.$method($($args),*),
+            DecoderType::GIF(ref mut decoder) => decoder.$method($($args),*),
         }
     };
     ($enumeration:expr, $method:ident) => {
         match $enumeration {
             DecoderType::PNG(decoder) => decoder.$method(),
             DecoderType::JPEG(decoder) => decoder.$method(),
-            _ => Err(ImageError::FormatError("Unsupported file format".to_string())),
+            DecoderType::PNM(decoder) => decoder.$method(),
+            DecoderType::ICO(decoder) => decoder.$method(),
+            DecoderType::TIFF(decoder) => decoder.$method(),
+            DecoderType::TGA(decoder) => decoder.$method(),
+            DecoderType::BMP(decoder) => decoder.$method(),
+            DecoderType::GIF(decoder) => decoder.$method(),
         }
     };
 }
 
-impl ImageDecoder for DecoderType {
+impl<R: Read + Seek> ImageDecoder for DecoderType<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
         select_decoder_variant!(*self, dimensions)
     }
@@ -139,9 +534,14 @@ impl ImageDecoder for DecoderType {
     }    
 }
 
-impl ImageDecoder for DecoderWithMetadata {
+impl<R: Read + Seek, M: MetadataBackend> ImageDecoder for DecoderWithMetadata<R, M> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        self.decoder.dimensions()
+        let (width, height) = self.decoder.dimensions()?;
+
+        match self.pending_orientation() {
+            Some(orientation) => Ok(orientation::oriented_dimensions(width, height, orientation)),
+            None => Ok((width, height)),
+        }
     }
     
     fn colortype(&mut self) -> ImageResult<ColorType> {
@@ -149,15 +549,41 @@ impl ImageDecoder for DecoderWithMetadata {
     }
     
     fn row_len(&mut self) -> ImageResult<usize> {
-        self.decoder.row_len()
+        match self.pending_orientation() {
+            Some(orientation) if orientation::swaps_dimensions(orientation) => {
+                let (_, height) = self.decoder.dimensions()?;
+
+                Ok(height as usize * self.bytes_per_pixel()?)
+            },
+            _ => self.decoder.row_len(),
+        }
     }
     
     fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
-        self.decoder.read_scanline(buf)
+        let orientation = match self.pending_orientation() {
+            Some(orientation) => orientation,
+            None => return self.decoder.read_scanline(buf),
+        };
+        let row_len = self.row_len()?;
+        let &mut (ref pixels, ref mut row) = self.oriented_scanlines(orientation)?;
+        let start = *row as usize * row_len;
+
+        if start >= pixels.len() {
+            return Err(ImageError::ImageEnd);
+        }
+        if buf.len() < row_len {
+            return Err(ImageError::DimensionError);
+        }
+        buf[..row_len].copy_from_slice(&pixels[start..start + row_len]);
+        *row += 1;
+        Ok(row_len as u32)
     }
     
     fn read_image(&mut self) -> ImageResult<DecodingResult> {
-        self.decoder.read_image()
+        match self.pending_orientation() {
+            Some(orientation) => self.read_oriented_image(orientation),
+            None => self.decoder.read_image(),
+        }
     }
     
     fn is_animated(&mut self) -> ImageResult<bool> {
@@ -165,14 +591,45 @@ impl ImageDecoder for DecoderWithMetadata {
     }
     
     fn into_frames(self) -> ImageResult<Frames> {
-        self.decoder.into_frames()
+        if self.format != ImageFormat::GIF {
+            return self.decoder.into_frames();
+        }
+        let frames = self.frames().and_then(|frames| frames.collect::<Result<Vec<_>, Rexiv2ImageError>>());
+
+        match frames {
+            Ok(frames) => Ok(Frames::new(frames.into_iter().map(|frame| frame.frame).collect())),
+            Err(Rexiv2ImageError::DecoderError(err)) => Err(err),
+            Err(err) => Err(ImageError::FormatError(err.to_string())),
+        }
     }
     
     fn load_rect(&mut self, x: u32, y: u32, length: u32, width: u32) -> ImageResult<Vec<u8>> {
-        self.decoder.load_rect(x, y, length, width)
+        let orientation = match self.pending_orientation() {
+            Some(orientation) => orientation,
+            None => return self.decoder.load_rect(x, y, length, width),
+        };
+        let (image_width, image_height) = self.dimensions()?;
+
+        let fits = |start: u32, size: u32, end: u32| start.checked_add(size).is_some_and(|last| last <= end);
+
+        if !fits(x, width, image_width) || !fits(y, length, image_height) {
+            return Err(ImageError::DimensionError);
+        }
+        let bytes_per_pixel = self.bytes_per_pixel()?;
+        let row_len = image_width as usize * bytes_per_pixel;
+        let &mut (ref pixels, _) = self.oriented_scanlines(orientation)?;
+        let mut rect = Vec::with_capacity(length as usize * width as usize * bytes_per_pixel);
+
+        for row in y..y + length {
+            let start = row as usize * row_len + x as usize * bytes_per_pixel;
+
+            rect.extend_from_slice(&pixels[start..start + width as usize * bytes_per_pixel]);
+        }
+        Ok(rect)
     }
 }
 
+#[cfg(feature = "exiv2")]
 impl From<Rexiv2Error> for Rexiv2ImageError {
     fn from(rexiv2error: Rexiv2Error) -> Rexiv2ImageError {
         Rexiv2ImageError::MetadataError(rexiv2error)
@@ -187,33 +644,224 @@ impl From<ImageError> for Rexiv2ImageError {
 
 impl From<std::io::Error> for Rexiv2ImageError {
     fn from(error: std::io::Error) -> Rexiv2ImageError {
-        Rexiv2ImageError::Internal(error.description().to_string())
+        Rexiv2ImageError::Io(error)
     }
 }
 
 impl Display for Rexiv2ImageError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match *self {
-            Rexiv2ImageError::Internal(ref err_string) => write!(f, "{}", err_string),
+            #[cfg(feature = "exiv2")]
             Rexiv2ImageError::MetadataError(ref err) => err.fmt(f),
             Rexiv2ImageError::DecoderError(ref err) => err.fmt(f),
+            Rexiv2ImageError::Io(ref err) => err.fmt(f),
+            Rexiv2ImageError::UnsupportedFormat(format) => write!(f, "Unsupported file format: {:?}", format),
+            Rexiv2ImageError::UnknownFormat => write!(f, "Unknown file format"),
+            Rexiv2ImageError::TagNotFound(ref tag) => write!(f, "Tag not found: {}", tag),
+            Rexiv2ImageError::UnsupportedTag(ref tag) => write!(f, "Unsupported tag: {}", tag),
+            Rexiv2ImageError::PreviewNotFound(index) => write!(f, "Preview not found: {}", index),
+            Rexiv2ImageError::PageNotFound(index) => write!(f, "Page not found: {}", index),
+            Rexiv2ImageError::IconNotFound(index) => write!(f, "Icon entry not found: {}", index),
+            Rexiv2ImageError::InvalidTagValue(ref tag, ref value) => write!(f, "Invalid value for {}: {}", tag, value),
+            Rexiv2ImageError::WithPath(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
         }
     }
 }
 
 impl Error for Rexiv2ImageError {
-    fn description(&self) -> &str {
-        match *self {
-            Rexiv2ImageError::MetadataError(ref err) => err.description(),
-            Rexiv2ImageError::DecoderError(ref err) => err.description(),
-            Rexiv2ImageError::Internal(ref err) => err.as_str(),
-        }
-    }
-    fn cause(&self) -> Option<&Error> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
+            #[cfg(feature = "exiv2")]
             Rexiv2ImageError::MetadataError(ref err) => Some(err),
             Rexiv2ImageError::DecoderError(ref err) => Some(err),
-            Rexiv2ImageError::Internal(_) => None,
+            Rexiv2ImageError::Io(ref err) => Some(err),
+            Rexiv2ImageError::WithPath(_, ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate gif as gif_codec;
+
+    use image::{ColorType, DecodingResult, DynamicImage, ImageDecoder, ImageFormat, RgbaImage};
+    use image::bmp::BMPEncoder;
+    use image::jpeg::JPEGEncoder;
+    use image::png::PNGEncoder;
+    use std::io::Cursor;
+    use super::DecoderWithMetadata;
+    use super::icon::{encode_icon, IconImage};
+    use super::memory::MemoryMetadata;
+
+    const WIDTH: u32 = 3;
+    const HEIGHT: u32 = 2;
+    const GRAY: [u8; 6] = [0, 50, 100, 150, 200, 250];
+
+    fn rgb() -> Vec<u8> {
+        GRAY.iter().flat_map(|&value| vec![value, 255 - value, value / 2]).collect()
+    }
+
+    fn rgba() -> Vec<u8> {
+        GRAY.iter().flat_map(|&value| vec![value, 255 - value, value / 2, 255]).collect()
+    }
+
+    fn pixels(result: DecodingResult) -> Vec<u8> {
+        match result {
+            DecodingResult::U8(pixels) => pixels,
+            DecodingResult::U16(_) => panic!("Unexpected 16 bits samples"),
+        }
+    }
+
+    fn decode(format: ImageFormat, data: Vec<u8>, colortype: ColorType) -> Vec<u8> {
+        let mut decoder = DecoderWithMetadata::with_backend(MemoryMetadata::new(), Cursor::new(data), format).unwrap();
+
+        assert_eq!(decoder.dimensions().unwrap(), (WIDTH, HEIGHT));
+        assert_eq!(decoder.colortype().unwrap(), colortype);
+        pixels(decoder.read_image().unwrap())
+    }
+
+    fn u16_le(value: u16) -> Vec<u8> {
+        value.to_le_bytes().to_vec()
+    }
+
+    //Uncompressed grayscale TIFF with a single strip
+    fn tiff() -> Vec<u8> {
+        let entries: &[(u16, u16, u32)] = &[(256, 3, WIDTH), (257, 3, HEIGHT), (258, 3, 8), (259, 3, 1), (262, 3, 1),
+                                            (273, 4, 8 + 2 + 10 * 12 + 4), (277, 3, 1), (278, 3, HEIGHT),
+                                            (279, 4, GRAY.len() as u32), (284, 3, 1)];
+        let mut data = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+
+        data.extend(u16_le(entries.len() as u16));
+        for &(tag, field_type, value) in entries {
+            data.extend(u16_le(tag));
+            data.extend(u16_le(field_type));
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
         }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&GRAY);
+        data
+    }
+
+    #[test]
+    fn decode_png() {
+        let mut data = Vec::new();
+
+        PNGEncoder::new(&mut data).encode(&rgb(), WIDTH, HEIGHT, ColorType::RGB(8)).unwrap();
+        assert_eq!(decode(ImageFormat::PNG, data, ColorType::RGB(8)), rgb());
+    }
+
+    //The pixels are a flat color so that the compression leaves them close to the original
+    #[test]
+    fn decode_jpeg() {
+        let flat = vec![120u8; (WIDTH * HEIGHT) as usize];
+        let mut data = Vec::new();
+
+        JPEGEncoder::new_with_quality(&mut data, 100).encode(&flat, WIDTH, HEIGHT, ColorType::Gray(8)).unwrap();
+        let decoded = decode(ImageFormat::JPEG, data, ColorType::Gray(8));
+
+        assert_eq!(decoded.len(), flat.len());
+        assert!(decoded.iter().all(|&value| (value as i32 - 120).abs() <= 2));
+    }
+
+    #[test]
+    fn decode_pnm() {
+        let mut data = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+
+        data.extend(rgb());
+        assert_eq!(decode(ImageFormat::PNM, data, ColorType::RGB(8)), rgb());
+    }
+
+    #[test]
+    fn decode_ico() {
+        let image = RgbaImage::from_raw(WIDTH, HEIGHT, rgba()).unwrap();
+        let mut data = Vec::new();
+
+        encode_icon(&[IconImage::Image(DynamicImage::ImageRgba8(image))], &mut data).unwrap();
+        assert_eq!(decode(ImageFormat::ICO, data, ColorType::RGBA(8)), rgba());
+    }
+
+    #[test]
+    fn decode_tiff() {
+        assert_eq!(decode(ImageFormat::TIFF, tiff(), ColorType::Gray(8)), GRAY.to_vec());
+    }
+
+    //The content read for the metadata is the one kept for the pages
+    #[test]
+    fn decode_tiff_from_reader() {
+        let mut input = Cursor::new(tiff());
+        let mut decoder = DecoderWithMetadata::<_, MemoryMetadata>::from_reader_with_backend(&mut input, ImageFormat::TIFF)
+            .unwrap();
+
+        assert_eq!(decoder.page_count().unwrap(), 1);
+        assert_eq!(pixels(decoder.read_image().unwrap()), GRAY.to_vec());
+    }
+
+    //Uncompressed true color, stored from the top row
+    #[test]
+    fn decode_tga() {
+        let mut data = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        data.extend(u16_le(WIDTH as u16));
+        data.extend(u16_le(HEIGHT as u16));
+        data.extend_from_slice(&[24, 0x20]);
+        data.extend(rgb().chunks(3).flat_map(|pixel| vec![pixel[2], pixel[1], pixel[0]]));
+        assert_eq!(decode(ImageFormat::TGA, data, ColorType::RGB(8)), rgb());
+    }
+
+    #[test]
+    fn decode_bmp() {
+        let mut data = Vec::new();
+
+        BMPEncoder::new(&mut data).encode(&rgb(), WIDTH, HEIGHT, ColorType::RGB(8)).unwrap();
+        assert_eq!(decode(ImageFormat::BMP, data, ColorType::RGB(8)), rgb());
+    }
+
+    //Two colors, which the quantization keeps exactly
+    #[test]
+    fn decode_gif() {
+        let mut pixels: Vec<u8> = (0..WIDTH * HEIGHT).flat_map(|i| if i % 2 == 0 { vec![255, 0, 0, 255] } else { vec![0, 0, 255, 255] })
+            .collect();
+        let expected = pixels.clone();
+        let mut data = Vec::new();
+
+        {
+            let mut encoder = gif_codec::Encoder::new(&mut data, WIDTH as u16, HEIGHT as u16, &[]).unwrap();
+
+            encoder.write_frame(&gif_codec::Frame::from_rgba(WIDTH as u16, HEIGHT as u16, &mut pixels)).unwrap();
+        }
+        assert_eq!(decode(ImageFormat::GIF, data, ColorType::RGBA(8)), expected);
+    }
+
+    fn rotated_png() -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        let metadata = MemoryMetadata::new().with_tag("Exif.Image.Orientation", "6");
+        let mut data = Vec::new();
+
+        PNGEncoder::new(&mut data).encode(&GRAY, WIDTH, HEIGHT, ColorType::Gray(8)).unwrap();
+        let mut decoder = DecoderWithMetadata::with_backend(metadata, Cursor::new(data), ImageFormat::PNG).unwrap();
+
+        decoder.set_auto_orient(true);
+        decoder
+    }
+
+    #[test]
+    fn oriented_scanlines() {
+        let mut decoder = rotated_png();
+        let mut row = [0; HEIGHT as usize];
+
+        assert_eq!(decoder.dimensions().unwrap(), (HEIGHT, WIDTH));
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), HEIGHT);
+        assert_eq!(row, [150, 0]);
+        assert!(decoder.read_scanline(&mut [0; 1]).is_err());
+    }
+
+    #[test]
+    fn oriented_rect() {
+        let mut decoder = rotated_png();
+
+        assert_eq!(decoder.load_rect(1, 1, 2, 1).unwrap(), vec![50, 100]);
+        assert!(decoder.load_rect(1, 0, u32::MAX, 1).is_err());
+        assert!(decoder.load_rect(u32::MAX, 0, 1, 1).is_err());
     }
 }
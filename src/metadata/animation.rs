@@ -0,0 +1,404 @@
+use gif::{self, ColorOutput, DisposalMethod, SetParameter};
+#[cfg(feature = "exiv2")]
+use gif::{Encoder, Repeat};
+use image::{Frame, ImageError, ImageFormat, RgbaImage};
+use num_rational::Ratio;
+#[cfg(feature = "exiv2")]
+use rexiv2::Metadata;
+#[cfg(feature = "exiv2")]
+use std::fs::{self, File};
+use std::io::{Read, Seek};
+#[cfg(feature = "exiv2")]
+use std::io::Write;
+#[cfg(feature = "exiv2")]
+use std::path::Path;
+use std::result::Result;
+#[cfg(feature = "exiv2")]
+use encoder::{self, TemporaryFile};
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+const XMP_APPLICATION: &[u8] = b"XMP DataXMP";
+const NETSCAPE_APPLICATION: &[u8] = b"NETSCAPE2.0";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disposal {
+    Unspecified,
+    //The frame is left in place
+    Keep,
+    //The frame area is cleared to the background
+    Background,
+    //The frame area is restored to what it was before the frame
+    Previous,
+}
+
+impl Disposal {
+    fn from_gif(disposal: DisposalMethod) -> Disposal {
+        match disposal {
+            DisposalMethod::Any => Disposal::Unspecified,
+            DisposalMethod::Keep => Disposal::Keep,
+            DisposalMethod::Background => Disposal::Background,
+            DisposalMethod::Previous => Disposal::Previous,
+        }
+    }
+
+    #[cfg(feature = "exiv2")]
+    fn to_gif(self) -> DisposalMethod {
+        match self {
+            Disposal::Unspecified => DisposalMethod::Any,
+            Disposal::Keep => DisposalMethod::Keep,
+            Disposal::Background => DisposalMethod::Background,
+            Disposal::Previous => DisposalMethod::Previous,
+        }
+    }
+}
+
+//Number of times the animation is repeated after the first play, from the NETSCAPE2.0 extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopCount {
+    Infinite,
+    Finite(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameTiming {
+    //In hundredths of a second as stored in the file, the delay of the Frame is the same in seconds
+    pub delay: u16,
+    pub disposal: Disposal,
+}
+
+//The frame covers its own area of the canvas, given by its left and top offsets
+#[derive(Clone)]
+pub struct GifFrame {
+    pub frame: Frame,
+    pub timing: FrameTiming,
+}
+
+pub struct GifFrames<'a> {
+    reader: gif::Reader<&'a [u8]>,
+}
+
+impl<'a> Iterator for GifFrames<'a> {
+    type Item = Result<GifFrame, Rexiv2ImageError>;
+
+    fn next(&mut self) -> Option<Result<GifFrame, Rexiv2ImageError>> {
+        let frame = match self.reader.read_next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return None,
+            Err(err) => return Some(Err(ImageError::from(err).into())),
+        };
+        let buffer = match RgbaImage::from_raw(frame.width as u32, frame.height as u32, frame.buffer.to_vec()) {
+            Some(buffer) => buffer,
+            None => return Some(Err(Rexiv2ImageError::DecoderError(ImageError::DimensionError))),
+        };
+
+        Some(Ok(GifFrame {
+            frame: Frame::from_parts(buffer, frame.left as u32, frame.top as u32, Ratio::new(frame.delay, 100)),
+            timing: FrameTiming { delay: frame.delay, disposal: Disposal::from_gif(frame.dispose) },
+        }))
+    }
+}
+
+#[derive(Default)]
+struct GifExtensions {
+    loop_count: Option<LoopCount>,
+    xmp: Option<Vec<u8>>,
+}
+
+//Returns the content of the data sub-blocks starting at `position`, and the position after their terminator
+fn read_sub_blocks(data: &[u8], mut position: usize) -> (Vec<u8>, usize) {
+    let mut content = Vec::new();
+
+    while let Some(&size) = data.get(position) {
+        position += 1;
+        if size == 0 {
+            break;
+        }
+        let end = (position + size as usize).min(data.len());
+
+        content.extend_from_slice(&data[position..end]);
+        position = end;
+    }
+    (content, position)
+}
+
+fn color_table_size(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+//The image decoders ignore the application extensions, so the blocks are walked here
+fn scan_extensions(data: &[u8]) -> GifExtensions {
+    let mut extensions = GifExtensions::default();
+    let mut position = match data.get(10) {
+        Some(&flags) => 13 + color_table_size(flags),
+        None => return extensions,
+    };
+
+    loop {
+        match data.get(position) {
+            Some(&0x21) if data.get(position + 1) == Some(&0xff) => {
+                let size = data.get(position + 2).cloned().unwrap_or(0) as usize;
+                let identifier = data.get(position + 3..position + 3 + size).unwrap_or(&[]);
+                let start = position + 3 + size;
+
+                //The XMP packet is stored raw, followed by a trailer which makes it look like sub-blocks
+                if identifier == XMP_APPLICATION {
+                    let end = data[start.min(data.len())..].windows(3).position(|bytes| bytes == [0x01, 0xff, 0xfe]);
+
+                    extensions.xmp = end.map(|end| data[start..start + end].to_vec());
+                }
+                let (content, next) = read_sub_blocks(data, start);
+
+                if identifier == NETSCAPE_APPLICATION && content.len() >= 3 && content[0] == 1 {
+                    extensions.loop_count = Some(match content[1] as u16 | (content[2] as u16) << 8 {
+                        0 => LoopCount::Infinite,
+                        count => LoopCount::Finite(count),
+                    });
+                }
+                position = next;
+            },
+            Some(&0x21) => position = read_sub_blocks(data, position + 2).1,
+            Some(&0x2c) => {
+                let flags = data.get(position + 9).cloned().unwrap_or(0);
+
+                //Descriptor, local color table and LZW code size
+                position = read_sub_blocks(data, position + 10 + color_table_size(flags) + 1).1;
+            },
+            _ => return extensions,
+        }
+    }
+}
+
+#[cfg(feature = "exiv2")]
+fn xmp_extension(packet: &[u8]) -> Vec<u8> {
+    let mut extension = vec![0x21, 0xff, XMP_APPLICATION.len() as u8];
+
+    extension.extend_from_slice(XMP_APPLICATION);
+    extension.extend_from_slice(packet);
+    extension.push(0x01);
+    extension.extend((0..=0xffu8).rev());
+    extension.push(0x00);
+    extension
+}
+
+//exiv2 reads no metadata from GIF files, the XMP packet is merged by the decoder constructors
+#[cfg(feature = "exiv2")]
+pub(super) fn read_gif_xmp(data: &[u8], metadata: &Metadata) -> Result<(), Rexiv2ImageError> {
+    if let Some(packet) = scan_extensions(data).xmp {
+        encoder::copy_tags(&Metadata::new_from_buffer(&packet)?, metadata, &[])?;
+    }
+    Ok(())
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    fn animation_data(&self) -> Result<&[u8], Rexiv2ImageError> {
+        match self.content {
+            Some(ref data) if self.format == ImageFormat::GIF => Ok(data),
+            _ => Err(Rexiv2ImageError::UnsupportedFormat(self.format)),
+        }
+    }
+
+    pub fn frames(&self) -> Result<GifFrames<'_>, Rexiv2ImageError> {
+        let mut decoder = gif::Decoder::new(self.animation_data()?);
+
+        decoder.set(ColorOutput::RGBA);
+        Ok(GifFrames { reader: decoder.read_info().map_err(ImageError::from)? })
+    }
+
+    //None when the animation is played once
+    pub fn loop_count(&self) -> Result<Option<LoopCount>, Rexiv2ImageError> {
+        Ok(scan_extensions(self.animation_data()?).loop_count)
+    }
+
+    //XMP packet of the XMP application extension, as stored in the file
+    pub fn gif_xmp_packet(&self) -> Result<Option<Vec<u8>>, Rexiv2ImageError> {
+        Ok(scan_extensions(self.animation_data()?).xmp)
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    //The frames are quantized again, the XMP of the decoder is stored in the XMP application extension
+    pub fn encode_gif<W: Write>(&self, frames: &[GifFrame], loop_count: Option<LoopCount>, output: &mut W)
+                                -> Result<(), Rexiv2ImageError> {
+        let width = frames.iter().map(|frame| frame.frame.left() + frame.frame.buffer().width()).max().unwrap_or(0);
+        let height = frames.iter().map(|frame| frame.frame.top() + frame.frame.buffer().height()).max().unwrap_or(0);
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(Rexiv2ImageError::DecoderError(ImageError::DimensionError));
+        }
+        let mut data = Vec::new();
+
+        {
+            let mut encoder = Encoder::new(&mut data, width as u16, height as u16, &[])?;
+
+            match loop_count {
+                Some(LoopCount::Infinite) => encoder.set(Repeat::Infinite)?,
+                Some(LoopCount::Finite(count)) => encoder.set(Repeat::Finite(count))?,
+                None => (),
+            }
+            for frame in frames {
+                let buffer = frame.frame.buffer();
+                let mut pixels = buffer.clone().into_raw();
+                let mut gif_frame = gif::Frame::from_rgba(buffer.width() as u16, buffer.height() as u16, &mut pixels);
+
+                gif_frame.left = frame.frame.left() as u16;
+                gif_frame.top = frame.frame.top() as u16;
+                gif_frame.delay = frame.timing.delay;
+                gif_frame.dispose = frame.timing.disposal.to_gif();
+                encoder.write_frame(&gif_frame)?;
+            }
+        }
+
+        //The encoder writes the trailer when dropped, the extension goes right before it
+        data.pop();
+        if !self.metadata.get_xmp_tags()?.is_empty() {
+            let temporary = TemporaryFile::new()?;
+
+            self.write_sidecar(&temporary.path)?;
+            data.extend(xmp_extension(&fs::read(&temporary.path)?));
+        }
+        data.push(0x3b);
+        Ok(output.write_all(&data)?)
+    }
+
+    //Re-encodes the animation with its timing and loop count, keeping the current XMP
+    pub fn save_gif(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        let frames = self.frames()?.collect::<Result<Vec<GifFrame>, Rexiv2ImageError>>()?;
+
+        self.encode_gif(&frames, self.loop_count()?, &mut File::create(path)?)
+            .map_err(|err| err.with_path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gif::{Encoder, Repeat, SetParameter};
+    use image::ImageFormat;
+    use num_rational::Ratio;
+    use std::io::Cursor;
+    use super::{Disposal, FrameTiming, GifFrame, LoopCount, XMP_APPLICATION};
+    use super::super::DecoderWithMetadata;
+    use super::super::memory::MemoryMetadata;
+
+    const PACKET: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+
+    //Two frames of 2x2 pixels, the second one only covering the right column
+    fn animation(repeat: Option<Repeat>) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2, &[]).unwrap();
+
+            if let Some(repeat) = repeat {
+                encoder.set(repeat).unwrap();
+            }
+            let mut red = [255, 0, 0, 255].repeat(4);
+            let mut first = gif::Frame::from_rgba(2, 2, &mut red);
+            first.delay = 10;
+            first.dispose = gif::DisposalMethod::Keep;
+            encoder.write_frame(&first).unwrap();
+
+            let mut blue = [0, 0, 255, 255].repeat(2);
+            let mut second = gif::Frame::from_rgba(1, 2, &mut blue);
+            second.left = 1;
+            second.delay = 25;
+            second.dispose = gif::DisposalMethod::Background;
+            encoder.write_frame(&second).unwrap();
+        }
+        data
+    }
+
+    //Same layout as the extension written by encode_gif
+    fn with_xmp(mut data: Vec<u8>) -> Vec<u8> {
+        data.pop();
+        data.extend_from_slice(&[0x21, 0xff, XMP_APPLICATION.len() as u8]);
+        data.extend_from_slice(XMP_APPLICATION);
+        data.extend_from_slice(PACKET);
+        data.push(0x01);
+        data.extend((0..=0xffu8).rev());
+        data.extend_from_slice(&[0x00, 0x3b]);
+        data
+    }
+
+    fn decoder(data: Vec<u8>) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_reader_with_backend(Cursor::new(data), ImageFormat::GIF).unwrap()
+    }
+
+    fn timings(frames: &[GifFrame]) -> Vec<FrameTiming> {
+        frames.iter().map(|frame| frame.timing).collect()
+    }
+
+    #[test]
+    fn frames() {
+        let decoder = decoder(animation(Some(Repeat::Finite(3))));
+        let frames: Vec<GifFrame> = decoder.frames().unwrap().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(timings(&frames), vec![FrameTiming { delay: 10, disposal: Disposal::Keep },
+                                          FrameTiming { delay: 25, disposal: Disposal::Background }]);
+        assert_eq!(frames[1].frame.left(), 1);
+        assert_eq!(frames[1].frame.buffer().dimensions(), (1, 2));
+        assert_eq!(frames[1].frame.buffer().get_pixel(0, 1).data, [0, 0, 255, 255]);
+        assert_eq!(frames[1].frame.delay(), Ratio::new(25, 100));
+        assert_eq!(decoder.loop_count().unwrap(), Some(LoopCount::Finite(3)));
+        assert_eq!(decoder.gif_xmp_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(decoder(animation(None)).loop_count().unwrap(), None);
+        assert_eq!(decoder(animation(Some(Repeat::Infinite))).loop_count().unwrap(), Some(LoopCount::Infinite));
+
+        let decoder = decoder(with_xmp(animation(Some(Repeat::Infinite))));
+
+        assert_eq!(decoder.gif_xmp_packet().unwrap(), Some(PACKET.to_vec()));
+        assert_eq!(decoder.loop_count().unwrap(), Some(LoopCount::Infinite));
+        //The extension does not disturb the frames
+        assert_eq!(decoder.frames().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn not_an_animation() {
+        let image = ::image::DynamicImage::ImageLuma8(::image::GrayImage::new(1, 1));
+        let decoder = DecoderWithMetadata::from_image(&image, MemoryMetadata::new()).unwrap();
+
+        assert!(decoder.frames().is_err());
+        assert!(decoder.loop_count().is_err());
+    }
+
+    #[cfg(feature = "exiv2")]
+    #[test]
+    fn encode_round_trip() {
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        let decoder = DecoderWithMetadata::from_buffer(animation(Some(Repeat::Finite(2))), ImageFormat::GIF).unwrap();
+        let frames: Vec<GifFrame> = decoder.frames().unwrap().collect::<Result<_, _>>().unwrap();
+        let mut data = Vec::new();
+
+        decoder.metadata.set_tag_string("Xmp.dc.title", "Sunset").unwrap();
+        decoder.encode_gif(&frames, Some(LoopCount::Infinite), &mut data).unwrap();
+        let encoded = DecoderWithMetadata::from_buffer(data, ImageFormat::GIF).unwrap();
+        let encoded_frames: Vec<GifFrame> = encoded.frames().unwrap().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(timings(&encoded_frames), timings(&frames));
+        assert_eq!(encoded.loop_count().unwrap(), Some(LoopCount::Infinite));
+        assert!(encoded.gif_xmp_packet().unwrap().is_some());
+        assert!(encoded.metadata.get_tag_string("Xmp.dc.title").unwrap().contains("Sunset"));
+
+        //save_gif keeps the loop count of the source
+        let path = env::temp_dir().join(format!("rexiv2image-animation-{}.gif", process::id()));
+
+        encoded.save_gif(&path).unwrap();
+        let saved = DecoderWithMetadata::from_buffer(fs::read(&path).unwrap(), ImageFormat::GIF);
+        fs::remove_file(&path).unwrap();
+        let saved = saved.unwrap();
+        assert_eq!(saved.frames().unwrap().count(), 2);
+        assert_eq!(saved.loop_count().unwrap(), Some(LoopCount::Infinite));
+        assert!(saved.metadata.get_tag_string("Xmp.dc.title").unwrap().contains("Sunset"));
+    }
+}
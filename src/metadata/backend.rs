@@ -0,0 +1,233 @@
+#[cfg(feature = "exiv2")]
+use rexiv2::{self, Metadata};
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::result::Result;
+use std::str;
+use orientation::{self, Orientation};
+#[cfg(feature = "exiv2")]
+use encoder;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+
+pub const ORIENTATION_TAG: &str = "Exif.Image.Orientation";
+
+//Backend of a DecoderWithMetadata when none is given
+#[cfg(feature = "exiv2")]
+pub type DefaultBackend = Metadata;
+#[cfg(all(feature = "exif", not(feature = "exiv2")))]
+pub type DefaultBackend = super::exif::ExifMetadata;
+//Without a backend only the compile_error! of lib.rs should be reported
+#[cfg(not(any(feature = "exiv2", feature = "exif")))]
+pub type DefaultBackend = super::memory::MemoryMetadata;
+
+//Operations on the tags of an image DecoderWithMetadata relies on, tags are named the exiv2 way
+//("Exif.Photo.FNumber") and their values are given as strings
+pub trait MetadataBackend {
+    //Parses the metadata of an encoded image
+    fn from_buffer(data: &[u8]) -> Result<Self, Rexiv2ImageError> where Self: Sized;
+
+    //TagNotFound when the tag is absent
+    fn read_tag(&self, tag: &str) -> Result<String, Rexiv2ImageError>;
+
+    fn write_tag(&mut self, tag: &str, value: &str) -> Result<(), Rexiv2ImageError>;
+
+    //Whether the tag was present
+    fn remove_tag(&mut self, tag: &str) -> bool;
+
+    fn list_tags(&self) -> Result<Vec<String>, Rexiv2ImageError>;
+
+    //Writes the tags into the existing image file
+    fn save(&self, path: &Path) -> Result<(), Rexiv2ImageError>;
+
+    fn has_tag(&self, tag: &str) -> bool {
+        self.read_tag(tag).is_ok()
+    }
+
+    //Values of a repeatable IPTC dataset or the items of an XMP array, empty when the tag is absent
+    fn read_tag_multiple(&self, tag: &str) -> Result<Vec<String>, Rexiv2ImageError> {
+        match self.read_tag(tag) {
+            Ok(value) => Ok(vec![value]),
+            Err(Rexiv2ImageError::TagNotFound(_)) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    //Replaces every value of the tag, the tag is removed when there is none
+    fn write_tag_multiple(&mut self, tag: &str, values: &[&str]) -> Result<(), Rexiv2ImageError> {
+        match *values {
+            [] => {
+                self.remove_tag(tag);
+                Ok(())
+            },
+            [value] => self.write_tag(tag, value),
+            _ => Err(Rexiv2ImageError::UnsupportedTag(tag.to_string())),
+        }
+    }
+
+    //Whether the values of the tag have to be read with read_tag_multiple, read_tag joining them
+    fn is_multiple_valued(&self, tag: &str) -> bool {
+        tag.starts_with("Iptc.")
+    }
+
+    //Whether the tag can be written in the format of the image
+    fn supports_tag(&self, _tag: &str) -> bool {
+        true
+    }
+
+    fn copy_tag(&mut self, from: &Self, tag: &str) -> Result<(), Rexiv2ImageError> where Self: Sized {
+        if from.is_multiple_valued(tag) {
+            let values = from.read_tag_multiple(tag)?;
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+
+            self.write_tag_multiple(tag, &values)
+        } else {
+            self.write_tag(tag, &from.read_tag(tag)?)
+        }
+    }
+
+    fn read_orientation(&self) -> Orientation {
+        self.read_tag(ORIENTATION_TAG).ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map_or(Orientation::Unspecified, orientation::from_exif)
+    }
+
+    fn write_orientation(&mut self, orientation: Orientation) -> Result<(), Rexiv2ImageError> {
+        self.write_tag(ORIENTATION_TAG, &(orientation as u16).to_string())
+    }
+
+    //What save_metadata does, for backends which keep pending changes beside the tags
+    fn save_decoder<R: Read + Seek>(decoder: &DecoderWithMetadata<R, Self>, path: &Path) -> Result<(), Rexiv2ImageError>
+        where Self: Sized {
+        decoder.metadata.save(path)
+    }
+
+    //Bytes of each value, IIM datasets are not always UTF-8
+    fn read_tag_bytes(&self, tag: &str) -> Result<Vec<Vec<u8>>, Rexiv2ImageError> {
+        Ok(self.read_tag_multiple(tag)?.into_iter().map(String::into_bytes).collect())
+    }
+
+    //Backends which only store text refuse values which are not UTF-8 with UnsupportedTag
+    fn write_tag_bytes(&mut self, tag: &str, values: &[Vec<u8>]) -> Result<(), Rexiv2ImageError> {
+        let values: Vec<&str> = values.iter().map(|value| str::from_utf8(value)).collect::<Result<_, _>>()
+            .map_err(|_| Rexiv2ImageError::UnsupportedTag(tag.to_string()))?;
+
+        self.write_tag_multiple(tag, &values)
+    }
+
+    //read_tag_bytes for backends which need the decoder to get the bytes
+    fn read_iim_bytes<R: Read + Seek>(decoder: &DecoderWithMetadata<R, Self>, tag: &str)
+                                      -> Result<Vec<Vec<u8>>, Rexiv2ImageError> where Self: Sized {
+        decoder.metadata.read_tag_bytes(tag)
+    }
+
+    //Removes the EXIF thumbnail along with its tags
+    fn erase_thumbnail<R: Read + Seek>(decoder: &mut DecoderWithMetadata<R, Self>) -> Result<(), Rexiv2ImageError>
+        where Self: Sized {
+        for tag in decoder.metadata.list_tags()?.iter().filter(|tag| tag.starts_with("Exif.Thumbnail.")) {
+            decoder.metadata.remove_tag(tag);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl MetadataBackend for Metadata {
+    fn from_buffer(data: &[u8]) -> Result<Metadata, Rexiv2ImageError> {
+        Ok(Metadata::new_from_buffer(data)?)
+    }
+
+    fn read_tag(&self, tag: &str) -> Result<String, Rexiv2ImageError> {
+        if !Metadata::has_tag(self, tag) {
+            return Err(Rexiv2ImageError::TagNotFound(tag.to_string()));
+        }
+        Ok(Metadata::get_tag_string(self, tag)?)
+    }
+
+    fn write_tag(&mut self, tag: &str, value: &str) -> Result<(), Rexiv2ImageError> {
+        Ok(Metadata::set_tag_string(self, tag, value)?)
+    }
+
+    fn remove_tag(&mut self, tag: &str) -> bool {
+        Metadata::clear_tag(self, tag)
+    }
+
+    fn list_tags(&self) -> Result<Vec<String>, Rexiv2ImageError> {
+        let mut tags = self.get_exif_tags()?;
+
+        tags.extend(self.get_iptc_tags()?);
+        tags.extend(self.get_xmp_tags()?);
+        Ok(tags)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        Ok(self.save_to_file(path)?)
+    }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        Metadata::has_tag(self, tag)
+    }
+
+    fn read_tag_multiple(&self, tag: &str) -> Result<Vec<String>, Rexiv2ImageError> {
+        if !Metadata::has_tag(self, tag) {
+            return Ok(Vec::new());
+        }
+        Ok(self.get_tag_multiple_strings(tag)?)
+    }
+
+    fn write_tag_multiple(&mut self, tag: &str, values: &[&str]) -> Result<(), Rexiv2ImageError> {
+        self.clear_tag(tag);
+        if values.is_empty() {
+            return Ok(());
+        }
+        Ok(self.set_tag_multiple_strings(tag, values)?)
+    }
+
+    fn is_multiple_valued(&self, tag: &str) -> bool {
+        encoder::is_multiple_valued(tag)
+    }
+
+    fn supports_tag(&self, tag: &str) -> bool {
+        (rexiv2::is_exif_tag(tag) && self.supports_exif()) || (rexiv2::is_iptc_tag(tag) && self.supports_iptc())
+            || (rexiv2::is_xmp_tag(tag) && self.supports_xmp())
+    }
+
+    fn copy_tag(&mut self, from: &Metadata, tag: &str) -> Result<(), Rexiv2ImageError> {
+        encoder::copy_tag(from, self, tag)
+    }
+
+    //Also looks at the XMP orientation
+    fn read_orientation(&self) -> Orientation {
+        self.get_orientation()
+    }
+
+    fn write_orientation(&mut self, orientation: Orientation) -> Result<(), Rexiv2ImageError> {
+        self.set_orientation(orientation);
+        Ok(())
+    }
+
+    //The EXIF thumbnail is updated once the tags are written
+    fn save_decoder<R: Read + Seek>(decoder: &DecoderWithMetadata<R, Metadata>, path: &Path)
+                                    -> Result<(), Rexiv2ImageError> {
+        decoder.metadata.save_to_file(path)?;
+        decoder.save_thumbnail_update(path)
+    }
+
+    //rexiv2 can not read the datasets which are not UTF-8, they come from the gexiv2 handle
+    fn read_iim_bytes<R: Read + Seek>(decoder: &DecoderWithMetadata<R, Metadata>, tag: &str)
+                                      -> Result<Vec<Vec<u8>>, Rexiv2ImageError> {
+        if !Metadata::has_tag(&decoder.metadata, tag) {
+            return Ok(Vec::new());
+        }
+        match decoder.metadata.get_tag_multiple_strings(tag) {
+            Ok(values) => Ok(values.into_iter().map(String::into_bytes).collect()),
+            Err(rexiv2::Rexiv2Error::Utf8(_)) => decoder.raw_tag_bytes(tag),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    //The thumbnail is erased by save_metadata
+    fn erase_thumbnail<R: Read + Seek>(decoder: &mut DecoderWithMetadata<R, Metadata>) -> Result<(), Rexiv2ImageError> {
+        decoder.erase_exif_thumbnail();
+        Ok(())
+    }
+}
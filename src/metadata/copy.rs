@@ -0,0 +1,265 @@
+use std::io::{Read, Seek};
+use std::result::Result;
+use image::ImageDecoder;
+use orientation::Orientation;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::strip::matches_pattern;
+use super::tags::LAYOUT_TAGS;
+
+const WIDTH_TAGS: [&str; 2] = ["Exif.Photo.PixelXDimension", "Xmp.exif.PixelXDimension"];
+const HEIGHT_TAGS: [&str; 2] = ["Exif.Photo.PixelYDimension", "Xmp.exif.PixelYDimension"];
+const ORIENTATION_TAGS: [&str; 2] = ["Exif.Image.Orientation", "Xmp.tiff.Orientation"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagFamily {
+    Exif,
+    Iptc,
+    Xmp,
+}
+
+impl TagFamily {
+    pub fn of(tag: &str) -> Option<TagFamily> {
+        match tag.split('.').next() {
+            Some("Exif") => Some(TagFamily::Exif),
+            Some("Iptc") => Some(TagFamily::Iptc),
+            Some("Xmp") => Some(TagFamily::Xmp),
+            _ => None,
+        }
+    }
+}
+
+//What happens to a tag the destination already has
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    Overwrite,
+    KeepExisting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DimensionFix {
+    //The dimension tags are set to the pixels of the destination
+    Destination,
+    //The dimension tags are copied like any other
+    Source,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrientationFix {
+    Copy,
+    //The destination keeps its own orientation
+    Keep,
+    //Set to normal, for exports whose pixels were already turned upright
+    Reset,
+}
+
+//Same as exiftool -tagsFromFile by default: every tag is copied and overwrites the existing one,
+//except those describing the pixel layout and the EXIF thumbnail
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyPolicy {
+    conflict: Conflict,
+    families: Vec<TagFamily>,
+    exclude: Vec<String>,
+    dimensions: DimensionFix,
+    orientation: OrientationFix,
+}
+
+impl Default for CopyPolicy {
+    fn default() -> CopyPolicy {
+        CopyPolicy {
+            conflict: Conflict::Overwrite,
+            families: vec![TagFamily::Exif, TagFamily::Iptc, TagFamily::Xmp],
+            exclude: Vec::new(),
+            dimensions: DimensionFix::Destination,
+            orientation: OrientationFix::Copy,
+        }
+    }
+}
+
+impl CopyPolicy {
+    pub fn new() -> CopyPolicy {
+        CopyPolicy::default()
+    }
+
+    pub fn conflict(mut self, conflict: Conflict) -> CopyPolicy {
+        self.conflict = conflict;
+        self
+    }
+
+    pub fn families(mut self, families: &[TagFamily]) -> CopyPolicy {
+        self.families = families.to_vec();
+        self
+    }
+
+    //Same patterns as the strip profiles, '*' matches any run of characters
+    pub fn exclude(mut self, pattern: &str) -> CopyPolicy {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    pub fn dimensions(mut self, dimensions: DimensionFix) -> CopyPolicy {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub fn orientation(mut self, orientation: OrientationFix) -> CopyPolicy {
+        self.orientation = orientation;
+        self
+    }
+
+    fn copies(&self, tag: &str) -> bool {
+        if LAYOUT_TAGS.contains(&tag) || tag.starts_with("Exif.Thumbnail.") || tag == "Iptc.Envelope.CharacterSet" {
+            return false;
+        }
+        if self.orientation == OrientationFix::Keep && ORIENTATION_TAGS.contains(&tag) {
+            return false;
+        }
+        TagFamily::of(tag).is_some_and(|family| self.families.contains(&family))
+            && !self.exclude.iter().any(|pattern| matches_pattern(pattern, tag))
+    }
+}
+
+//Copies the metadata of `from` into `to` and returns the copied tags, nothing is written until save_metadata
+pub fn copy_metadata<R: Read + Seek, S: Read + Seek, M: MetadataBackend>(from: &DecoderWithMetadata<R, M>,
+                                                                       to: &mut DecoderWithMetadata<S, M>,
+                                                                       policy: &CopyPolicy)
+                                                                       -> Result<Vec<String>, Rexiv2ImageError> {
+    let mut copied = Vec::new();
+
+    for tag in from.metadata.list_tags()?.into_iter().filter(|tag| policy.copies(tag)) {
+        if policy.conflict == Conflict::KeepExisting && to.metadata.has_tag(&tag) {
+            continue;
+        }
+        if TagFamily::of(&tag) == Some(TagFamily::Iptc) {
+            //Decoded with the charset of the source, encoded with the one of the destination
+            to.set_iim_values(&tag, &from.get_iim_values(&tag)?)?;
+        } else {
+            to.metadata.copy_tag(&from.metadata, &tag)?;
+        }
+        copied.push(tag);
+    }
+
+    if policy.orientation == OrientationFix::Reset {
+        to.metadata.write_orientation(Orientation::Normal)?;
+    }
+    if policy.dimensions == DimensionFix::Destination {
+        let (width, height) = to.decoder.dimensions()?;
+
+        for &(tags, value) in &[(WIDTH_TAGS, width), (HEIGHT_TAGS, height)] {
+            for tag in tags.iter() {
+                if to.metadata.has_tag(tag) {
+                    to.metadata.write_tag(tag, &value.to_string())?;
+                }
+            }
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::io::Cursor;
+    use super::{Conflict, CopyPolicy, DimensionFix, OrientationFix, TagFamily, copy_metadata};
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    fn decoder(width: u32, height: u32, metadata: MemoryMetadata) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        let pixels = vec![128; (width * height) as usize];
+
+        DecoderWithMetadata::from_image(&DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels).unwrap()),
+                                        metadata).unwrap()
+    }
+
+    fn source() -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.Image.ImageWidth", "4")
+            .with_tag("Exif.Image.Make", "Camera")
+            .with_tag("Exif.Image.Orientation", "6")
+            .with_tag("Exif.Photo.PixelXDimension", "4")
+            .with_tag("Exif.Photo.PixelYDimension", "3")
+            .with_tag("Exif.Thumbnail.Compression", "6")
+            .with_tag("Iptc.Application2.City", "Paris")
+            .with_tag("Xmp.dc.subject", "sea")
+            .with_tag("Xmp.exif.PixelXDimension", "4");
+
+        decoder(4, 3, metadata)
+    }
+
+    fn destination() -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.Image.Make", "Scanner")
+            .with_tag("Exif.Image.Orientation", "3")
+            .with_tag("Xmp.photoshop.City", "Lyon");
+
+        decoder(2, 1, metadata)
+    }
+
+    #[test]
+    fn overwrite() {
+        let mut to = destination();
+        let copied = copy_metadata(&source(), &mut to, &CopyPolicy::new()).unwrap();
+
+        assert_eq!(copied, vec!["Exif.Image.Make", "Exif.Image.Orientation", "Exif.Photo.PixelXDimension",
+                                "Exif.Photo.PixelYDimension", "Iptc.Application2.City", "Xmp.dc.subject",
+                                "Xmp.exif.PixelXDimension"]);
+        assert_eq!(to.metadata.read_tag("Exif.Image.Make").unwrap(), "Camera");
+        assert_eq!(to.metadata.read_tag("Xmp.photoshop.City").unwrap(), "Lyon");
+        assert!(!to.metadata.has_tag("Exif.Image.ImageWidth"));
+        assert!(!to.metadata.has_tag("Exif.Thumbnail.Compression"));
+    }
+
+    #[test]
+    fn keep_existing() {
+        let mut to = destination();
+        let copied = copy_metadata(&source(), &mut to, &CopyPolicy::new().conflict(Conflict::KeepExisting)).unwrap();
+
+        assert!(!copied.contains(&"Exif.Image.Make".to_string()));
+        assert!(copied.contains(&"Iptc.Application2.City".to_string()));
+        assert_eq!(to.metadata.read_tag("Exif.Image.Make").unwrap(), "Scanner");
+        assert_eq!(to.metadata.read_tag("Exif.Image.Orientation").unwrap(), "3");
+    }
+
+    #[test]
+    fn families() {
+        let mut to = destination();
+        let policy = CopyPolicy::new().families(&[TagFamily::Iptc, TagFamily::Xmp]).exclude("Xmp.exif.*");
+        let copied = copy_metadata(&source(), &mut to, &policy).unwrap();
+
+        assert_eq!(copied, vec!["Iptc.Application2.City", "Xmp.dc.subject"]);
+        assert_eq!(to.metadata.read_tag("Exif.Image.Make").unwrap(), "Scanner");
+        assert_eq!(TagFamily::of("Exif.Image.Make"), Some(TagFamily::Exif));
+        assert_eq!(TagFamily::of("Makernote.Make"), None);
+    }
+
+    #[test]
+    fn dimensions() {
+        let mut to = destination();
+
+        copy_metadata(&source(), &mut to, &CopyPolicy::new()).unwrap();
+        assert_eq!(to.metadata.read_tag("Exif.Photo.PixelXDimension").unwrap(), "2");
+        assert_eq!(to.metadata.read_tag("Exif.Photo.PixelYDimension").unwrap(), "1");
+        assert_eq!(to.metadata.read_tag("Xmp.exif.PixelXDimension").unwrap(), "2");
+        //Only the dimension tags which were copied are rewritten
+        assert!(!to.metadata.has_tag("Xmp.exif.PixelYDimension"));
+
+        let mut to = destination();
+
+        copy_metadata(&source(), &mut to, &CopyPolicy::new().dimensions(DimensionFix::Source)).unwrap();
+        assert_eq!(to.metadata.read_tag("Exif.Photo.PixelXDimension").unwrap(), "4");
+        assert_eq!(to.metadata.read_tag("Exif.Photo.PixelYDimension").unwrap(), "3");
+    }
+
+    #[test]
+    fn orientation() {
+        let expected = [(OrientationFix::Copy, "6"), (OrientationFix::Keep, "3"), (OrientationFix::Reset, "1")];
+
+        for &(fix, value) in expected.iter() {
+            let mut to = destination();
+
+            copy_metadata(&source(), &mut to, &CopyPolicy::new().orientation(fix)).unwrap();
+            assert_eq!(to.metadata.read_tag("Exif.Image.Orientation").unwrap(), value, "{:?}", fix);
+        }
+    }
+}
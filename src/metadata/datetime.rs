@@ -0,0 +1,594 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Seek};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::tags::parse_ratio;
+
+//Date, subseconds and offset tags of each EXIF timestamp
+const EXIF_DATES: [(&str, &str, &str); 3] = [
+    ("Exif.Photo.DateTimeOriginal", "Exif.Photo.SubSecTimeOriginal", "Exif.Photo.OffsetTimeOriginal"),
+    ("Exif.Photo.DateTimeDigitized", "Exif.Photo.SubSecTimeDigitized", "Exif.Photo.OffsetTimeDigitized"),
+    ("Exif.Image.DateTime", "Exif.Photo.SubSecTime", "Exif.Photo.OffsetTime"),
+];
+
+//ISO 8601 dates, possibly without the time
+const XMP_DATES: [&str; 7] = [
+    "Xmp.exif.DateTimeOriginal",
+    "Xmp.photoshop.DateCreated",
+    "Xmp.exif.DateTimeDigitized",
+    "Xmp.xmp.CreateDate",
+    "Xmp.tiff.DateTime",
+    "Xmp.xmp.ModifyDate",
+    "Xmp.xmp.MetadataDate",
+];
+
+//Date and time datasets, the time holds the offset
+const IPTC_DATES: [(&str, &str); 2] = [
+    ("Iptc.Application2.DateCreated", "Iptc.Application2.TimeCreated"),
+    ("Iptc.Application2.DigitizationDate", "Iptc.Application2.DigitizationTime"),
+];
+
+const GPS_DATE: &str = "Exif.GPSInfo.GPSDateStamp";
+const GPS_TIME: &str = "Exif.GPSInfo.GPSTimeStamp";
+
+//Timezones are at most 14 hours away from UTC, and on a quarter of an hour
+const MAX_OFFSET: i32 = 14 * 60;
+const OFFSET_STEP: i64 = 15;
+
+//Days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year as i64 - 1 } else { year as i64 };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as i32, month, day)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    (days_from_civil(if month == 12 { year + 1 } else { year }, month % 12 + 1, 1)
+        - days_from_civil(year, month, 1)) as u32
+}
+
+//"+02:00", "-0530" or "Z", in minutes east of UTC
+pub fn parse_offset(value: &str) -> Option<i32> {
+    let value = value.trim();
+
+    if value == "Z" {
+        return Some(0);
+    }
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = value[1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let offset = digits[..2].parse::<i32>().ok()? * 60 + digits[2..].parse::<i32>().ok()?;
+
+    if offset > MAX_OFFSET {
+        return None;
+    }
+    Some(sign * offset)
+}
+
+pub fn format_offset(offset: i32) -> String {
+    format!("{}{:02}:{:02}", if offset < 0 { '-' } else { '+' }, offset.abs() / 60, offset.abs() % 60)
+}
+
+fn parse_number(value: &str, digits: usize) -> Option<u32> {
+    if value.len() != digits || !value.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+//Fraction digits as nanoseconds, "5" is half a second
+fn parse_subsec(digits: &str) -> Option<u32> {
+    let digits = digits.trim();
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let padded: String = digits.chars().chain("000000000".chars()).take(9).collect();
+    padded.parse().ok()
+}
+
+fn format_subsec(nanosecond: u32) -> String {
+    let digits = format!("{:09}", nanosecond);
+
+    digits.trim_end_matches('0').to_string()
+}
+
+//Wall clock time of a capture, the offset is unknown for most cameras
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanosecond: u32,
+    //Minutes east of UTC
+    pub offset: Option<i32>,
+}
+
+impl DateTime {
+    pub fn new(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Option<DateTime> {
+        if month == 0 || month > 12 || day == 0 || day > days_in_month(year, month)
+            || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        Some(DateTime { year, month, day, hour, minute, second, nanosecond: 0, offset: None })
+    }
+
+    pub fn with_nanosecond(mut self, nanosecond: u32) -> DateTime {
+        self.nanosecond = nanosecond % 1_000_000_000;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: Option<i32>) -> DateTime {
+        self.offset = offset;
+        self
+    }
+
+    //"YYYY:MM:DD HH:MM:SS" as in EXIF, cameras write blanks or zeros when the clock is not set
+    pub fn parse_exif(value: &str) -> Option<DateTime> {
+        let value = value.trim();
+        if value.len() != 19 || &value[10..11] != " " {
+            return None;
+        }
+        let date: Vec<&str> = value[..10].split(':').collect();
+        let time: Vec<&str> = value[11..].split(':').collect();
+        if date.len() != 3 || time.len() != 3 {
+            return None;
+        }
+
+        DateTime::new(parse_number(date[0], 4)? as i32, parse_number(date[1], 2)?, parse_number(date[2], 2)?,
+                      parse_number(time[0], 2)?, parse_number(time[1], 2)?, parse_number(time[2], 2)?)
+    }
+
+    pub fn to_exif_string(&self) -> String {
+        format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+                self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+
+    //ISO 8601 as used by XMP, the time and its parts may be left out and are then zero
+    pub fn parse_iso8601(value: &str) -> Option<DateTime> {
+        DateTime::parse_iso8601_with_time(value).map(|(date_time, _)| date_time)
+    }
+
+    fn parse_iso8601_with_time(value: &str) -> Option<(DateTime, bool)> {
+        let value = value.trim();
+        let (date, time) = match value.find('T') {
+            Some(index) => (&value[..index], Some(&value[index + 1..])),
+            None => (value, None),
+        };
+        let date: Vec<&str> = date.split('-').collect();
+        let year = parse_number(date[0], 4)? as i32;
+        let month = match date.get(1) { Some(month) => parse_number(month, 2)?, None => 1 };
+        let day = match date.get(2) { Some(day) => parse_number(day, 2)?, None => 1 };
+        if date.len() > 3 {
+            return None;
+        }
+        let time = match time {
+            None => return DateTime::new(year, month, day, 0, 0, 0).map(|date_time| (date_time, false)),
+            Some(time) => time,
+        };
+
+        let offset_start = time.find(['Z', '+', '-']).unwrap_or(time.len());
+        let offset = if offset_start < time.len() { Some(parse_offset(&time[offset_start..])?) } else { None };
+        let (time, fraction) = match time[..offset_start].find('.') {
+            Some(index) => (&time[..index], Some(&time[index + 1..offset_start])),
+            None => (&time[..offset_start], None),
+        };
+        let time: Vec<&str> = time.split(':').collect();
+        if time.len() < 2 || time.len() > 3 {
+            return None;
+        }
+        let second = match time.get(2) { Some(second) => parse_number(second, 2)?, None => 0 };
+        let nanosecond = match fraction { Some(fraction) => parse_subsec(fraction)?, None => 0 };
+
+        DateTime::new(year, month, day, parse_number(time[0], 2)?, parse_number(time[1], 2)?, second)
+            .map(|date_time| (date_time.with_nanosecond(nanosecond).with_offset(offset), true))
+    }
+
+    pub fn to_iso8601(&self) -> String {
+        let mut value = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                                self.year, self.month, self.day, self.hour, self.minute, self.second);
+
+        if self.nanosecond != 0 {
+            value.push('.');
+            value.push_str(&format_subsec(self.nanosecond));
+        }
+        if let Some(offset) = self.offset {
+            value.push_str(&format_offset(offset));
+        }
+        value
+    }
+
+    //Seconds since 1970-01-01 00:00:00 of the wall clock, ignoring the offset
+    fn local_seconds(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86_400
+            + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    fn from_local_seconds(seconds: i64, nanosecond: u32, offset: Option<i32>) -> DateTime {
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+        let time = seconds.rem_euclid(86_400) as u32;
+
+        DateTime { year, month, day, hour: time / 3600, minute: time / 60 % 60, second: time % 60, nanosecond, offset }
+    }
+
+    //Unix timestamp, only known with the offset
+    pub fn timestamp(&self) -> Option<i64> {
+        self.offset.map(|offset| self.local_seconds() - offset as i64 * 60)
+    }
+
+    //Moves the wall clock, the offset is kept
+    pub fn shifted(&self, seconds: i64) -> DateTime {
+        DateTime::from_local_seconds(self.local_seconds() + seconds, self.nanosecond, self.offset)
+    }
+
+    //Same instant seen from another timezone, a time without offset only gets the new one
+    pub fn to_offset(&self, offset: i32) -> DateTime {
+        match self.offset {
+            Some(current) => self.shifted((offset - current) as i64 * 60).with_offset(Some(offset)),
+            None => self.with_offset(Some(offset)),
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_iso8601())
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    fn get_exif_date(&self, (date, subsec, offset): (&str, &str, &str)) -> Result<Option<DateTime>, Rexiv2ImageError> {
+        let date_time = match self.read_optional_tag(date)?.and_then(|value| DateTime::parse_exif(&value)) {
+            Some(date_time) => date_time,
+            None => return Ok(None),
+        };
+        let nanosecond = self.read_optional_tag(subsec)?.and_then(|value| parse_subsec(&value)).unwrap_or(0);
+        let offset = self.read_optional_tag(offset)?.and_then(|value| parse_offset(&value));
+
+        Ok(Some(date_time.with_nanosecond(nanosecond).with_offset(offset)))
+    }
+
+    fn set_exif_date(&mut self, (date, subsec, offset): (&str, &str, &str), date_time: &DateTime)
+                     -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag(date, &date_time.to_exif_string())?;
+        if date_time.nanosecond != 0 {
+            self.metadata.write_tag(subsec, &format_subsec(date_time.nanosecond))?;
+        } else {
+            self.metadata.remove_tag(subsec);
+        }
+        match date_time.offset {
+            Some(value) => self.metadata.write_tag(offset, &format_offset(value))?,
+            None => {
+                self.metadata.remove_tag(offset);
+            },
+        }
+        Ok(())
+    }
+
+    //exiv2 gives IPTC dates as YYYY-MM-DD and times as HH:MM:SS+HH:MM
+    fn get_iptc_date(&self, (date, time): (&str, &str)) -> Result<Option<DateTime>, Rexiv2ImageError> {
+        let (date, time) = match (self.read_optional_tag(date)?, self.read_optional_tag(time)?) {
+            (Some(date), Some(time)) => (date, time),
+            _ => return Ok(None),
+        };
+
+        Ok(DateTime::parse_iso8601(&format!("{}T{}", date.trim(), time.trim())))
+    }
+
+    //An unknown offset is not written, the one of the current time is kept if there is one
+    fn set_iptc_date(&mut self, (date, time): (&str, &str), date_time: &DateTime) -> Result<(), Rexiv2ImageError> {
+        let offset = match date_time.offset {
+            Some(offset) => Some(offset),
+            None => self.read_optional_tag(time)?.and_then(|value| value.trim().get(8..).and_then(parse_offset)),
+        };
+
+        self.metadata.write_tag(date, &format!("{:04}-{:02}-{:02}", date_time.year, date_time.month, date_time.day))?;
+        self.metadata.write_tag(time, &format!("{:02}:{:02}:{:02}{}",
+                                               date_time.hour, date_time.minute, date_time.second,
+                                               offset.map(format_offset).unwrap_or_default()))?;
+        Ok(())
+    }
+
+    //UTC time of the GPS fix
+    pub fn gps_time(&self) -> Result<Option<DateTime>, Rexiv2ImageError> {
+        let (date, time) = match (self.read_optional_tag(GPS_DATE)?, self.read_optional_tag(GPS_TIME)?) {
+            (Some(date), Some(time)) => (date, time),
+            _ => return Ok(None),
+        };
+        let date = match DateTime::parse_exif(&format!("{} 00:00:00", date.trim())) {
+            Some(date) => date,
+            None => return Ok(None),
+        };
+        let parts: Option<Vec<f64>> = time.split_whitespace()
+            .map(|part| parse_ratio(part).map(|ratio| *ratio.numer() as f64 / *ratio.denom() as f64))
+            .collect();
+        let seconds = match parts {
+            Some(ref parts) if parts.len() == 3 => parts[0] * 3600.0 + parts[1] * 60.0 + parts[2],
+            _ => return Ok(None),
+        };
+
+        Ok(Some(date.shifted(seconds.trunc() as i64)
+                .with_nanosecond((seconds.fract() * 1e9) as u32)
+                .with_offset(Some(0))))
+    }
+
+    //Taken from DateTimeOriginal, the XMP and IPTC creation dates, then the digitization and modification dates.
+    //A missing offset is looked for in the other sources at the same wall clock time,
+    //then deduced from the GPS time
+    pub fn capture_time(&self) -> Result<Option<DateTime>, Rexiv2ImageError> {
+        let mut candidates = Vec::new();
+
+        candidates.extend(self.get_exif_date(EXIF_DATES[0])?);
+        for tag in XMP_DATES[..2].iter() {
+            candidates.extend(self.read_optional_tag(tag)?
+                .and_then(|value| DateTime::parse_iso8601_with_time(&value))
+                .and_then(|(date_time, has_time)| if has_time { Some(date_time) } else { None }));
+        }
+        candidates.extend(self.get_iptc_date(IPTC_DATES[0])?);
+        candidates.extend(self.get_exif_date(EXIF_DATES[1])?);
+        candidates.extend(self.get_exif_date(EXIF_DATES[2])?);
+
+        let capture = match candidates.first() {
+            Some(capture) => *capture,
+            None => return Ok(None),
+        };
+        if capture.offset.is_some() {
+            return Ok(Some(capture));
+        }
+        let same_time = candidates.iter()
+            .find(|candidate| candidate.offset.is_some() && candidate.local_seconds() == capture.local_seconds());
+        if let Some(candidate) = same_time {
+            return Ok(Some(capture.with_offset(candidate.offset)));
+        }
+        Ok(Some(capture.with_offset(self.gps_time()?.and_then(|gps| {
+            let minutes = (capture.local_seconds() - gps.local_seconds()) as f64 / 60.0;
+            let offset = ((minutes / OFFSET_STEP as f64).round() as i64 * OFFSET_STEP) as i32;
+
+            if offset.abs() <= MAX_OFFSET { Some(offset) } else { None }
+        }))))
+    }
+
+    //Writes DateTimeOriginal and the creation dates of XMP and IPTC, when the file can hold them
+    pub fn set_capture_time(&mut self, date_time: &DateTime) -> Result<(), Rexiv2ImageError> {
+        if self.metadata.supports_tag(EXIF_DATES[0].0) {
+            self.set_exif_date(EXIF_DATES[0], date_time)?;
+        }
+        for tag in XMP_DATES[..2].iter() {
+            if self.metadata.supports_tag(tag) {
+                self.metadata.write_tag(tag, &date_time.to_iso8601())?;
+            }
+        }
+        //The dates are ASCII, which every IPTC charset reads the same
+        if self.metadata.supports_tag(IPTC_DATES[0].0) {
+            self.set_iptc_date(IPTC_DATES[0], date_time)?;
+        }
+        Ok(())
+    }
+
+    //Applies `change` to every date the file holds. The GPS time is left as it is,
+    //it comes from the satellites and not from the camera clock
+    fn map_dates<F: Fn(DateTime) -> DateTime>(&mut self, change: F) -> Result<(), Rexiv2ImageError> {
+        for &tags in EXIF_DATES.iter() {
+            if let Some(date_time) = self.get_exif_date(tags)? {
+                self.set_exif_date(tags, &change(date_time))?;
+            }
+        }
+        for tag in XMP_DATES.iter() {
+            let parsed = self.read_optional_tag(tag)?.and_then(|value| DateTime::parse_iso8601_with_time(&value));
+
+            //A date without time can not be moved by a fraction of a day
+            if let Some((date_time, true)) = parsed {
+                self.metadata.write_tag(tag, &change(date_time).to_iso8601())?;
+            }
+        }
+        for &tags in IPTC_DATES.iter() {
+            if let Some(date_time) = self.get_iptc_date(tags)? {
+                self.set_iptc_date(tags, &change(date_time))?;
+            }
+        }
+        Ok(())
+    }
+
+    //Moves every date by the same amount of seconds, to fix a camera clock that was off
+    pub fn shift_dates(&mut self, seconds: i64) -> Result<(), Rexiv2ImageError> {
+        self.map_dates(|date_time| date_time.shifted(seconds))
+    }
+
+    //For a camera set to the timezone `from` instead of `to`: the wall clock times are moved by the difference
+    //and every date gets the `to` offset
+    pub fn change_timezone(&mut self, from: i32, to: i32) -> Result<(), Rexiv2ImageError> {
+        self.map_dates(|date_time| date_time.shifted((to - from) as i64 * 60).with_offset(Some(to)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::io::Cursor;
+    use super::DateTime;
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    const TIME: &str = "Iptc.Application2.TimeCreated";
+
+    fn iptc_time(metadata: MemoryMetadata, date_time: DateTime) -> String {
+        let image = DynamicImage::ImageLuma8(GrayImage::new(1, 1));
+        let mut decoder = DecoderWithMetadata::from_image(&image, metadata).unwrap();
+
+        decoder.set_capture_time(&date_time).unwrap();
+        assert_eq!(decoder.metadata.tags()["Iptc.Application2.DateCreated"], vec!["2021-06-15"]);
+        decoder.metadata.tags()[TIME][0].clone()
+    }
+
+    #[test]
+    fn iptc_offset() {
+        let date_time = DateTime::new(2021, 6, 15, 10, 20, 30).unwrap();
+
+        assert_eq!(iptc_time(MemoryMetadata::new(), date_time), "10:20:30");
+        assert_eq!(iptc_time(MemoryMetadata::new().with_tag(TIME, "08:00:00+02:00"), date_time), "10:20:30+02:00");
+        assert_eq!(iptc_time(MemoryMetadata::new().with_tag(TIME, "08:00:00+02:00"), date_time.with_offset(Some(-330))),
+                   "10:20:30-05:30");
+    }
+
+    fn decoder(metadata: MemoryMetadata) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_image(&DynamicImage::ImageLuma8(GrayImage::new(1, 1)), metadata).unwrap()
+    }
+
+    fn date_time(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime {
+        DateTime::new(year, month, day, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let exif = DateTime::parse_exif("2021:06:15 10:20:30").unwrap();
+
+        assert_eq!(exif, date_time(2021, 6, 15, 10, 20, 30));
+        assert_eq!(exif.to_exif_string(), "2021:06:15 10:20:30");
+        for value in ["2021-06-15T10:20:30", "2021-06-15T10:20:30.25+02:00", "2021-06-15T10:20:30.000001-05:30"].iter() {
+            assert_eq!(DateTime::parse_iso8601(value).unwrap().to_iso8601(), *value);
+        }
+        assert_eq!(DateTime::parse_iso8601("2021-06-15T10:20:30.250+02:00").unwrap(),
+                   exif.with_nanosecond(250_000_000).with_offset(Some(120)));
+        assert_eq!(DateTime::parse_iso8601("2021-06-15T10:20Z").unwrap().to_iso8601(), "2021-06-15T10:20:00+00:00");
+        assert_eq!(DateTime::parse_iso8601("2021-06").unwrap(), date_time(2021, 6, 1, 0, 0, 0));
+        assert_eq!(DateTime::parse_exif("0000:00:00 00:00:00"), None);
+        assert_eq!(DateTime::parse_exif("    :  :     :  :  "), None);
+        assert_eq!(DateTime::parse_exif("2021:02:29 10:20:30"), None);
+        assert_eq!(DateTime::parse_iso8601("2021-06-15T10:20:30+15:00"), None);
+    }
+
+    #[test]
+    fn exif_tags() {
+        let mut decoder = decoder(MemoryMetadata::new());
+        let capture = date_time(2021, 6, 15, 10, 20, 30).with_nanosecond(50_000_000).with_offset(Some(120));
+
+        decoder.set_capture_time(&capture).unwrap();
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.DateTimeOriginal").unwrap(), "2021:06:15 10:20:30");
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.SubSecTimeOriginal").unwrap(), "05");
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.OffsetTimeOriginal").unwrap(), "+02:00");
+        assert_eq!(decoder.metadata.read_tag("Xmp.exif.DateTimeOriginal").unwrap(), "2021-06-15T10:20:30.05+02:00");
+        assert_eq!(decoder.capture_time().unwrap(), Some(capture));
+
+        let capture = date_time(2021, 6, 15, 10, 20, 30);
+
+        decoder.set_capture_time(&capture).unwrap();
+        assert!(!decoder.metadata.has_tag("Exif.Photo.SubSecTimeOriginal"));
+        assert!(!decoder.metadata.has_tag("Exif.Photo.OffsetTimeOriginal"));
+        //The IPTC time keeps the offset it had
+        assert_eq!(decoder.capture_time().unwrap(), Some(capture.with_offset(Some(120))));
+    }
+
+    #[test]
+    fn shifted() {
+        assert_eq!(date_time(2020, 12, 31, 23, 30, 0).shifted(3600), date_time(2021, 1, 1, 0, 30, 0));
+        assert_eq!(date_time(2020, 2, 28, 23, 0, 0).shifted(3600), date_time(2020, 2, 29, 0, 0, 0));
+        assert_eq!(date_time(2021, 3, 1, 0, 10, 0).shifted(-3600), date_time(2021, 2, 28, 23, 10, 0));
+        assert_eq!(date_time(2021, 1, 1, 0, 0, 0).with_offset(Some(60)).timestamp(), Some(1_609_455_600));
+        assert_eq!(date_time(2021, 1, 1, 0, 0, 0).timestamp(), None);
+        assert_eq!(date_time(2021, 1, 1, 1, 0, 0).with_offset(Some(60)).to_offset(-300),
+                   date_time(2020, 12, 31, 19, 0, 0).with_offset(Some(-300)));
+    }
+
+    #[test]
+    fn shift_dates() {
+        let mut decoder = decoder(MemoryMetadata::new()
+            .with_tag("Exif.Photo.DateTimeOriginal", "2021:01:31 23:30:00")
+            .with_tag("Exif.Image.DateTime", "2021:02:01 08:00:00")
+            .with_tag("Xmp.xmp.CreateDate", "2021-01-31T23:30:00+01:00")
+            .with_tag("Xmp.photoshop.DateCreated", "2021-01-31")
+            .with_tag("Iptc.Application2.DateCreated", "2021-01-31")
+            .with_tag(TIME, "23:30:00+01:00")
+            .with_tag("Exif.GPSInfo.GPSDateStamp", "2021:01:31")
+            .with_tag("Exif.GPSInfo.GPSTimeStamp", "22/1 30/1 0/1"));
+
+        decoder.shift_dates(3600).unwrap();
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.DateTimeOriginal").unwrap(), "2021:02:01 00:30:00");
+        assert_eq!(decoder.metadata.read_tag("Exif.Image.DateTime").unwrap(), "2021:02:01 09:00:00");
+        assert_eq!(decoder.metadata.read_tag("Xmp.xmp.CreateDate").unwrap(), "2021-02-01T00:30:00+01:00");
+        assert_eq!(decoder.metadata.read_tag("Xmp.photoshop.DateCreated").unwrap(), "2021-01-31");
+        assert_eq!(decoder.metadata.read_tag("Iptc.Application2.DateCreated").unwrap(), "2021-02-01");
+        assert_eq!(decoder.metadata.read_tag(TIME).unwrap(), "00:30:00+01:00");
+        assert_eq!(decoder.metadata.read_tag("Exif.GPSInfo.GPSTimeStamp").unwrap(), "22/1 30/1 0/1");
+    }
+
+    #[test]
+    fn change_timezone() {
+        let mut decoder = decoder(MemoryMetadata::new()
+            .with_tag("Exif.Photo.DateTimeOriginal", "2021:06:15 23:30:00")
+            .with_tag("Xmp.xmp.CreateDate", "2021-06-15T23:30:00Z"));
+
+        decoder.change_timezone(0, 120).unwrap();
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.DateTimeOriginal").unwrap(), "2021:06:16 01:30:00");
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.OffsetTimeOriginal").unwrap(), "+02:00");
+        assert_eq!(decoder.metadata.read_tag("Xmp.xmp.CreateDate").unwrap(), "2021-06-16T01:30:00+02:00");
+    }
+
+    #[test]
+    fn capture_time_order() {
+        let xmp = "2021-06-15T11:00:00";
+        let iptc = MemoryMetadata::new()
+            .with_tag("Iptc.Application2.DateCreated", "2021-06-15")
+            .with_tag(TIME, "12:00:00+02:00");
+        let capture = |metadata: MemoryMetadata| decoder(metadata).capture_time().unwrap();
+
+        assert_eq!(capture(MemoryMetadata::new()), None);
+        assert_eq!(capture(iptc.clone()), Some(date_time(2021, 6, 15, 12, 0, 0).with_offset(Some(120))));
+        //A date without time is not a capture time
+        assert_eq!(capture(iptc.clone().with_tag("Xmp.photoshop.DateCreated", "2021-06-15")),
+                   Some(date_time(2021, 6, 15, 12, 0, 0).with_offset(Some(120))));
+        assert_eq!(capture(iptc.clone().with_tag("Xmp.photoshop.DateCreated", xmp)),
+                   Some(date_time(2021, 6, 15, 11, 0, 0)));
+        assert_eq!(capture(iptc.clone()
+                           .with_tag("Xmp.photoshop.DateCreated", xmp)
+                           .with_tag("Exif.Photo.DateTimeOriginal", "2021:06:15 12:00:00")
+                           .with_tag("Exif.Image.DateTime", "2021:06:16 08:00:00")),
+                   Some(date_time(2021, 6, 15, 12, 0, 0).with_offset(Some(120))));
+        assert_eq!(capture(MemoryMetadata::new().with_tag("Exif.Image.DateTime", "2021:06:16 08:00:00")),
+                   Some(date_time(2021, 6, 16, 8, 0, 0)));
+    }
+
+    #[test]
+    fn offset_from_gps() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.Photo.DateTimeOriginal", "2021:06:15 01:00:00")
+            .with_tag("Exif.GPSInfo.GPSDateStamp", "2021:06:14");
+        let capture = |time: &str| {
+            decoder(metadata.clone().with_tag("Exif.GPSInfo.GPSTimeStamp", time)).capture_time().unwrap().unwrap()
+        };
+
+        //The GPS fix was taken a few seconds away from the shot
+        assert_eq!(capture("20/1 30/1 12/1").offset, Some(270));
+        assert_eq!(capture("23/1 0/1 0/1").offset, Some(120));
+        assert_eq!(capture("1/1 0/1 0/1").offset, None);
+    }
+}
@@ -0,0 +1,203 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Seek};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::copy::TagFamily;
+use super::strip::matches_pattern;
+#[cfg(feature = "serde")]
+use super::snapshot::{self, MetadataSnapshot, TagValue};
+
+//Values are compared in their exiv2 text form, repeatable IPTC datasets and XMP arrays hold one entry per value
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "change", rename_all = "snake_case"))]
+pub enum TagChange {
+    Added { tag: String, value: Vec<String> },
+    Removed { tag: String, value: Vec<String> },
+    Changed { tag: String, old: Vec<String>, new: Vec<String> },
+}
+
+impl TagChange {
+    pub fn tag(&self) -> &str {
+        match *self {
+            TagChange::Added { ref tag, .. } | TagChange::Removed { ref tag, .. } | TagChange::Changed { ref tag, .. } => tag,
+        }
+    }
+}
+
+//Changes sorted by tag name
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataDiff {
+    pub changes: Vec<TagChange>,
+}
+
+fn decoder_values<R: Read + Seek, M: MetadataBackend>(decoder: &DecoderWithMetadata<R, M>)
+                                                      -> Result<BTreeMap<String, Vec<String>>, Rexiv2ImageError> {
+    let mut values = BTreeMap::new();
+
+    for tag in decoder.metadata.list_tags()? {
+        let value = if TagFamily::of(&tag) == Some(TagFamily::Iptc) {
+            decoder.get_iim_values(&tag)?
+        } else if decoder.metadata.is_multiple_valued(&tag) {
+            decoder.metadata.read_tag_multiple(&tag)?
+        } else {
+            vec![decoder.metadata.read_tag(&tag)?]
+        };
+        values.insert(tag, value);
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "serde")]
+fn snapshot_values(snapshot: &MetadataSnapshot) -> BTreeMap<String, Vec<String>> {
+    snapshot.tags.iter().map(|(tag, tag_snapshot)| {
+        let value = match tag_snapshot.value {
+            TagValue::Text(ref text) => vec![text.clone()],
+            TagValue::Multiple(ref values) => values.clone(),
+            //Same text as exiv2 gives for the whole property
+            TagValue::LangAlt(ref entries) => vec![entries.iter()
+                                                  .map(|(language, text)| format!("lang=\"{}\" {}", language, text))
+                                                  .collect::<Vec<_>>()
+                                                  .join(", ")],
+            TagValue::Binary(ref data) => vec![snapshot::format_undefined(data)],
+        };
+        (tag.clone(), value)
+    }).collect()
+}
+
+impl MetadataDiff {
+    pub fn between<R: Read + Seek, S: Read + Seek, M: MetadataBackend, N: MetadataBackend>(
+        old: &DecoderWithMetadata<R, M>, new: &DecoderWithMetadata<S, N>) -> Result<MetadataDiff, Rexiv2ImageError> {
+        Ok(MetadataDiff::from_values(decoder_values(old)?, decoder_values(new)?))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn between_snapshots(old: &MetadataSnapshot, new: &MetadataSnapshot) -> MetadataDiff {
+        MetadataDiff::from_values(snapshot_values(old), snapshot_values(new))
+    }
+
+    fn from_values(mut old: BTreeMap<String, Vec<String>>, new: BTreeMap<String, Vec<String>>) -> MetadataDiff {
+        let mut changes = Vec::new();
+
+        for (tag, value) in new {
+            match old.remove(&tag) {
+                None => changes.push(TagChange::Added { tag, value }),
+                Some(old_value) => if old_value != value {
+                    changes.push(TagChange::Changed { tag, old: old_value, new: value });
+                },
+            }
+        }
+        changes.extend(old.into_iter().map(|(tag, value)| TagChange::Removed { tag, value }));
+        changes.sort_by(|a, b| a.tag().cmp(b.tag()));
+        MetadataDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn get(&self, tag: &str) -> Option<&TagChange> {
+        self.changes.iter().find(|change| change.tag() == tag)
+    }
+
+    pub fn added(&self) -> Vec<&str> {
+        self.tags_where(|change| matches!(change, TagChange::Added { .. }))
+    }
+
+    pub fn removed(&self) -> Vec<&str> {
+        self.tags_where(|change| matches!(change, TagChange::Removed { .. }))
+    }
+
+    pub fn changed(&self) -> Vec<&str> {
+        self.tags_where(|change| matches!(change, TagChange::Changed { .. }))
+    }
+
+    fn tags_where<F: Fn(&TagChange) -> bool>(&self, predicate: F) -> Vec<&str> {
+        self.changes.iter().filter(|change| predicate(change)).map(|change| change.tag()).collect()
+    }
+
+    //Drops the changes on tags matching the pattern, for tags a pipeline is expected to rewrite like dates
+    pub fn ignore(mut self, pattern: &str) -> MetadataDiff {
+        self.changes.retain(|change| !matches_pattern(pattern, change.tag()));
+        self
+    }
+}
+
+impl Display for MetadataDiff {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for change in &self.changes {
+            match *change {
+                TagChange::Added { ref tag, ref value } => writeln!(f, "+ {}: {}", tag, value.join(", "))?,
+                TagChange::Removed { ref tag, ref value } => writeln!(f, "- {}: {}", tag, value.join(", "))?,
+                TagChange::Changed { ref tag, ref old, ref new } =>
+                    writeln!(f, "~ {}: {} -> {}", tag, old.join(", "), new.join(", "))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::io::Cursor;
+    use super::{MetadataDiff, TagChange};
+    use super::super::DecoderWithMetadata;
+    use super::super::memory::MemoryMetadata;
+
+    fn decoder(metadata: MemoryMetadata) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_image(&DynamicImage::ImageLuma8(GrayImage::from_raw(1, 1, vec![0]).unwrap()),
+                                        metadata).unwrap()
+    }
+
+    fn diff() -> MetadataDiff {
+        let old = MemoryMetadata::new()
+            .with_tag("Exif.Image.Make", "Camera")
+            .with_tag("Exif.Image.Model", "X100")
+            .with_tag("Exif.Photo.DateTimeOriginal", "2020:01:01 10:00:00")
+            .with_tag_bytes("Iptc.Application2.City", &[&b"Z\xfcrich"[..]]);
+        let mut new = decoder(MemoryMetadata::new()
+            .with_tag("Exif.Image.Make", "Scanner")
+            .with_tag("Exif.Image.Software", "Editor")
+            .with_tag("Exif.Photo.DateTimeOriginal", "2020:01:01 10:00:00")
+            .with_tag("Iptc.Application2.City", "Zürich"));
+
+        new.set_xmp_array("Xmp.dc.subject", &["sea", "sky"]).unwrap();
+        MetadataDiff::between(&decoder(old), &new).unwrap()
+    }
+
+    #[test]
+    fn changes() {
+        let diff = diff();
+
+        assert_eq!(diff.added(), vec!["Exif.Image.Software", "Xmp.dc.subject"]);
+        assert_eq!(diff.removed(), vec!["Exif.Image.Model"]);
+        assert_eq!(diff.changed(), vec!["Exif.Image.Make"]);
+        assert_eq!(diff.get("Xmp.dc.subject"), Some(&TagChange::Added {
+            tag: "Xmp.dc.subject".to_string(),
+            value: vec!["sea".to_string(), "sky".to_string()],
+        }));
+        assert_eq!(diff.get("Exif.Image.Make"), Some(&TagChange::Changed {
+            tag: "Exif.Image.Make".to_string(),
+            old: vec!["Camera".to_string()],
+            new: vec!["Scanner".to_string()],
+        }));
+        //Same text once the Latin-1 dataset is decoded
+        assert_eq!(diff.get("Iptc.Application2.City"), None);
+        assert!(diff.ignore("Exif.*").ignore("Xmp.*").is_empty());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(diff().to_string(), "~ Exif.Image.Make: Camera -> Scanner\n\
+                                        - Exif.Image.Model: X100\n\
+                                        + Exif.Image.Software: Editor\n\
+                                        + Xmp.dc.subject: sea, sky\n");
+        assert_eq!(MetadataDiff::default().to_string(), "");
+    }
+}
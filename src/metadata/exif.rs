@@ -0,0 +1,930 @@
+use image::{ImageError, ImageFormat};
+use num_rational::Ratio;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+#[cfg(not(feature = "exiv2"))]
+use std::fs::File;
+#[cfg(not(feature = "exiv2"))]
+use std::io::Cursor;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::result::Result;
+use std::str::FromStr;
+use super::{guess_format_from_bytes, DecoderWithMetadata, Rexiv2ImageError};
+#[cfg(not(feature = "exiv2"))]
+use super::guess_format;
+use super::backend::MetadataBackend;
+use super::pages::{self, TiffReader, TiffValue};
+
+//Pure Rust backend for builds where gexiv2 can not be linked.
+//It reads the EXIF tags of JPEG, PNG (eXIf chunk) and TIFF files and writes them back into JPEG and PNG files,
+//IPTC and XMP tags are not supported. Maker notes and other values holding offsets would point to the wrong bytes
+//once the tags are laid out again, so save refuses them until they are removed.
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const SBYTE: u16 = 6;
+const UNDEFINED: u16 = 7;
+const SSHORT: u16 = 8;
+const SLONG: u16 = 9;
+const SRATIONAL: u16 = 10;
+const IFD: u16 = 13;
+
+const EXIF_POINTER: u16 = 0x8769;
+const GPS_POINTER: u16 = 0x8825;
+const INTEROPERABILITY_POINTER: u16 = 0xa005;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+const SUB_IFDS: u16 = 0x014a;
+const MAKER_NOTE: u16 = 0x927c;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+//IFD of a tag, named as in the exiv2 keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Group {
+    Image,
+    Photo,
+    Iop,
+    GpsInfo,
+    Thumbnail,
+}
+
+impl Group {
+    fn name(self) -> &'static str {
+        match self {
+            Group::Image => "Image",
+            Group::Photo => "Photo",
+            Group::Iop => "Iop",
+            Group::GpsInfo => "GPSInfo",
+            Group::Thumbnail => "Thumbnail",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Group> {
+        [Group::Image, Group::Photo, Group::Iop, Group::GpsInfo, Group::Thumbnail].iter()
+            .cloned()
+            .find(|group| group.name() == name)
+    }
+
+    //The thumbnail IFD uses the tags of the main image
+    fn table(self) -> Group {
+        match self {
+            Group::Thumbnail => Group::Image,
+            group => group,
+        }
+    }
+}
+
+//Tags which can be written, with their field type
+static EXIF_TAGS: &[(Group, u16, &str, u16)] = &[
+    (Group::Image, 0x000b, "ProcessingSoftware", ASCII),
+    (Group::Image, 0x00fe, "NewSubfileType", LONG),
+    (Group::Image, 0x0100, "ImageWidth", LONG),
+    (Group::Image, 0x0101, "ImageLength", LONG),
+    (Group::Image, 0x0102, "BitsPerSample", SHORT),
+    (Group::Image, 0x0103, "Compression", SHORT),
+    (Group::Image, 0x0106, "PhotometricInterpretation", SHORT),
+    (Group::Image, 0x010d, "DocumentName", ASCII),
+    (Group::Image, 0x010e, "ImageDescription", ASCII),
+    (Group::Image, 0x010f, "Make", ASCII),
+    (Group::Image, 0x0110, "Model", ASCII),
+    (Group::Image, 0x0111, "StripOffsets", LONG),
+    (Group::Image, 0x0112, "Orientation", SHORT),
+    (Group::Image, 0x0115, "SamplesPerPixel", SHORT),
+    (Group::Image, 0x0116, "RowsPerStrip", LONG),
+    (Group::Image, 0x0117, "StripByteCounts", LONG),
+    (Group::Image, 0x011a, "XResolution", RATIONAL),
+    (Group::Image, 0x011b, "YResolution", RATIONAL),
+    (Group::Image, 0x011c, "PlanarConfiguration", SHORT),
+    (Group::Image, 0x011d, "PageName", ASCII),
+    (Group::Image, 0x0128, "ResolutionUnit", SHORT),
+    (Group::Image, 0x0129, "PageNumber", SHORT),
+    (Group::Image, 0x012d, "TransferFunction", SHORT),
+    (Group::Image, 0x0131, "Software", ASCII),
+    (Group::Image, 0x0132, "DateTime", ASCII),
+    (Group::Image, 0x013b, "Artist", ASCII),
+    (Group::Image, 0x013c, "HostComputer", ASCII),
+    (Group::Image, 0x013e, "WhitePoint", RATIONAL),
+    (Group::Image, 0x013f, "PrimaryChromaticities", RATIONAL),
+    (Group::Image, 0x0211, "YCbCrCoefficients", RATIONAL),
+    (Group::Image, 0x0212, "YCbCrSubSampling", SHORT),
+    (Group::Image, 0x0213, "YCbCrPositioning", SHORT),
+    (Group::Image, 0x0214, "ReferenceBlackWhite", RATIONAL),
+    (Group::Image, 0x4746, "Rating", SHORT),
+    (Group::Image, 0x4749, "RatingPercent", SHORT),
+    (Group::Image, 0x8298, "Copyright", ASCII),
+    (Group::Image, 0x9c9b, "XPTitle", BYTE),
+    (Group::Image, 0x9c9c, "XPComment", BYTE),
+    (Group::Image, 0x9c9d, "XPAuthor", BYTE),
+    (Group::Image, 0x9c9e, "XPKeywords", BYTE),
+    (Group::Image, 0x9c9f, "XPSubject", BYTE),
+    (Group::Photo, 0x829a, "ExposureTime", RATIONAL),
+    (Group::Photo, 0x829d, "FNumber", RATIONAL),
+    (Group::Photo, 0x8822, "ExposureProgram", SHORT),
+    (Group::Photo, 0x8824, "SpectralSensitivity", ASCII),
+    (Group::Photo, 0x8827, "ISOSpeedRatings", SHORT),
+    (Group::Photo, 0x8830, "SensitivityType", SHORT),
+    (Group::Photo, 0x8831, "StandardOutputSensitivity", LONG),
+    (Group::Photo, 0x8832, "RecommendedExposureIndex", LONG),
+    (Group::Photo, 0x8833, "ISOSpeed", LONG),
+    (Group::Photo, 0x9000, "ExifVersion", UNDEFINED),
+    (Group::Photo, 0x9003, "DateTimeOriginal", ASCII),
+    (Group::Photo, 0x9004, "DateTimeDigitized", ASCII),
+    (Group::Photo, 0x9010, "OffsetTime", ASCII),
+    (Group::Photo, 0x9011, "OffsetTimeOriginal", ASCII),
+    (Group::Photo, 0x9012, "OffsetTimeDigitized", ASCII),
+    (Group::Photo, 0x9101, "ComponentsConfiguration", UNDEFINED),
+    (Group::Photo, 0x9102, "CompressedBitsPerPixel", RATIONAL),
+    (Group::Photo, 0x9201, "ShutterSpeedValue", SRATIONAL),
+    (Group::Photo, 0x9202, "ApertureValue", RATIONAL),
+    (Group::Photo, 0x9203, "BrightnessValue", SRATIONAL),
+    (Group::Photo, 0x9204, "ExposureBiasValue", SRATIONAL),
+    (Group::Photo, 0x9205, "MaxApertureValue", RATIONAL),
+    (Group::Photo, 0x9206, "SubjectDistance", RATIONAL),
+    (Group::Photo, 0x9207, "MeteringMode", SHORT),
+    (Group::Photo, 0x9208, "LightSource", SHORT),
+    (Group::Photo, 0x9209, "Flash", SHORT),
+    (Group::Photo, 0x920a, "FocalLength", RATIONAL),
+    (Group::Photo, 0x9214, "SubjectArea", SHORT),
+    (Group::Photo, 0x927c, "MakerNote", UNDEFINED),
+    (Group::Photo, 0x9286, "UserComment", UNDEFINED),
+    (Group::Photo, 0x9290, "SubSecTime", ASCII),
+    (Group::Photo, 0x9291, "SubSecTimeOriginal", ASCII),
+    (Group::Photo, 0x9292, "SubSecTimeDigitized", ASCII),
+    (Group::Photo, 0xa000, "FlashpixVersion", UNDEFINED),
+    (Group::Photo, 0xa001, "ColorSpace", SHORT),
+    (Group::Photo, 0xa002, "PixelXDimension", LONG),
+    (Group::Photo, 0xa003, "PixelYDimension", LONG),
+    (Group::Photo, 0xa20e, "FocalPlaneXResolution", RATIONAL),
+    (Group::Photo, 0xa20f, "FocalPlaneYResolution", RATIONAL),
+    (Group::Photo, 0xa210, "FocalPlaneResolutionUnit", SHORT),
+    (Group::Photo, 0xa217, "SensingMethod", SHORT),
+    (Group::Photo, 0xa300, "FileSource", UNDEFINED),
+    (Group::Photo, 0xa301, "SceneType", UNDEFINED),
+    (Group::Photo, 0xa401, "CustomRendered", SHORT),
+    (Group::Photo, 0xa402, "ExposureMode", SHORT),
+    (Group::Photo, 0xa403, "WhiteBalance", SHORT),
+    (Group::Photo, 0xa404, "DigitalZoomRatio", RATIONAL),
+    (Group::Photo, 0xa405, "FocalLengthIn35mmFilm", SHORT),
+    (Group::Photo, 0xa406, "SceneCaptureType", SHORT),
+    (Group::Photo, 0xa407, "GainControl", SHORT),
+    (Group::Photo, 0xa408, "Contrast", SHORT),
+    (Group::Photo, 0xa409, "Saturation", SHORT),
+    (Group::Photo, 0xa40a, "Sharpness", SHORT),
+    (Group::Photo, 0xa40c, "SubjectDistanceRange", SHORT),
+    (Group::Photo, 0xa420, "ImageUniqueID", ASCII),
+    (Group::Photo, 0xa430, "CameraOwnerName", ASCII),
+    (Group::Photo, 0xa431, "BodySerialNumber", ASCII),
+    (Group::Photo, 0xa432, "LensSpecification", RATIONAL),
+    (Group::Photo, 0xa433, "LensMake", ASCII),
+    (Group::Photo, 0xa434, "LensModel", ASCII),
+    (Group::Photo, 0xa435, "LensSerialNumber", ASCII),
+    (Group::Iop, 0x0001, "InteroperabilityIndex", ASCII),
+    (Group::Iop, 0x0002, "InteroperabilityVersion", UNDEFINED),
+    (Group::GpsInfo, 0x0000, "GPSVersionID", BYTE),
+    (Group::GpsInfo, 0x0001, "GPSLatitudeRef", ASCII),
+    (Group::GpsInfo, 0x0002, "GPSLatitude", RATIONAL),
+    (Group::GpsInfo, 0x0003, "GPSLongitudeRef", ASCII),
+    (Group::GpsInfo, 0x0004, "GPSLongitude", RATIONAL),
+    (Group::GpsInfo, 0x0005, "GPSAltitudeRef", BYTE),
+    (Group::GpsInfo, 0x0006, "GPSAltitude", RATIONAL),
+    (Group::GpsInfo, 0x0007, "GPSTimeStamp", RATIONAL),
+    (Group::GpsInfo, 0x0008, "GPSSatellites", ASCII),
+    (Group::GpsInfo, 0x0009, "GPSStatus", ASCII),
+    (Group::GpsInfo, 0x000a, "GPSMeasureMode", ASCII),
+    (Group::GpsInfo, 0x000b, "GPSDOP", RATIONAL),
+    (Group::GpsInfo, 0x000c, "GPSSpeedRef", ASCII),
+    (Group::GpsInfo, 0x000d, "GPSSpeed", RATIONAL),
+    (Group::GpsInfo, 0x000e, "GPSTrackRef", ASCII),
+    (Group::GpsInfo, 0x000f, "GPSTrack", RATIONAL),
+    (Group::GpsInfo, 0x0010, "GPSImgDirectionRef", ASCII),
+    (Group::GpsInfo, 0x0011, "GPSImgDirection", RATIONAL),
+    (Group::GpsInfo, 0x0012, "GPSMapDatum", ASCII),
+    (Group::GpsInfo, 0x001b, "GPSProcessingMethod", UNDEFINED),
+    (Group::GpsInfo, 0x001c, "GPSAreaInformation", UNDEFINED),
+    (Group::GpsInfo, 0x001d, "GPSDateStamp", ASCII),
+    (Group::GpsInfo, 0x001e, "GPSDifferential", SHORT),
+];
+
+fn tag_name(group: Group, tag: u16) -> String {
+    EXIF_TAGS.iter()
+        .find(|&&(table, id, _, _)| table == group.table() && id == tag)
+        .map_or_else(|| format!("0x{:04x}", tag), |&(_, _, name, _)| name.to_string())
+}
+
+fn key(group: Group, tag: u16) -> String {
+    format!("Exif.{}.{}", group.name(), tag_name(group, tag))
+}
+
+//Tags unknown to the table are accepted in the "Exif.Image.0x1234" form exiv2 gives them
+fn parse_key(key: &str) -> Option<(Group, u16)> {
+    let mut parts = key.splitn(3, '.');
+
+    if parts.next()? != "Exif" {
+        return None;
+    }
+    let group = Group::from_name(parts.next()?)?;
+    let name = parts.next()?;
+
+    EXIF_TAGS.iter()
+        .find(|&&(table, _, table_name, _)| table == group.table() && table_name == name)
+        .map(|&(_, id, _, _)| id)
+        .or_else(|| u16::from_str_radix(name.strip_prefix("0x")?, 16).ok())
+        .map(|tag| (group, tag))
+}
+
+fn known_type(group: Group, tag: u16) -> Option<u16> {
+    EXIF_TAGS.iter()
+        .find(|&&(table, id, _, _)| table == group.table() && id == tag)
+        .map(|&(_, _, _, field_type)| field_type)
+}
+
+//Offsets are computed when the tags are written
+fn is_structural(group: Group, tag: u16) -> bool {
+    matches!((group, tag),
+             (Group::Image, EXIF_POINTER) | (Group::Image, GPS_POINTER) | (Group::Photo, INTEROPERABILITY_POINTER) |
+             (Group::Thumbnail, JPEG_INTERCHANGE_FORMAT) | (Group::Thumbnail, JPEG_INTERCHANGE_FORMAT_LENGTH))
+}
+
+//Unknown LONG values may be offsets as well, like the pointers of private IFDs
+fn holds_offsets(group: Group, tag: u16, field_type: u16) -> bool {
+    matches!((group.table(), tag), (Group::Photo, MAKER_NOTE) | (Group::Image, SUB_IFDS))
+        || field_type == IFD || (field_type == LONG && known_type(group, tag).is_none())
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+}
+
+//Values are formatted as exiv2 does: components separated by spaces, rationals as "numerator/denominator"
+fn format_value(value: &TiffValue) -> String {
+    match *value {
+        TiffValue::Ascii(ref text) => text.clone(),
+        TiffValue::Bytes(ref bytes) | TiffValue::Other(_, ref bytes) => join(bytes),
+        TiffValue::Unsigned(ref values) => join(values),
+        TiffValue::Signed(ref values) => join(values),
+        TiffValue::Rational(ref values) => values.iter()
+            .map(|ratio| format!("{}/{}", ratio.numer(), ratio.denom()))
+            .collect::<Vec<_>>()
+            .join(" "),
+        TiffValue::SignedRational(ref values) => values.iter()
+            .map(|ratio| format!("{}/{}", ratio.numer(), ratio.denom()))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn parse_numbers<T: FromStr>(text: &str) -> Option<Vec<T>> {
+    text.split_whitespace().map(|value| value.parse().ok()).collect()
+}
+
+//A rational without denominator is a whole number
+fn parse_ratios<T: FromStr + Copy>(text: &str, one: T) -> Option<Vec<(T, T)>> {
+    text.split_whitespace().map(|value| {
+        let mut parts = value.splitn(2, '/');
+        let numerator = parts.next()?.parse().ok()?;
+        let denominator = match parts.next() {
+            Some(denominator) => denominator.parse().ok()?,
+            None => one,
+        };
+
+        Some((numerator, denominator))
+    }).collect()
+}
+
+fn parse_value(field_type: u16, text: &str) -> Option<TiffValue> {
+    Some(match field_type {
+        ASCII => TiffValue::Ascii(text.to_string()),
+        BYTE | UNDEFINED => TiffValue::Bytes(parse_numbers(text)?),
+        SHORT => TiffValue::Unsigned(parse_numbers::<u16>(text)?.into_iter().map(u32::from).collect()),
+        LONG => TiffValue::Unsigned(parse_numbers(text)?),
+        SBYTE => TiffValue::Signed(parse_numbers::<i8>(text)?.into_iter().map(i32::from).collect()),
+        SSHORT => TiffValue::Signed(parse_numbers::<i16>(text)?.into_iter().map(i32::from).collect()),
+        SLONG => TiffValue::Signed(parse_numbers(text)?),
+        RATIONAL => TiffValue::Rational(parse_ratios(text, 1u32)?.into_iter()
+                                        .map(|(numerator, denominator)| Ratio::new_raw(numerator, denominator))
+                                        .collect()),
+        SRATIONAL => TiffValue::SignedRational(parse_ratios(text, 1i32)?.into_iter()
+                                               .map(|(numerator, denominator)| Ratio::new_raw(numerator, denominator))
+                                               .collect()),
+        _ => return None,
+    })
+}
+
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    //Encoded in the byte order of the metadata
+    value: Vec<u8>,
+}
+
+//Size of an IFD followed by its values which do not fit in the entries
+fn ifd_size(entries: &[Entry]) -> usize {
+    2 + entries.len() * 12 + 4 + entries.iter()
+        .filter(|entry| entry.value.len() > 4)
+        .map(|entry| entry.value.len() + entry.value.len() % 2)
+        .sum::<usize>()
+}
+
+fn invalid_exif() -> Rexiv2ImageError {
+    Rexiv2ImageError::DecoderError(ImageError::FormatError("Invalid EXIF data".to_string()))
+}
+
+//EXIF tags held in memory, written back by save
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExifMetadata {
+    //Field type and value of each tag
+    tags: BTreeMap<(Group, u16), (u16, TiffValue)>,
+    //Byte order of the data the tags come from, kept for the values of unknown types
+    big_endian: bool,
+    //JPEG data of the thumbnail IFD
+    thumbnail: Option<Vec<u8>>,
+}
+
+impl ExifMetadata {
+    pub fn new() -> ExifMetadata {
+        ExifMetadata::default()
+    }
+
+    //Parses an EXIF block, the TIFF structure found after the "Exif" header of JPEG files
+    pub fn from_exif_data(data: &[u8]) -> Result<ExifMetadata, Rexiv2ImageError> {
+        ExifMetadata::read(data, true)
+    }
+
+    //The IFD following the first one is a thumbnail in EXIF blocks but another page in TIFF files
+    fn read(data: &[u8], thumbnail: bool) -> Result<ExifMetadata, Rexiv2ImageError> {
+        let reader = TiffReader::new(data).ok_or_else(invalid_exif)?;
+        let mut metadata = ExifMetadata { big_endian: reader.big_endian, ..ExifMetadata::default() };
+        let first = reader.first_ifd().ok_or_else(invalid_exif)?;
+        let next = metadata.read_ifd(&reader, Group::Image, first).ok_or_else(invalid_exif)?;
+
+        if thumbnail && next != 0 && next != first && metadata.read_ifd(&reader, Group::Thumbnail, next).is_some() {
+            let offset = metadata.tags.remove(&(Group::Thumbnail, JPEG_INTERCHANGE_FORMAT));
+            let length = metadata.tags.remove(&(Group::Thumbnail, JPEG_INTERCHANGE_FORMAT_LENGTH));
+
+            if let (Some((_, TiffValue::Unsigned(offset))), Some((_, TiffValue::Unsigned(length)))) = (offset, length) {
+                if let (Some(&offset), Some(&length)) = (offset.first(), length.first()) {
+                    metadata.thumbnail = data.get(offset as usize..offset as usize + length as usize).map(<[u8]>::to_vec);
+                }
+            }
+        }
+        Ok(metadata)
+    }
+
+    //Stores the entries of the IFD in the group and follows the pointers to the other IFDs,
+    //returns the offset of the next IFD
+    fn read_ifd(&mut self, reader: &TiffReader, group: Group, offset: u32) -> Option<u32> {
+        let (entries, next) = reader.ifd(offset)?;
+
+        for (tag, field_type, value) in entries {
+            let pointed = match (group, tag) {
+                (Group::Image, EXIF_POINTER) => Some(Group::Photo),
+                (Group::Image, GPS_POINTER) => Some(Group::GpsInfo),
+                (Group::Photo, INTEROPERABILITY_POINTER) => Some(Group::Iop),
+                _ => None,
+            };
+
+            match (pointed, value) {
+                (Some(pointed), TiffValue::Unsigned(ref offsets)) if !offsets.is_empty() => {
+                    self.read_ifd(reader, pointed, offsets[0]);
+                },
+                (Some(_), _) => (),
+                (None, value) => {
+                    self.tags.insert((group, tag), (field_type, value));
+                },
+            }
+        }
+        Some(next)
+    }
+
+    pub fn thumbnail(&self) -> Option<&[u8]> {
+        self.thumbnail.as_deref()
+    }
+
+    //The thumbnail tags, like its compression, are left to the caller
+    pub fn set_thumbnail(&mut self, jpeg: Option<Vec<u8>>) {
+        self.thumbnail = jpeg;
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    //None when the value does not fit the field type
+    fn encode(&self, field_type: u16, value: &TiffValue) -> Option<Vec<u8>> {
+        Some(match *value {
+            TiffValue::Ascii(ref text) if field_type == ASCII => {
+                let mut bytes = text.as_bytes().to_vec();
+
+                bytes.push(0);
+                bytes
+            },
+            TiffValue::Bytes(ref bytes) if field_type == BYTE || field_type == UNDEFINED => bytes.clone(),
+            TiffValue::Unsigned(ref values) if field_type == SHORT => values.iter()
+                .map(|&value| u16::try_from(value).ok().map(|value| self.u16_bytes(value)))
+                .collect::<Option<Vec<_>>>()?
+                .concat(),
+            TiffValue::Unsigned(ref values) if field_type == LONG => values.iter().flat_map(|&value| self.u32_bytes(value)).collect(),
+            TiffValue::Signed(ref values) if field_type == SBYTE => values.iter()
+                .map(|&value| i8::try_from(value).ok().map(|value| value as u8))
+                .collect::<Option<_>>()?,
+            TiffValue::Signed(ref values) if field_type == SSHORT => values.iter()
+                .map(|&value| i16::try_from(value).ok().map(|value| self.u16_bytes(value as u16)))
+                .collect::<Option<Vec<_>>>()?
+                .concat(),
+            TiffValue::Signed(ref values) if field_type == SLONG => values.iter().flat_map(|&value| self.u32_bytes(value as u32)).collect(),
+            TiffValue::Rational(ref values) if field_type == RATIONAL => values.iter()
+                .flat_map(|ratio| IntoIterator::into_iter(self.u32_bytes(*ratio.numer())).chain(self.u32_bytes(*ratio.denom())))
+                .collect(),
+            TiffValue::SignedRational(ref values) if field_type == SRATIONAL => values.iter()
+                .flat_map(|ratio| IntoIterator::into_iter(self.u32_bytes(*ratio.numer() as u32))
+                          .chain(self.u32_bytes(*ratio.denom() as u32)))
+                .collect(),
+            TiffValue::Other(other, ref bytes) if other == field_type => bytes.clone(),
+            _ => return None,
+        })
+    }
+
+    fn entries(&self, group: Group) -> Vec<Entry> {
+        self.tags.iter()
+            .filter(|&(&(tag_group, _), _)| tag_group == group)
+            .filter_map(|(&(_, tag), &(field_type, ref value))| {
+                Some(Entry { tag, field_type, value: self.encode(field_type, value)? })
+            })
+            .collect()
+    }
+
+    fn pointer(&self, tag: u16, offset: usize) -> Entry {
+        Entry { tag, field_type: LONG, value: self.u32_bytes(offset as u32).to_vec() }
+    }
+
+    fn set_pointer(&self, entries: &mut [Entry], tag: u16, offset: usize) {
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.tag == tag) {
+            entry.value = self.u32_bytes(offset as u32).to_vec();
+        }
+    }
+
+    fn write_ifd(&self, output: &mut Vec<u8>, mut entries: Vec<Entry>, next: usize) {
+        let mut extra_offset = output.len() + 2 + entries.len() * 12 + 4;
+        let mut extra = Vec::new();
+
+        entries.sort_by_key(|entry| entry.tag);
+        output.extend_from_slice(&self.u16_bytes(entries.len() as u16));
+        for entry in &entries {
+            output.extend_from_slice(&self.u16_bytes(entry.tag));
+            output.extend_from_slice(&self.u16_bytes(entry.field_type));
+            output.extend_from_slice(&self.u32_bytes((entry.value.len() / pages::type_size(entry.field_type)) as u32));
+            if entry.value.len() <= 4 {
+                let mut inline = [0u8; 4];
+
+                inline[..entry.value.len()].copy_from_slice(&entry.value);
+                output.extend_from_slice(&inline);
+            } else {
+                output.extend_from_slice(&self.u32_bytes(extra_offset as u32));
+                extra.extend_from_slice(&entry.value);
+                //Values must start on a word boundary
+                if entry.value.len() % 2 == 1 {
+                    extra.push(0);
+                }
+                extra_offset += entry.value.len() + entry.value.len() % 2;
+            }
+        }
+        output.extend_from_slice(&self.u32_bytes(next as u32));
+        output.extend_from_slice(&extra);
+    }
+
+    //The EXIF block of the tags: the main IFD, then the EXIF, interoperability, GPS and thumbnail IFDs.
+    //UnsupportedTag when a tag holds offsets
+    pub fn exif_data(&self) -> Result<Vec<u8>, Rexiv2ImageError> {
+        if let Some(&(group, tag)) = self.tags.iter()
+            .find(|&(&(group, tag), &(field_type, _))| holds_offsets(group, tag, field_type))
+            .map(|(key, _)| key) {
+            return Err(Rexiv2ImageError::UnsupportedTag(key(group, tag)));
+        }
+        let mut image = self.entries(Group::Image);
+        let mut photo = self.entries(Group::Photo);
+        let iop = self.entries(Group::Iop);
+        let gps = self.entries(Group::GpsInfo);
+        let mut thumbnail = Vec::new();
+
+        //Pointers are added before the sizes are known, their values are fixed below
+        if !iop.is_empty() {
+            photo.push(self.pointer(INTEROPERABILITY_POINTER, 0));
+        }
+        if !photo.is_empty() {
+            image.push(self.pointer(EXIF_POINTER, 0));
+        }
+        if !gps.is_empty() {
+            image.push(self.pointer(GPS_POINTER, 0));
+        }
+        if let Some(ref jpeg) = self.thumbnail {
+            thumbnail = self.entries(Group::Thumbnail);
+            thumbnail.push(self.pointer(JPEG_INTERCHANGE_FORMAT, 0));
+            thumbnail.push(self.pointer(JPEG_INTERCHANGE_FORMAT_LENGTH, jpeg.len()));
+        }
+
+        let size = |entries: &[Entry]| if entries.is_empty() { 0 } else { ifd_size(entries) };
+        let photo_offset = 8 + ifd_size(&image);
+        let iop_offset = photo_offset + size(&photo);
+        let gps_offset = iop_offset + size(&iop);
+        let thumbnail_offset = gps_offset + size(&gps);
+        let jpeg_offset = thumbnail_offset + size(&thumbnail);
+
+        self.set_pointer(&mut image, EXIF_POINTER, photo_offset);
+        self.set_pointer(&mut image, GPS_POINTER, gps_offset);
+        self.set_pointer(&mut photo, INTEROPERABILITY_POINTER, iop_offset);
+        self.set_pointer(&mut thumbnail, JPEG_INTERCHANGE_FORMAT, jpeg_offset);
+
+        let mut output = if self.big_endian { b"MM\x00\x2a".to_vec() } else { b"II\x2a\x00".to_vec() };
+        output.extend_from_slice(&self.u32_bytes(8));
+        self.write_ifd(&mut output, image, if thumbnail.is_empty() { 0 } else { thumbnail_offset });
+        for entries in IntoIterator::into_iter([photo, iop, gps, thumbnail]) {
+            if !entries.is_empty() {
+                self.write_ifd(&mut output, entries, 0);
+            }
+        }
+        if let Some(ref jpeg) = self.thumbnail {
+            output.extend_from_slice(jpeg);
+        }
+        Ok(output)
+    }
+
+    //The image with its EXIF block replaced by the tags, or removed when there is none.
+    //Only JPEG and PNG images can be written
+    pub fn embed(&self, image: &[u8]) -> Result<Vec<u8>, Rexiv2ImageError> {
+        let exif = if self.tags.is_empty() && self.thumbnail.is_none() { None } else { Some(self.exif_data()?) };
+
+        match guess_format_from_bytes(image) {
+            Some(ImageFormat::JPEG) => embed_jpeg(image, exif.as_deref()),
+            Some(ImageFormat::PNG) => embed_png(image, exif.as_deref()),
+            Some(format) => Err(Rexiv2ImageError::UnsupportedFormat(format)),
+            None => Err(Rexiv2ImageError::UnknownFormat),
+        }
+    }
+}
+
+//Marker and bytes of a JPEG segment
+type Segment<'a> = (u8, &'a [u8]);
+
+//Segments before the image data, and the position of the image data
+fn jpeg_segments(data: &[u8]) -> Result<(Vec<Segment<'_>>, usize), Rexiv2ImageError> {
+    let mut segments = Vec::new();
+    let mut position = 2;
+
+    loop {
+        //Any number of fill bytes may come before a marker
+        while data.get(position..position + 2) == Some(&[0xff, 0xff]) {
+            position += 1;
+        }
+        match data.get(position..position + 2) {
+            Some(&[0xff, marker]) if marker == 0xda || marker == 0xd9 => return Ok((segments, position)),
+            Some(&[0xff, marker]) => {
+                let length = data.get(position + 2..position + 4).ok_or_else(invalid_exif)?;
+                let end = position + 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+
+                segments.push((marker, data.get(position..end).ok_or_else(invalid_exif)?));
+                position = end;
+            },
+            _ => return Err(invalid_exif()),
+        }
+    }
+}
+
+fn is_exif_segment(marker: u8, segment: &[u8]) -> bool {
+    marker == 0xe1 && segment.get(4..4 + EXIF_HEADER.len()) == Some(EXIF_HEADER)
+}
+
+fn jpeg_exif(data: &[u8]) -> Result<Option<&[u8]>, Rexiv2ImageError> {
+    Ok(jpeg_segments(data)?.0.into_iter()
+       .find(|&(marker, segment)| is_exif_segment(marker, segment))
+       .map(|(_, segment)| &segment[4 + EXIF_HEADER.len()..]))
+}
+
+//The new APP1 segment goes after the JFIF APP0 one, which has to stay first
+fn embed_jpeg(data: &[u8], exif: Option<&[u8]>) -> Result<Vec<u8>, Rexiv2ImageError> {
+    let (segments, end) = jpeg_segments(data)?;
+    let mut pending = match exif {
+        Some(exif) => {
+            let length = u16::try_from(2 + EXIF_HEADER.len() + exif.len()).map_err(|_| {
+                Rexiv2ImageError::DecoderError(ImageError::FormatError("EXIF data too large for a JPEG segment".to_string()))
+            })?;
+            let mut segment = vec![0xff, 0xe1];
+
+            segment.extend_from_slice(&length.to_be_bytes());
+            segment.extend_from_slice(EXIF_HEADER);
+            segment.extend_from_slice(exif);
+            Some(segment)
+        },
+        None => None,
+    };
+    let mut output = data[..2].to_vec();
+
+    for (marker, segment) in segments {
+        if marker != 0xe0 {
+            if let Some(exif) = pending.take() {
+                output.extend_from_slice(&exif);
+            }
+        }
+        if !is_exif_segment(marker, segment) {
+            output.extend_from_slice(segment);
+        }
+    }
+    if let Some(exif) = pending.take() {
+        output.extend_from_slice(&exif);
+    }
+    output.extend_from_slice(&data[end..]);
+    Ok(output)
+}
+
+//Type, data and whole bytes of a PNG chunk
+type Chunk<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+fn png_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, Rexiv2ImageError> {
+    let mut chunks = Vec::new();
+    let mut position = PNG_SIGNATURE.len();
+
+    while position < data.len() {
+        let header = data.get(position..position + 8).ok_or_else(invalid_exif)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = position + 12 + length;
+        let chunk = data.get(position..end).ok_or_else(invalid_exif)?;
+
+        chunks.push((&chunk[4..8], &chunk[8..8 + length], chunk));
+        position = end;
+    }
+    Ok(chunks)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+fn png_exif(data: &[u8]) -> Result<Option<&[u8]>, Rexiv2ImageError> {
+    Ok(png_chunks(data)?.into_iter()
+       .find(|&(chunk_type, _, _)| chunk_type == b"eXIf")
+       .map(|(_, chunk_data, _)| chunk_data))
+}
+
+//The eXIf chunk has to come before the image data
+fn embed_png(data: &[u8], exif: Option<&[u8]>) -> Result<Vec<u8>, Rexiv2ImageError> {
+    let mut pending = exif.map(|exif| {
+        let mut chunk = (exif.len() as u32).to_be_bytes().to_vec();
+
+        chunk.extend_from_slice(b"eXIf");
+        chunk.extend_from_slice(exif);
+        let crc = crc32(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        chunk
+    });
+    let mut output = PNG_SIGNATURE.to_vec();
+
+    for (chunk_type, _, chunk) in png_chunks(data)? {
+        if chunk_type == b"IDAT" || chunk_type == b"IEND" {
+            if let Some(exif) = pending.take() {
+                output.extend_from_slice(&exif);
+            }
+        }
+        if chunk_type != b"eXIf" {
+            output.extend_from_slice(chunk);
+        }
+    }
+    Ok(output)
+}
+
+impl MetadataBackend for ExifMetadata {
+    //Images without EXIF data give empty metadata
+    fn from_buffer(data: &[u8]) -> Result<ExifMetadata, Rexiv2ImageError> {
+        let exif = match guess_format_from_bytes(data) {
+            Some(ImageFormat::TIFF) => return ExifMetadata::read(data, false),
+            Some(ImageFormat::JPEG) => jpeg_exif(data)?,
+            Some(ImageFormat::PNG) => png_exif(data)?,
+            _ => None,
+        };
+
+        exif.map_or_else(|| Ok(ExifMetadata::new()), ExifMetadata::from_exif_data)
+    }
+
+    fn read_tag(&self, tag: &str) -> Result<String, Rexiv2ImageError> {
+        let key = parse_key(tag).ok_or_else(|| Rexiv2ImageError::UnsupportedTag(tag.to_string()))?;
+
+        self.tags.get(&key)
+            .map(|(_, value)| format_value(value))
+            .ok_or_else(|| Rexiv2ImageError::TagNotFound(tag.to_string()))
+    }
+
+    //The value is parsed according to the type of the tag, unknown tags can only be written if they are present
+    fn write_tag(&mut self, tag: &str, value: &str) -> Result<(), Rexiv2ImageError> {
+        let unsupported = || Rexiv2ImageError::UnsupportedTag(tag.to_string());
+        let key = parse_key(tag).filter(|&(group, id)| !is_structural(group, id)).ok_or_else(unsupported)?;
+        let field_type = known_type(key.0, key.1)
+            .or_else(|| self.tags.get(&key).map(|&(field_type, _)| field_type))
+            .ok_or_else(unsupported)?;
+        if holds_offsets(key.0, key.1, field_type) {
+            return Err(unsupported());
+        }
+        let parsed = parse_value(field_type, value)
+            .filter(|parsed| self.encode(field_type, parsed).is_some())
+            .ok_or_else(|| Rexiv2ImageError::InvalidTagValue(tag.to_string(), value.to_string()))?;
+
+        self.tags.insert(key, (field_type, parsed));
+        Ok(())
+    }
+
+    fn remove_tag(&mut self, tag: &str) -> bool {
+        parse_key(tag).and_then(|key| self.tags.remove(&key)).is_some()
+    }
+
+    fn list_tags(&self) -> Result<Vec<String>, Rexiv2ImageError> {
+        Ok(self.tags.keys().map(|&(group, tag)| key(group, tag)).collect())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        let image = fs::read(path)?;
+
+        Ok(fs::write(path, self.embed(&image)?)?)
+    }
+
+    //Only EXIF tags, and not the offsets rewritten by save
+    fn supports_tag(&self, tag: &str) -> bool {
+        parse_key(tag).is_some_and(|(group, id)| !is_structural(group, id))
+    }
+
+    fn erase_thumbnail<R: Read + Seek>(decoder: &mut DecoderWithMetadata<R, ExifMetadata>)
+                                       -> Result<(), Rexiv2ImageError> {
+        decoder.metadata.tags.retain(|&(group, _), _| group != Group::Thumbnail);
+        decoder.metadata.set_thumbnail(None);
+        Ok(())
+    }
+}
+
+//Without exiv2 the constructors of DecoderWithMetadata parse the metadata with this backend
+
+#[cfg(not(feature = "exiv2"))]
+impl DecoderWithMetadata<File> {
+    pub fn new(path: &Path, format: ImageFormat) -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
+        File::open(path)
+            .map_err(Rexiv2ImageError::from)
+            .and_then(|file| DecoderWithMetadata::from_reader_with_backend(file, format))
+            .map_err(|err| err.with_path(path))
+    }
+
+    pub fn open(path: &Path) -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
+        DecoderWithMetadata::new(path, guess_format(path)?)
+    }
+}
+
+#[cfg(not(feature = "exiv2"))]
+impl DecoderWithMetadata<Cursor<Vec<u8>>> {
+    pub fn from_buffer(data: Vec<u8>, format: ImageFormat)
+                       -> Result<DecoderWithMetadata<Cursor<Vec<u8>>>, Rexiv2ImageError> {
+        let metadata = ExifMetadata::from_buffer(&data)?;
+
+        DecoderWithMetadata::with_backend(metadata, Cursor::new(data), format)
+    }
+
+    pub fn open_buffer(data: Vec<u8>) -> Result<DecoderWithMetadata<Cursor<Vec<u8>>>, Rexiv2ImageError> {
+        let format = guess_format_from_bytes(&data)
+            .ok_or(Rexiv2ImageError::UnknownFormat)?;
+
+        DecoderWithMetadata::from_buffer(data, format)
+    }
+}
+
+#[cfg(not(feature = "exiv2"))]
+impl<'a> DecoderWithMetadata<Cursor<&'a [u8]>> {
+    pub fn from_slice(data: &'a [u8], format: ImageFormat)
+                      -> Result<DecoderWithMetadata<Cursor<&'a [u8]>>, Rexiv2ImageError> {
+        DecoderWithMetadata::with_backend(ExifMetadata::from_buffer(data)?, Cursor::new(data), format)
+    }
+}
+
+#[cfg(not(feature = "exiv2"))]
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    pub fn from_reader(reader: R, format: ImageFormat) -> Result<DecoderWithMetadata<R>, Rexiv2ImageError> {
+        DecoderWithMetadata::from_reader_with_backend(reader, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, ImageDecoder, ImageFormat};
+    use image::jpeg::JPEGEncoder;
+    use image::png::PNGEncoder;
+    use std::io::Cursor;
+    use super::super::pages::TiffValue;
+    use super::{ExifMetadata, Group, MAKER_NOTE, UNDEFINED};
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::super::backend::MetadataBackend;
+
+    const THUMBNAIL: &[u8] = b"\xff\xd8\xff\xd9";
+
+    fn metadata(thumbnail: bool) -> ExifMetadata {
+        let mut metadata = ExifMetadata::new();
+
+        for &(tag, value) in &[("Exif.Image.Make", "Camera"), ("Exif.Image.Orientation", "6"),
+                               ("Exif.Photo.FNumber", "28/10"), ("Exif.Photo.ExposureBiasValue", "-1/3"),
+                               ("Exif.Iop.InteroperabilityIndex", "R98"), ("Exif.GPSInfo.GPSLatitudeRef", "N"),
+                               ("Exif.GPSInfo.GPSLatitude", "48/1 51/1 2400/100"),
+                               ("Exif.Thumbnail.Compression", "6")] {
+            metadata.write_tag(tag, value).unwrap();
+        }
+        if thumbnail {
+            metadata.set_thumbnail(Some(THUMBNAIL.to_vec()));
+        }
+        metadata
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let mut data = Vec::new();
+
+        JPEGEncoder::new(&mut data).encode(&[128; 4], 2, 2, ColorType::Gray(8)).unwrap();
+        data
+    }
+
+    fn png() -> Vec<u8> {
+        let mut data = Vec::new();
+
+        PNGEncoder::new(&mut data).encode(&[128; 4], 2, 2, ColorType::Gray(8)).unwrap();
+        data
+    }
+
+    fn count(data: &[u8], pattern: &[u8]) -> usize {
+        data.windows(pattern.len()).filter(|window| *window == pattern).count()
+    }
+
+    //The thumbnail IFD is another page in TIFF files, so only the tags of the first one are read
+    #[test]
+    fn tiff_round_trip() {
+        let tiff = metadata(true).exif_data().unwrap();
+        let mut expected = metadata(false);
+
+        expected.remove_tag("Exif.Thumbnail.Compression");
+        assert_eq!(ExifMetadata::from_buffer(&tiff).unwrap(), expected);
+        assert_eq!(ExifMetadata::from_exif_data(&metadata(true).exif_data().unwrap()).unwrap(), metadata(true));
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let jpeg = metadata(true).embed(&jpeg()).unwrap();
+        let parsed = ExifMetadata::from_buffer(&jpeg).unwrap();
+
+        assert_eq!(parsed, metadata(true));
+        assert_eq!(parsed.thumbnail(), Some(THUMBNAIL));
+        assert_eq!(count(&parsed.embed(&jpeg).unwrap(), b"Exif\0\0"), 1);
+
+        let mut decoder = DecoderWithMetadata::with_backend(parsed, Cursor::new(jpeg), ImageFormat::JPEG).unwrap();
+        assert_eq!(decoder.make().unwrap(), Some("Camera".to_string()));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert!(decoder.read_image().is_ok());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let png = metadata(true).embed(&png()).unwrap();
+        let parsed = ExifMetadata::from_buffer(&png).unwrap();
+
+        assert_eq!(parsed, metadata(true));
+        assert!(png.windows(4).position(|window| window == b"eXIf") < png.windows(4).position(|window| window == b"IDAT"));
+        assert_eq!(count(&parsed.embed(&png).unwrap(), b"eXIf"), 1);
+
+        let mut decoder = DecoderWithMetadata::with_backend(parsed, Cursor::new(png), ImageFormat::PNG).unwrap();
+        assert_eq!(decoder.make().unwrap(), Some("Camera".to_string()));
+        assert!(decoder.read_image().is_ok());
+    }
+
+    #[test]
+    fn jpeg_fill_bytes() {
+        let plain = jpeg();
+        let mut filled = plain[..2].to_vec();
+
+        filled.extend_from_slice(&[0xff, 0xff]);
+        filled.extend_from_slice(&plain[2..]);
+        assert_eq!(ExifMetadata::from_buffer(&metadata(true).embed(&filled).unwrap()).unwrap(), metadata(true));
+    }
+
+    #[test]
+    fn maker_note_is_refused() {
+        let mut metadata = metadata(false);
+
+        metadata.tags.insert((Group::Photo, MAKER_NOTE), (UNDEFINED, TiffValue::Bytes(vec![1, 2, 3, 4, 5])));
+        match metadata.embed(&jpeg()) {
+            Err(Rexiv2ImageError::UnsupportedTag(ref tag)) if tag == "Exif.Photo.MakerNote" => (),
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+        assert!(matches!(metadata.write_tag("Exif.Photo.MakerNote", "1 2"), Err(Rexiv2ImageError::UnsupportedTag(_))));
+        assert!(metadata.remove_tag("Exif.Photo.MakerNote"));
+        assert!(metadata.embed(&jpeg()).is_ok());
+    }
+}
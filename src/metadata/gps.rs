@@ -0,0 +1,254 @@
+use std::io::{Read, Seek};
+use std::result::Result;
+use num_rational::Ratio;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::tags::parse_ratio;
+
+const LATITUDE: &str = "Exif.GPSInfo.GPSLatitude";
+const LATITUDE_REF: &str = "Exif.GPSInfo.GPSLatitudeRef";
+const LONGITUDE: &str = "Exif.GPSInfo.GPSLongitude";
+const LONGITUDE_REF: &str = "Exif.GPSInfo.GPSLongitudeRef";
+const ALTITUDE: &str = "Exif.GPSInfo.GPSAltitude";
+const ALTITUDE_REF: &str = "Exif.GPSInfo.GPSAltitudeRef";
+const VERSION_ID: &str = "Exif.GPSInfo.GPSVersionID";
+
+//Tags of a position, the other GPS tags like the time or the direction are left alone when it is replaced
+const POSITION_TAGS: [&str; 10] = [
+    LATITUDE,
+    LATITUDE_REF,
+    LONGITUDE,
+    LONGITUDE_REF,
+    ALTITUDE,
+    ALTITUDE_REF,
+    "Xmp.exif.GPSLatitude",
+    "Xmp.exif.GPSLongitude",
+    "Xmp.exif.GPSAltitude",
+    "Xmp.exif.GPSAltitudeRef",
+];
+
+//Precision of the seconds written from decimal degrees, about 3mm at the equator
+const SECONDS_DENOMINATOR: i32 = 10000;
+
+//Unsigned angle as stored by EXIF, the hemisphere is kept apart in the reference tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dms {
+    pub degrees: Ratio<i32>,
+    pub minutes: Ratio<i32>,
+    pub seconds: Ratio<i32>,
+}
+
+impl Dms {
+    pub fn new(degrees: i32, minutes: i32, seconds: Ratio<i32>) -> Dms {
+        Dms {
+            degrees: Ratio::from_integer(degrees),
+            minutes: Ratio::from_integer(minutes),
+            seconds,
+        }
+    }
+
+    //The sign is dropped
+    pub fn from_decimal(decimal: f64) -> Dms {
+        let total = (decimal.abs() * 3600.0 * SECONDS_DENOMINATOR as f64).round() as i64;
+        let per_degree = 3600 * SECONDS_DENOMINATOR as i64;
+        let per_minute = 60 * SECONDS_DENOMINATOR as i64;
+        let degrees = total / per_degree;
+        let minutes = (total % per_degree) / per_minute;
+        let seconds = total % per_minute;
+
+        Dms::new(degrees as i32, minutes as i32, Ratio::new(seconds as i32, SECONDS_DENOMINATOR))
+    }
+
+    pub fn to_decimal(self) -> f64 {
+        let as_f64 = |ratio: Ratio<i32>| *ratio.numer() as f64 / *ratio.denom() as f64;
+
+        as_f64(self.degrees) + as_f64(self.minutes) / 60.0 + as_f64(self.seconds) / 3600.0
+    }
+
+    fn to_tag_string(self) -> String {
+        format!("{}/{} {}/{} {}/{}",
+                self.degrees.numer(), self.degrees.denom(),
+                self.minutes.numer(), self.minutes.denom(),
+                self.seconds.numer(), self.seconds.denom())
+    }
+
+    fn parse(value: &str) -> Option<Dms> {
+        let parts: Vec<Ratio<i32>> = value.split_whitespace().map(parse_ratio).collect::<Option<_>>()?;
+
+        match parts.len() {
+            3 => Some(Dms { degrees: parts[0], minutes: parts[1], seconds: parts[2] }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GpsPosition {
+    pub latitude: Dms,
+    pub south: bool,
+    pub longitude: Dms,
+    pub west: bool,
+    //In meters
+    pub altitude: Option<Ratio<i32>>,
+    pub below_sea_level: bool,
+}
+
+impl GpsPosition {
+    //Negative latitudes are south, negative longitudes are west
+    pub fn from_decimal(latitude: f64, longitude: f64) -> GpsPosition {
+        GpsPosition {
+            latitude: Dms::from_decimal(latitude),
+            south: latitude < 0.0,
+            longitude: Dms::from_decimal(longitude),
+            west: longitude < 0.0,
+            altitude: None,
+            below_sea_level: false,
+        }
+    }
+
+    //Centimeter precision, negative altitudes are below sea level
+    pub fn with_altitude(mut self, altitude: f64) -> GpsPosition {
+        self.altitude = Some(Ratio::new((altitude.abs() * 100.0).round() as i32, 100));
+        self.below_sea_level = altitude < 0.0;
+        self
+    }
+
+    pub fn latitude_decimal(&self) -> f64 {
+        if self.south { -self.latitude.to_decimal() } else { self.latitude.to_decimal() }
+    }
+
+    pub fn longitude_decimal(&self) -> f64 {
+        if self.west { -self.longitude.to_decimal() } else { self.longitude.to_decimal() }
+    }
+
+    pub fn altitude_meters(&self) -> Option<f64> {
+        self.altitude.map(|altitude| {
+            let meters = *altitude.numer() as f64 / *altitude.denom() as f64;
+
+            if self.below_sea_level { -meters } else { meters }
+        })
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    fn get_gps_coordinate(&self, tag: &str) -> Result<Option<Dms>, Rexiv2ImageError> {
+        match self.read_optional_tag(tag)? {
+            None => Ok(None),
+            Some(value) => Dms::parse(&value)
+                .map(Some)
+                .ok_or_else(|| Rexiv2ImageError::InvalidTagValue(tag.to_string(), value)),
+        }
+    }
+
+    //A position needs both coordinates, a missing reference is read as north or east
+    pub fn gps_position(&self) -> Result<Option<GpsPosition>, Rexiv2ImageError> {
+        let (latitude, longitude) = match (self.get_gps_coordinate(LATITUDE)?, self.get_gps_coordinate(LONGITUDE)?) {
+            (Some(latitude), Some(longitude)) => (latitude, longitude),
+            _ => return Ok(None),
+        };
+        let altitude = match self.read_optional_tag(ALTITUDE)? {
+            None => None,
+            Some(value) => Some(parse_ratio(&value)
+                .ok_or_else(|| Rexiv2ImageError::InvalidTagValue(ALTITUDE.to_string(), value))?),
+        };
+        let south = self.read_optional_tag(LATITUDE_REF)?.is_some_and(|value| value.trim() == "S");
+        let west = self.read_optional_tag(LONGITUDE_REF)?.is_some_and(|value| value.trim() == "W");
+        let below_sea_level = self.read_optional_tag(ALTITUDE_REF)?.is_some_and(|value| value.trim() == "1");
+
+        Ok(Some(GpsPosition { latitude, south, longitude, west, altitude, below_sea_level }))
+    }
+
+    //Replaces every position tag, so no stale altitude is left behind a new position
+    pub fn set_gps_position(&mut self, position: &GpsPosition) -> Result<(), Rexiv2ImageError> {
+        self.clear_gps_position();
+        self.metadata.write_tag(VERSION_ID, "2 2 0 0")?;
+        self.metadata.write_tag(LATITUDE, &position.latitude.to_tag_string())?;
+        self.metadata.write_tag(LATITUDE_REF, if position.south { "S" } else { "N" })?;
+        self.metadata.write_tag(LONGITUDE, &position.longitude.to_tag_string())?;
+        self.metadata.write_tag(LONGITUDE_REF, if position.west { "W" } else { "E" })?;
+        if let Some(altitude) = position.altitude {
+            self.metadata.write_tag(ALTITUDE, &format!("{}/{}", altitude.numer(), altitude.denom()))?;
+            self.metadata.write_tag(ALTITUDE_REF, if position.below_sea_level { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+
+    //Also removes the XMP copies of the position
+    pub fn clear_gps_position(&mut self) {
+        for tag in POSITION_TAGS.iter() {
+            self.metadata.remove_tag(tag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use num_rational::Ratio;
+    use super::{Dms, GpsPosition};
+    use super::super::DecoderWithMetadata;
+    use super::super::memory::MemoryMetadata;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(2, 2, vec![0, 80, 160, 240]).unwrap())
+    }
+
+    #[test]
+    fn unknown_seconds() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.GPSInfo.GPSLatitude", "48/1 51/1 0/0")
+            .with_tag("Exif.GPSInfo.GPSLongitude", "2/1 21/1 0/0");
+        let decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+        let position = decoder.gps_position().unwrap().unwrap();
+
+        assert_eq!(position.latitude, Dms::new(48, 51, Ratio::from_integer(0)));
+        assert_eq!(position.longitude, Dms::new(2, 21, Ratio::from_integer(0)));
+    }
+
+    #[test]
+    fn other_gps_tags_are_kept() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.GPSInfo.GPSLatitude", "1/1 0/1 0/1")
+            .with_tag("Exif.GPSInfo.GPSAltitude", "100/1")
+            .with_tag("Exif.GPSInfo.GPSTimeStamp", "12/1 30/1 0/1")
+            .with_tag("Exif.GPSInfo.GPSDateStamp", "2020:01:01")
+            .with_tag("Exif.GPSInfo.GPSImgDirection", "90/1");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.set_gps_position(&GpsPosition::from_decimal(48.5, -2.25)).unwrap();
+        assert!(!decoder.metadata.tags().contains_key("Exif.GPSInfo.GPSAltitude"));
+        for tag in &["Exif.GPSInfo.GPSTimeStamp", "Exif.GPSInfo.GPSDateStamp", "Exif.GPSInfo.GPSImgDirection"] {
+            assert!(decoder.metadata.tags().contains_key(*tag), "{} was removed", tag);
+        }
+        decoder.clear_gps_position();
+        assert!(decoder.gps_position().unwrap().is_none());
+        assert!(decoder.metadata.tags().contains_key("Exif.GPSInfo.GPSTimeStamp"));
+    }
+
+    #[cfg(feature = "exif")]
+    #[test]
+    fn saved_position() {
+        use image::{ColorType, ImageFormat};
+        use image::png::PNGEncoder;
+        use std::env;
+        use std::fs::{self, File};
+        use std::process;
+        use super::super::exif::ExifMetadata;
+
+        let path = env::temp_dir().join(format!("rexiv2image-gps-{}.png", process::id()));
+        let position = GpsPosition::from_decimal(48.858_222, -2.294_5).with_altitude(-12.5);
+        let mut png = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&image().raw_pixels(), 2, 2, ColorType::Gray(8)).unwrap();
+        fs::write(&path, &png).unwrap();
+        let mut decoder = DecoderWithMetadata::from_image(&image(), ExifMetadata::new()).unwrap();
+        decoder.set_gps_position(&position).unwrap();
+        decoder.save_metadata(&path).unwrap();
+
+        let reopened = DecoderWithMetadata::<File, ExifMetadata>::from_reader_with_backend(File::open(&path).unwrap(),
+                                                                                             ImageFormat::PNG);
+        let saved = reopened.and_then(|decoder| decoder.gps_position());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.unwrap(), Some(position));
+    }
+}
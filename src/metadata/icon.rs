@@ -0,0 +1,349 @@
+use image::{self, DynamicImage, ImageError, ImageFormat, GenericImage};
+use image::png::PNGEncoder;
+#[cfg(feature = "exiv2")]
+use rexiv2::Metadata;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::result::Result;
+#[cfg(feature = "exiv2")]
+use encoder::EncoderWithMetadata;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+const ICONDIR_SIZE: usize = 6;
+const DIRENTRY_SIZE: usize = 16;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+//Entry of the ICO directory, the image is either a PNG file or a BMP without file header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IconEntry {
+    pub index: usize,
+    pub width: u32,
+    pub height: u32,
+    //Size of the palette, 0 when there is none
+    pub color_count: u8,
+    pub bits_per_pixel: u16,
+    //Position and size in bytes of the image in the file
+    pub offset: u32,
+    pub size: u32,
+    pub png: bool,
+}
+
+impl IconEntry {
+    fn data<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        data.get(self.offset as usize..(self.offset as usize).checked_add(self.size as usize)?)
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+//A width or height of 0 in the directory stands for 256
+fn dimension(byte: u8) -> u32 {
+    if byte == 0 { 256 } else { byte as u32 }
+}
+
+pub(crate) fn read_icon_entries(data: &[u8]) -> Option<Vec<IconEntry>> {
+    if u16_at(data, 0)? != 0 || u16_at(data, 2)? != 1 {
+        return None;
+    }
+
+    (0..u16_at(data, 4)? as usize).map(|index| {
+        let entry = ICONDIR_SIZE + index * DIRENTRY_SIZE;
+        let header = data.get(entry..entry + DIRENTRY_SIZE)?;
+        let offset = u32_at(header, 12)?;
+
+        Some(IconEntry {
+            index,
+            width: dimension(header[0]),
+            height: dimension(header[1]),
+            color_count: header[2],
+            bits_per_pixel: u16_at(header, 6)?,
+            offset,
+            size: u32_at(header, 8)?,
+            png: data.get(offset as usize..).is_some_and(|image| image.starts_with(PNG_SIGNATURE)),
+        })
+    }).collect()
+}
+
+//Image of an icon to write, a PNG is stored as is so that its metadata is kept
+#[derive(Clone)]
+pub enum IconImage {
+    Image(DynamicImage),
+    Png(Vec<u8>),
+}
+
+impl IconImage {
+    //The metadata is embedded in the PNG of the entry
+    #[cfg(feature = "exiv2")]
+    pub fn with_metadata(image: &DynamicImage, metadata: &Metadata) -> Result<IconImage, Rexiv2ImageError> {
+        let png = EncoderWithMetadata::new(metadata, ImageFormat::PNG)?.encode_image_to_buffer(image)?;
+
+        Ok(IconImage::Png(png))
+    }
+
+    fn to_png(&self) -> Result<Vec<u8>, Rexiv2ImageError> {
+        match *self {
+            IconImage::Png(ref png) => Ok(png.clone()),
+            IconImage::Image(ref image) => {
+                let (width, height) = image.dimensions();
+                let mut png = Vec::new();
+
+                PNGEncoder::new(&mut png).encode(&image.raw_pixels(), width, height, image.color())?;
+                Ok(png)
+            },
+        }
+    }
+}
+
+//Width, height and bits per pixel from the IHDR chunk
+fn png_header(png: &[u8]) -> Result<(u32, u32, u16), Rexiv2ImageError> {
+    let invalid = || Rexiv2ImageError::DecoderError(ImageError::FormatError("Icon image is not a PNG".to_string()));
+
+    if !png.starts_with(PNG_SIGNATURE) || png.get(12..16) != Some(b"IHDR") {
+        return Err(invalid());
+    }
+    let ihdr = png.get(16..26).ok_or_else(invalid)?;
+    let channels = match ihdr[9] {
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => 1,
+    };
+
+    Ok((u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]),
+        u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]),
+        ihdr[8] as u16 * channels))
+}
+
+//Every entry is stored as a PNG, in the given order
+pub fn encode_icon<W: Write>(images: &[IconImage], output: &mut W) -> Result<(), Rexiv2ImageError> {
+    let pngs = images.iter().map(IconImage::to_png).collect::<Result<Vec<_>, _>>()?;
+    let mut offset = ICONDIR_SIZE + pngs.len() * DIRENTRY_SIZE;
+    let mut directory = Vec::with_capacity(offset);
+
+    directory.extend_from_slice(&[0, 0, 1, 0]);
+    directory.extend_from_slice(&(pngs.len() as u16).to_le_bytes());
+    for png in &pngs {
+        let (width, height, bits_per_pixel) = png_header(png)?;
+
+        if width == 0 || height == 0 || width > 256 || height > 256 {
+            return Err(Rexiv2ImageError::DecoderError(ImageError::DimensionError));
+        }
+        directory.extend_from_slice(&[width as u8, height as u8, 0, 0]);
+        directory.extend_from_slice(&1u16.to_le_bytes());
+        directory.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        directory.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        directory.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += png.len();
+    }
+
+    output.write_all(&directory)?;
+    for png in &pngs {
+        output.write_all(png)?;
+    }
+    Ok(())
+}
+
+pub fn save_icon(images: &[IconImage], path: &Path) -> Result<(), Rexiv2ImageError> {
+    let file = File::create(path).map_err(|error| Rexiv2ImageError::from(error).with_path(path))?;
+    let mut output = BufWriter::new(file);
+
+    encode_icon(images, &mut output)
+        .and_then(|_| output.flush().map_err(Rexiv2ImageError::from))
+        .map_err(|error| error.with_path(path))
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    fn icon_data(&self) -> Result<&[u8], Rexiv2ImageError> {
+        match self.content {
+            Some(ref data) if self.format == ImageFormat::ICO => Ok(data),
+            _ => Err(Rexiv2ImageError::UnsupportedFormat(self.format)),
+        }
+    }
+
+    pub fn icon_entries(&self) -> Result<Vec<IconEntry>, Rexiv2ImageError> {
+        read_icon_entries(self.icon_data()?)
+            .ok_or_else(|| Rexiv2ImageError::DecoderError(ImageError::FormatError("Invalid ICO directory".to_string())))
+    }
+
+    //Encoded bytes of the entry, a PNG file or a BMP without file header
+    pub fn icon_entry_data(&self, index: usize) -> Result<&[u8], Rexiv2ImageError> {
+        let entry = self.icon_entries()?.into_iter().nth(index).ok_or(Rexiv2ImageError::IconNotFound(index))?;
+
+        entry.data(self.icon_data()?)
+            .ok_or_else(|| Rexiv2ImageError::DecoderError(ImageError::FormatError("ICO entry out of bounds".to_string())))
+    }
+
+    pub fn read_icon_entry(&self, index: usize) -> Result<DynamicImage, Rexiv2ImageError> {
+        let data = self.icon_entry_data(index)?;
+
+        if data.starts_with(PNG_SIGNATURE) {
+            return Ok(image::load_from_memory_with_format(data, ImageFormat::PNG)?);
+        }
+        //The ICO decoder of the image crate picks the largest entry, so it is given a file with only this one
+        let mut icon = self.icon_data()?[..4].to_vec();
+        let entry = ICONDIR_SIZE + index * DIRENTRY_SIZE;
+
+        icon.extend_from_slice(&1u16.to_le_bytes());
+        icon.extend_from_slice(&self.icon_data()?[entry..entry + 12]);
+        icon.extend_from_slice(&((ICONDIR_SIZE + DIRENTRY_SIZE) as u32).to_le_bytes());
+        icon.extend_from_slice(data);
+        Ok(image::load_from_memory_with_format(&icon, ImageFormat::ICO)?)
+    }
+
+    //PNG entries are kept as they are, with their metadata, to be written again by encode_icon
+    pub fn icon_image(&self, index: usize) -> Result<IconImage, Rexiv2ImageError> {
+        let data = self.icon_entry_data(index)?;
+
+        if data.starts_with(PNG_SIGNATURE) {
+            Ok(IconImage::Png(data.to_vec()))
+        } else {
+            Ok(IconImage::Image(self.read_icon_entry(index)?))
+        }
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    //Only PNG entries carry metadata
+    pub fn icon_entry_metadata(&self, index: usize) -> Result<Metadata, Rexiv2ImageError> {
+        let data = self.icon_entry_data(index)?;
+
+        if !data.starts_with(PNG_SIGNATURE) {
+            return Err(Rexiv2ImageError::UnsupportedFormat(ImageFormat::BMP));
+        }
+        Ok(Metadata::new_from_buffer(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, ImageFormat, Rgba, RgbaImage};
+    use image::png::PNGEncoder;
+    use std::io::Cursor;
+    use super::{DIRENTRY_SIZE, ICONDIR_SIZE, IconImage, encode_icon, read_icon_entries};
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::super::memory::MemoryMetadata;
+
+    fn images() -> Vec<DynamicImage> {
+        vec![
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 0, 255]))),
+            DynamicImage::ImageLuma8(GrayImage::from_fn(256, 256, |x, y| ::image::Luma([(x ^ y) as u8]))),
+            DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![10, 20]).unwrap()),
+        ]
+    }
+
+    fn icon() -> Vec<u8> {
+        let images = images();
+        let mut png = Vec::new();
+        let mut icon = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&images[2].raw_pixels(), 2, 1, images[2].color()).unwrap();
+        encode_icon(&[IconImage::Image(images[0].clone()), IconImage::Image(images[1].clone()), IconImage::Png(png)],
+                    &mut icon).unwrap();
+        icon
+    }
+
+    fn decoder(data: Vec<u8>) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_reader_with_backend(Cursor::new(data), ImageFormat::ICO).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let decoder = decoder(icon());
+        let entries = decoder.icon_entries().unwrap();
+
+        assert_eq!(entries.iter().map(|entry| (entry.width, entry.height, entry.bits_per_pixel)).collect::<Vec<_>>(),
+                   vec![(16, 16, 32), (256, 256, 8), (2, 1, 8)]);
+        assert!(entries.iter().all(|entry| entry.png && entry.color_count == 0));
+        assert_eq!(entries[0].offset as usize, ICONDIR_SIZE + 3 * DIRENTRY_SIZE);
+        assert_eq!(entries[1].offset, entries[0].offset + entries[0].size);
+        for (index, image) in images().iter().enumerate() {
+            assert_eq!(decoder.read_icon_entry(index).unwrap().raw_pixels(), image.raw_pixels());
+        }
+        match decoder.icon_image(2).unwrap() {
+            IconImage::Png(ref png) => assert_eq!(png.as_slice(), decoder.icon_entry_data(2).unwrap()),
+            IconImage::Image(_) => panic!("the PNG entry is not kept as is"),
+        }
+    }
+
+    #[test]
+    fn dimension_256() {
+        let icon = icon();
+        let entry = ICONDIR_SIZE + DIRENTRY_SIZE;
+
+        //Stored as 0 in the directory
+        assert_eq!(&icon[entry..entry + 2], &[0, 0]);
+        assert_eq!(read_icon_entries(&icon).unwrap()[1].width, 256);
+
+        let too_large = DynamicImage::ImageLuma8(GrayImage::new(257, 1));
+        match encode_icon(&[IconImage::Image(too_large)], &mut Vec::new()) {
+            Err(Rexiv2ImageError::DecoderError(_)) => (),
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        let decoder = decoder(icon());
+
+        match decoder.read_icon_entry(3) {
+            Err(Rexiv2ImageError::IconNotFound(3)) => (),
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let icon = icon();
+
+        //The directory announces three entries
+        assert_eq!(read_icon_entries(&icon[..ICONDIR_SIZE + 2 * DIRENTRY_SIZE]), None);
+        assert_eq!(read_icon_entries(&icon[..4]), None);
+        assert_eq!(read_icon_entries(&[0, 0, 1, 0, 0, 0]), Some(Vec::new()));
+        //Cursors are not icons
+        assert_eq!(read_icon_entries(&[0, 0, 2, 0, 0, 0]), None);
+
+        //An entry beyond the end of the file
+        let mut icon = icon;
+        let length = icon.len();
+
+        icon.truncate(length - 1);
+        let entries = read_icon_entries(&icon).unwrap();
+        assert!(entries[2].data(&icon).is_none());
+        assert!(entries[1].data(&icon).is_some());
+    }
+
+    #[cfg(feature = "exiv2")]
+    #[test]
+    fn entry_metadata() {
+        use rexiv2::Metadata;
+
+        let image = &images()[0];
+        let mut png = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&image.raw_pixels(), 16, 16, image.color()).unwrap();
+        let metadata = Metadata::new_from_buffer(&png).unwrap();
+        metadata.set_tag_string("Xmp.dc.title", "Icon").unwrap();
+
+        let mut icon = Vec::new();
+        encode_icon(&[IconImage::with_metadata(image, &metadata).unwrap(), IconImage::Image(images()[2].clone())],
+                    &mut icon).unwrap();
+        let decoder = DecoderWithMetadata::from_buffer(icon, ImageFormat::ICO).unwrap();
+
+        assert!(decoder.icon_entry_metadata(0).unwrap().get_tag_string("Xmp.dc.title").unwrap().contains("Icon"));
+        assert!(!decoder.icon_entry_metadata(1).unwrap().has_tag("Xmp.dc.title"));
+        assert!(decoder.icon_entry_metadata(2).is_err());
+    }
+}
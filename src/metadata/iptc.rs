@@ -0,0 +1,440 @@
+#[cfg(feature = "exiv2")]
+use rexiv2::{Metadata, Rexiv2Error};
+use std::io::{Read, Seek};
+use std::result::Result;
+use std::str;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::xmp::DEFAULT_LANGUAGE;
+
+const CHARACTER_SET: &str = "Iptc.Envelope.CharacterSet";
+//ISO 2022 escape sequences stored in the CodedCharacterSet dataset (1:90)
+const UTF8_ESCAPE: &str = "\x1b%G";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IptcCharset {
+    Utf8,
+    //Also used when no charset is declared and the values are not valid UTF-8
+    Latin1,
+}
+
+impl IptcCharset {
+    fn from_escape(escape: &[u8]) -> Option<IptcCharset> {
+        match escape {
+            b"\x1b%G" | b"\x1b%/G" | b"\x1b%/I" => Some(IptcCharset::Utf8),
+            b"\x1b.A" | b"\x1b-A" => Some(IptcCharset::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            IptcCharset::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            IptcCharset::Latin1 => bytes.iter().map(|&byte| byte as char).collect(),
+        }
+    }
+
+    //None when a character has no Latin-1 code
+    pub fn encode(self, value: &str) -> Option<Vec<u8>> {
+        match self {
+            IptcCharset::Utf8 => Some(value.as_bytes().to_vec()),
+            IptcCharset::Latin1 => value.chars().map(|c| if (c as u32) < 0x100 { Some(c as u8) } else { None }).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum XmpKind {
+    Text,
+    Seq,
+    Bag,
+    LangAlt,
+}
+
+//IPTC fields existing both as IIM datasets and as IPTC Core XMP properties
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IptcField {
+    ObjectName,
+    Headline,
+    Caption,
+    Keywords,
+    Byline,
+    BylineTitle,
+    Credit,
+    Source,
+    Copyright,
+    City,
+    SubLocation,
+    ProvinceState,
+    CountryName,
+    CountryCode,
+    Writer,
+    SpecialInstructions,
+    TransmissionReference,
+    Category,
+    Urgency,
+}
+
+pub const IPTC_FIELDS: [IptcField; 19] = [
+    IptcField::ObjectName,
+    IptcField::Headline,
+    IptcField::Caption,
+    IptcField::Keywords,
+    IptcField::Byline,
+    IptcField::BylineTitle,
+    IptcField::Credit,
+    IptcField::Source,
+    IptcField::Copyright,
+    IptcField::City,
+    IptcField::SubLocation,
+    IptcField::ProvinceState,
+    IptcField::CountryName,
+    IptcField::CountryCode,
+    IptcField::Writer,
+    IptcField::SpecialInstructions,
+    IptcField::TransmissionReference,
+    IptcField::Category,
+    IptcField::Urgency,
+];
+
+impl IptcField {
+    pub fn iim_tag(&self) -> &'static str {
+        match *self {
+            IptcField::ObjectName => "Iptc.Application2.ObjectName",
+            IptcField::Headline => "Iptc.Application2.Headline",
+            IptcField::Caption => "Iptc.Application2.Caption",
+            IptcField::Keywords => "Iptc.Application2.Keywords",
+            IptcField::Byline => "Iptc.Application2.Byline",
+            IptcField::BylineTitle => "Iptc.Application2.BylineTitle",
+            IptcField::Credit => "Iptc.Application2.Credit",
+            IptcField::Source => "Iptc.Application2.Source",
+            IptcField::Copyright => "Iptc.Application2.Copyright",
+            IptcField::City => "Iptc.Application2.City",
+            IptcField::SubLocation => "Iptc.Application2.SubLocation",
+            IptcField::ProvinceState => "Iptc.Application2.ProvinceState",
+            IptcField::CountryName => "Iptc.Application2.CountryName",
+            IptcField::CountryCode => "Iptc.Application2.CountryCode",
+            IptcField::Writer => "Iptc.Application2.Writer",
+            IptcField::SpecialInstructions => "Iptc.Application2.SpecialInstructions",
+            IptcField::TransmissionReference => "Iptc.Application2.TransmissionReference",
+            IptcField::Category => "Iptc.Application2.Category",
+            IptcField::Urgency => "Iptc.Application2.Urgency",
+        }
+    }
+
+    //Mapping from the IPTC Photo Metadata standard
+    pub fn xmp_tag(&self) -> &'static str {
+        match *self {
+            IptcField::ObjectName => "Xmp.dc.title",
+            IptcField::Headline => "Xmp.photoshop.Headline",
+            IptcField::Caption => "Xmp.dc.description",
+            IptcField::Keywords => "Xmp.dc.subject",
+            IptcField::Byline => "Xmp.dc.creator",
+            IptcField::BylineTitle => "Xmp.photoshop.AuthorsPosition",
+            IptcField::Credit => "Xmp.photoshop.Credit",
+            IptcField::Source => "Xmp.photoshop.Source",
+            IptcField::Copyright => "Xmp.dc.rights",
+            IptcField::City => "Xmp.photoshop.City",
+            IptcField::SubLocation => "Xmp.iptc.Location",
+            IptcField::ProvinceState => "Xmp.photoshop.State",
+            IptcField::CountryName => "Xmp.photoshop.Country",
+            IptcField::CountryCode => "Xmp.iptc.CountryCode",
+            IptcField::Writer => "Xmp.photoshop.CaptionWriter",
+            IptcField::SpecialInstructions => "Xmp.photoshop.Instructions",
+            IptcField::TransmissionReference => "Xmp.photoshop.TransmissionReference",
+            IptcField::Category => "Xmp.photoshop.Category",
+            IptcField::Urgency => "Xmp.photoshop.Urgency",
+        }
+    }
+
+    fn xmp_kind(&self) -> XmpKind {
+        match *self {
+            IptcField::ObjectName | IptcField::Caption | IptcField::Copyright => XmpKind::LangAlt,
+            IptcField::Keywords => XmpKind::Bag,
+            IptcField::Byline => XmpKind::Seq,
+            _ => XmpKind::Text,
+        }
+    }
+
+    //Datasets which may appear several times in IIM
+    pub fn is_repeatable(&self) -> bool {
+        self.xmp_kind() == XmpKind::Bag || self.xmp_kind() == XmpKind::Seq
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R, Metadata> {
+    //The bytes come from the gexiv2 handle, which decoders built with_backend do not have
+    pub(super) fn raw_tag_bytes(&self, tag: &str) -> Result<Vec<Vec<u8>>, Rexiv2ImageError> {
+        let raw = self.raw.as_ref().ok_or_else(|| {
+            Rexiv2Error::Internal(Some(format!("No gexiv2 handle to read the bytes of {}", tag)))
+        })?;
+
+        Ok(raw.get_tag_multiple_bytes(tag))
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //None when the dataset is absent or holds an escape sequence that is not handled
+    pub fn iptc_charset(&self) -> Option<IptcCharset> {
+        M::read_iim_bytes(self, CHARACTER_SET).ok()?.first()
+            .and_then(|escape| IptcCharset::from_escape(escape))
+    }
+
+    //Values of an IIM dataset decoded with the declared charset,
+    //undeclared values are read as UTF-8 when valid and Latin-1 otherwise
+    pub fn get_iim_values(&self, tag: &str) -> Result<Vec<String>, Rexiv2ImageError> {
+        let charset = self.iptc_charset();
+
+        Ok(M::read_iim_bytes(self, tag)?.iter().map(|bytes| match (charset, str::from_utf8(bytes)) {
+            (Some(charset), _) => charset.decode(bytes),
+            (None, Ok(value)) => value.to_string(),
+            (None, Err(_)) => IptcCharset::Latin1.decode(bytes),
+        }).collect())
+    }
+
+    //Values are encoded in the declared Latin-1 charset when they can be and the backend stores bytes,
+    //otherwise the datasets are converted to UTF-8 first so that both charsets are never mixed
+    pub fn set_iim_values<S: AsRef<str>>(&mut self, tag: &str, values: &[S]) -> Result<(), Rexiv2ImageError> {
+        if self.iptc_charset() == Some(IptcCharset::Latin1) {
+            let encoded: Option<Vec<Vec<u8>>> = values.iter()
+                .map(|value| IptcCharset::Latin1.encode(value.as_ref()))
+                .collect();
+
+            if let Some(encoded) = encoded {
+                match self.metadata.write_tag_bytes(tag, &encoded) {
+                    Err(Rexiv2ImageError::UnsupportedTag(_)) => (),
+                    result => return result,
+                }
+            }
+        }
+        let values: Vec<&str> = values.iter().map(|value| value.as_ref()).collect();
+
+        self.normalize_iptc_charset()?;
+        self.metadata.write_tag_multiple(tag, &values)
+    }
+
+    //Rewrites the IIM datasets which are not UTF-8 and declares it. ASCII values are the same in both charsets
+    pub fn normalize_iptc_charset(&mut self) -> Result<(), Rexiv2ImageError> {
+        let charset = self.iptc_charset();
+
+        if charset == Some(IptcCharset::Utf8) {
+            return Ok(());
+        }
+        let tags = self.metadata.list_tags()?;
+
+        for tag in tags.iter().filter(|tag| tag.starts_with("Iptc.") && tag.as_str() != CHARACTER_SET) {
+            let bytes = M::read_iim_bytes(self, tag)?;
+            let is_utf8 = bytes.iter().all(|value| value.is_ascii() || (charset.is_none() && str::from_utf8(value).is_ok()));
+
+            if is_utf8 {
+                continue;
+            }
+            let values = self.get_iim_values(tag)?;
+            let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+
+            self.metadata.write_tag_multiple(tag, &values)?;
+        }
+        self.metadata.write_tag(CHARACTER_SET, UTF8_ESCAPE)
+    }
+
+    fn get_iptc_xmp(&self, field: IptcField) -> Result<Vec<String>, Rexiv2ImageError> {
+        let tag = field.xmp_tag();
+
+        match field.xmp_kind() {
+            XmpKind::Bag | XmpKind::Seq => self.get_xmp_array(tag),
+            XmpKind::LangAlt => Ok(self.get_xmp_lang_alt_entry(tag, DEFAULT_LANGUAGE)?.into_iter().collect()),
+            XmpKind::Text => Ok(self.read_optional_tag(tag)?.into_iter().collect()),
+        }
+    }
+
+    fn set_iptc_xmp<S: AsRef<str>>(&mut self, field: IptcField, values: &[S]) -> Result<(), Rexiv2ImageError> {
+        let tag = field.xmp_tag();
+
+        match (field.xmp_kind(), values.first()) {
+            (XmpKind::Bag, _) | (XmpKind::Seq, _) => self.set_xmp_array(tag, values),
+            (_, None) => {
+                self.metadata.remove_tag(tag);
+                Ok(())
+            },
+            (XmpKind::LangAlt, Some(value)) => self.set_xmp_lang_alt_entry(tag, DEFAULT_LANGUAGE, value.as_ref()),
+            (XmpKind::Text, Some(value)) => self.metadata.write_tag(tag, value.as_ref()),
+        }
+    }
+
+    fn set_iptc_iim<S: AsRef<str>>(&mut self, field: IptcField, values: &[S]) -> Result<(), Rexiv2ImageError> {
+        let values: Vec<&str> = values.iter().map(|value| value.as_ref()).collect();
+        let values = if field.is_repeatable() { &values[..] } else { &values[..values.len().min(1)] };
+
+        self.set_iim_values(field.iim_tag(), values)
+    }
+
+    //IPTC Core is preferred, the IIM dataset is only read when the XMP property is absent
+    pub fn get_iptc(&self, field: IptcField) -> Result<Vec<String>, Rexiv2ImageError> {
+        let values = self.get_iptc_xmp(field)?;
+
+        if !values.is_empty() {
+            return Ok(values);
+        }
+        self.get_iim_values(field.iim_tag())
+    }
+
+    //Writes both the IIM dataset, in the charset of the file, and the IPTC Core property
+    pub fn set_iptc<S: AsRef<str>>(&mut self, field: IptcField, values: &[S]) -> Result<(), Rexiv2ImageError> {
+        if self.metadata.supports_tag(field.iim_tag()) {
+            self.set_iptc_iim(field, values)?;
+        }
+        if self.metadata.supports_tag(field.xmp_tag()) {
+            self.set_iptc_xmp(field, values)?;
+        }
+        Ok(())
+    }
+
+    //Called by save_metadata: IPTC Core gets the fields only found in IIM,
+    //and when the file has IIM datasets they are updated from IPTC Core
+    pub fn sync_iptc(&mut self) -> Result<(), Rexiv2ImageError> {
+        let has_iim = self.metadata.list_tags()?.iter().any(|tag| tag.starts_with("Iptc."));
+
+        //Without XMP there is nothing to synchronize with
+        for &field in IPTC_FIELDS.iter() {
+            if !self.metadata.supports_tag(field.xmp_tag()) {
+                continue;
+            }
+            let xmp_values = self.get_iptc_xmp(field)?;
+            let iim_values = if has_iim { self.get_iim_values(field.iim_tag())? } else { Vec::new() };
+
+            if xmp_values.is_empty() && !iim_values.is_empty() {
+                self.set_iptc_xmp(field, &iim_values)?;
+            } else if has_iim && !xmp_values.is_empty() && xmp_values != iim_values {
+                self.set_iptc_iim(field, &xmp_values)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::path::Path;
+    use super::{CHARACTER_SET, IPTC_FIELDS, IptcCharset, IptcField};
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    const LATIN1_ESCAPE: &str = "\x1b.A";
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(2, 2, vec![0, 80, 160, 240]).unwrap())
+    }
+
+    #[test]
+    fn declared_latin1() {
+        let metadata = MemoryMetadata::new()
+            .with_tag(CHARACTER_SET, LATIN1_ESCAPE)
+            .with_tag_bytes("Iptc.Application2.City", &[&b"Z\xfcrich"[..]]);
+        let decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        assert_eq!(decoder.iptc_charset(), Some(IptcCharset::Latin1));
+        assert_eq!(decoder.get_iptc(IptcField::City).unwrap(), vec!["Zürich"]);
+    }
+
+    #[test]
+    fn declared_utf8() {
+        let metadata = MemoryMetadata::new()
+            .with_tag(CHARACTER_SET, "\x1b%G")
+            .with_tag("Iptc.Application2.City", "Zürich");
+        let decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        assert_eq!(decoder.iptc_charset(), Some(IptcCharset::Utf8));
+        assert_eq!(decoder.get_iptc(IptcField::City).unwrap(), vec!["Zürich"]);
+    }
+
+    #[test]
+    fn undeclared_charset() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Iptc.Application2.City", "Zürich")
+            .with_tag_bytes("Iptc.Application2.CountryName", &[&b"Espa\xf1a"[..]]);
+        let decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        assert_eq!(decoder.iptc_charset(), None);
+        assert_eq!(decoder.get_iptc(IptcField::City).unwrap(), vec!["Zürich"]);
+        assert_eq!(decoder.get_iptc(IptcField::CountryName).unwrap(), vec!["España"]);
+    }
+
+    #[test]
+    fn latin1_write() {
+        let metadata = MemoryMetadata::new()
+            .with_tag(CHARACTER_SET, LATIN1_ESCAPE)
+            .with_tag_bytes("Iptc.Application2.City", &[&b"Z\xfcrich"[..]]);
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.set_iptc(IptcField::CountryName, &["España"]).unwrap();
+        assert_eq!(decoder.metadata.read_tag_bytes("Iptc.Application2.CountryName").unwrap(),
+                   vec![b"Espa\xf1a".to_vec()]);
+
+        //Not encodable in Latin-1, every dataset is converted to UTF-8
+        decoder.set_iptc(IptcField::ObjectName, &["東京"]).unwrap();
+        assert_eq!(decoder.iptc_charset(), Some(IptcCharset::Utf8));
+        assert_eq!(decoder.metadata.read_tag("Iptc.Application2.City").unwrap(), "Zürich");
+        assert_eq!(decoder.metadata.read_tag("Iptc.Application2.CountryName").unwrap(), "España");
+        assert_eq!(decoder.get_iptc(IptcField::ObjectName).unwrap(), vec!["東京"]);
+    }
+
+    #[test]
+    fn iim_to_xmp() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Iptc.Application2.ObjectName", "Sunset")
+            .with_tag("Iptc.Application2.Headline", "Evening")
+            .with_tag("Iptc.Application2.Keywords", "sea");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.sync_iptc().unwrap();
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.title", "x-default").unwrap(), Some("Sunset".to_string()));
+        assert_eq!(decoder.metadata.read_tag("Xmp.photoshop.Headline").unwrap(), "Evening");
+        assert_eq!(decoder.get_xmp_array("Xmp.dc.subject").unwrap(), vec!["sea"]);
+    }
+
+    #[test]
+    fn xmp_to_iim() {
+        let metadata = MemoryMetadata::new()
+            .with_tag("Iptc.Application2.Headline", "Morning")
+            .with_tag("Xmp.photoshop.Headline", "Evening");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.set_xmp_array("Xmp.dc.creator", &["Ann", "Bob"]).unwrap();
+        decoder.sync_iptc().unwrap();
+        assert_eq!(decoder.metadata.read_tag("Iptc.Application2.Headline").unwrap(), "Evening");
+        assert_eq!(decoder.metadata.read_tag_multiple("Iptc.Application2.Byline").unwrap(), vec!["Ann", "Bob"]);
+    }
+
+    #[test]
+    fn without_iim() {
+        let metadata = MemoryMetadata::new().with_tag("Xmp.photoshop.City", "Lyon");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.sync_iptc().unwrap();
+        assert!(decoder.metadata.tags().keys().all(|tag| !tag.starts_with("Iptc.")));
+    }
+
+    #[test]
+    fn saved_forms_are_equal() {
+        let metadata = MemoryMetadata::new()
+            .with_tag(CHARACTER_SET, LATIN1_ESCAPE)
+            .with_tag_bytes("Iptc.Application2.Caption", &[&b"Cr\xe8me"[..]])
+            .with_tag("Iptc.Application2.City", "Paris")
+            .with_tag("Xmp.photoshop.City", "Nice");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        decoder.set_xmp_array("Xmp.dc.subject", &["café", "thé"]).unwrap();
+        decoder.save_metadata(Path::new("image.png")).unwrap();
+        for &field in IPTC_FIELDS.iter() {
+            assert_eq!(decoder.get_iptc_xmp(field).unwrap(), decoder.get_iim_values(field.iim_tag()).unwrap(),
+                       "{:?}", field);
+        }
+        assert_eq!(decoder.get_iptc(IptcField::Caption).unwrap(), vec!["Crème"]);
+        assert_eq!(decoder.get_iptc(IptcField::City).unwrap(), vec!["Nice"]);
+        assert_eq!(decoder.metadata.read_tag_bytes("Iptc.Application2.Keywords").unwrap(),
+                   vec![b"caf\xe9".to_vec(), b"th\xe9".to_vec()]);
+    }
+}
@@ -0,0 +1,264 @@
+use image::{DynamicImage, ImageFormat, GenericImage};
+use image::png::PNGEncoder;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+//Backend keeping the tags in a map, for testing code built on DecoderWithMetadata without image files
+//or gexiv2. Nothing is written by save, which records the path or fails with the injected error
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryMetadata {
+    //Repeatable IPTC datasets and XMP arrays hold several values
+    tags: BTreeMap<String, Vec<String>>,
+    //Values which are not UTF-8, like legacy IIM datasets, they can only be read as bytes
+    bytes: BTreeMap<String, Vec<Vec<u8>>>,
+    //Tags write_tag refuses with UnsupportedTag
+    unsupported: Vec<String>,
+    save_error: Option<io::ErrorKind>,
+    saved: RefCell<Vec<PathBuf>>,
+}
+
+impl MemoryMetadata {
+    pub fn new() -> MemoryMetadata {
+        MemoryMetadata::default()
+    }
+
+    pub fn with_tag(mut self, tag: &str, value: &str) -> MemoryMetadata {
+        self.tags.insert(tag.to_string(), vec![value.to_string()]);
+        self
+    }
+
+    pub fn with_tag_bytes(mut self, tag: &str, values: &[&[u8]]) -> MemoryMetadata {
+        self.store_bytes(tag, values.iter().map(|value| value.to_vec()).collect());
+        self
+    }
+
+    pub fn with_unsupported_tag(mut self, tag: &str) -> MemoryMetadata {
+        self.unsupported.push(tag.to_string());
+        self
+    }
+
+    //Every save fails with an Io error of this kind
+    pub fn with_save_error(mut self, kind: io::ErrorKind) -> MemoryMetadata {
+        self.save_error = Some(kind);
+        self
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.tags
+    }
+
+    //Paths given to the successful saves, in order
+    pub fn saved_paths(&self) -> Vec<PathBuf> {
+        self.saved.borrow().clone()
+    }
+
+    //Values which are all UTF-8 are kept as text
+    fn store_bytes(&mut self, tag: &str, values: Vec<Vec<u8>>) {
+        let text: Result<Vec<String>, _> = values.iter().map(|value| String::from_utf8(value.clone())).collect();
+
+        self.bytes.remove(tag);
+        self.tags.remove(tag);
+        if values.is_empty() {
+            return;
+        }
+        match text {
+            Ok(text) => {
+                self.tags.insert(tag.to_string(), text);
+            },
+            Err(_) => {
+                self.bytes.insert(tag.to_string(), values);
+            },
+        }
+    }
+
+    fn check_text(&self, tag: &str) -> Result<(), Rexiv2ImageError> {
+        match self.bytes.get(tag) {
+            Some(values) => {
+                let lossy: Vec<String> = values.iter().map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+
+                Err(Rexiv2ImageError::InvalidTagValue(tag.to_string(), lossy.join(", ")))
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn check_supported(&self, tag: &str) -> Result<(), Rexiv2ImageError> {
+        if self.unsupported.iter().any(|unsupported| unsupported == tag) {
+            return Err(Rexiv2ImageError::UnsupportedTag(tag.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl MetadataBackend for MemoryMetadata {
+    //The image data is not looked at, the metadata starts empty
+    fn from_buffer(_data: &[u8]) -> Result<MemoryMetadata, Rexiv2ImageError> {
+        Ok(MemoryMetadata::new())
+    }
+
+    //Several values are joined the way exiv2 does
+    fn read_tag(&self, tag: &str) -> Result<String, Rexiv2ImageError> {
+        self.check_text(tag)?;
+        self.tags.get(tag).map(|values| values.join(", ")).ok_or_else(|| Rexiv2ImageError::TagNotFound(tag.to_string()))
+    }
+
+    fn write_tag(&mut self, tag: &str, value: &str) -> Result<(), Rexiv2ImageError> {
+        self.check_supported(tag)?;
+        self.bytes.remove(tag);
+        self.tags.insert(tag.to_string(), vec![value.to_string()]);
+        Ok(())
+    }
+
+    fn remove_tag(&mut self, tag: &str) -> bool {
+        let removed = self.bytes.remove(tag).is_some();
+
+        self.tags.remove(tag).is_some() || removed
+    }
+
+    fn list_tags(&self) -> Result<Vec<String>, Rexiv2ImageError> {
+        let mut tags: Vec<String> = self.tags.keys().chain(self.bytes.keys()).cloned().collect();
+
+        tags.sort();
+        Ok(tags)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        if let Some(kind) = self.save_error {
+            return Err(io::Error::new(kind, "Injected save error").into());
+        }
+        self.saved.borrow_mut().push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn read_tag_multiple(&self, tag: &str) -> Result<Vec<String>, Rexiv2ImageError> {
+        self.check_text(tag)?;
+        Ok(self.tags.get(tag).cloned().unwrap_or_default())
+    }
+
+    fn write_tag_multiple(&mut self, tag: &str, values: &[&str]) -> Result<(), Rexiv2ImageError> {
+        self.check_supported(tag)?;
+        self.bytes.remove(tag);
+        if values.is_empty() {
+            self.tags.remove(tag);
+        } else {
+            self.tags.insert(tag.to_string(), values.iter().map(|value| value.to_string()).collect());
+        }
+        Ok(())
+    }
+
+    fn read_tag_bytes(&self, tag: &str) -> Result<Vec<Vec<u8>>, Rexiv2ImageError> {
+        match self.bytes.get(tag) {
+            Some(values) => Ok(values.clone()),
+            None => Ok(self.tags.get(tag).map_or_else(Vec::new, |values| {
+                values.iter().map(|value| value.as_bytes().to_vec()).collect()
+            })),
+        }
+    }
+
+    fn write_tag_bytes(&mut self, tag: &str, values: &[Vec<u8>]) -> Result<(), Rexiv2ImageError> {
+        self.check_supported(tag)?;
+        self.store_bytes(tag, values.to_vec());
+        Ok(())
+    }
+
+    //Values are kept apart whatever the tag
+    fn is_multiple_valued(&self, _tag: &str) -> bool {
+        true
+    }
+
+    fn supports_tag(&self, tag: &str) -> bool {
+        !self.unsupported.iter().any(|unsupported| unsupported == tag)
+    }
+}
+
+impl<M: MetadataBackend> DecoderWithMetadata<Cursor<Vec<u8>>, M> {
+    //Decoder of the pixels encoded as a PNG, with the given metadata
+    pub fn from_image(image: &DynamicImage, metadata: M)
+                      -> Result<DecoderWithMetadata<Cursor<Vec<u8>>, M>, Rexiv2ImageError> {
+        let (width, height) = image.dimensions();
+        let mut png = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&image.raw_pixels(), width, height, image.color())?;
+        DecoderWithMetadata::with_backend(metadata, Cursor::new(png), ImageFormat::PNG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, ImageDecoder};
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use super::MemoryMetadata;
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::super::backend::MetadataBackend;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(3, 2, vec![0, 50, 100, 150, 200, 250]).unwrap())
+    }
+
+    #[test]
+    fn from_image() {
+        let metadata = MemoryMetadata::new().with_tag("Exif.Image.Make", "Camera");
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+
+        assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+        assert_eq!(decoder.read_dynamic_image().unwrap().raw_pixels(), image().raw_pixels());
+        assert_eq!(decoder.make().unwrap(), Some("Camera".to_string()));
+    }
+
+    #[test]
+    fn save_records_the_path() {
+        let mut decoder = DecoderWithMetadata::from_image(&image(), MemoryMetadata::new()).unwrap();
+
+        decoder.save_metadata(Path::new("first.png")).unwrap();
+        decoder.save_metadata(Path::new("second.png")).unwrap();
+        assert_eq!(decoder.metadata.saved_paths(), vec![Path::new("first.png"), Path::new("second.png")]);
+    }
+
+    #[test]
+    fn injected_save_error() {
+        let metadata = MemoryMetadata::new().with_save_error(ErrorKind::PermissionDenied);
+        let mut decoder = DecoderWithMetadata::from_image(&image(), metadata).unwrap();
+        let err = decoder.save_metadata(Path::new("image.png")).unwrap_err();
+
+        match err {
+            Rexiv2ImageError::WithPath(ref path, ref inner) => {
+                assert_eq!(path, Path::new("image.png"));
+                match **inner {
+                    Rexiv2ImageError::Io(ref io) => assert_eq!(io.kind(), ErrorKind::PermissionDenied),
+                    ref inner => panic!("Unexpected error {:?}", inner),
+                }
+            },
+            err => panic!("Unexpected error {:?}", err),
+        }
+        assert!(decoder.metadata.saved_paths().is_empty());
+    }
+
+    #[test]
+    fn unsupported_tag() {
+        let mut metadata = MemoryMetadata::new().with_unsupported_tag("Exif.Image.Make");
+
+        match metadata.write_tag("Exif.Image.Make", "Camera") {
+            Err(Rexiv2ImageError::UnsupportedTag(ref tag)) => assert_eq!(tag, "Exif.Image.Make"),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(metadata.write_tag("Exif.Image.Model", "Model").is_ok());
+        assert!(!metadata.has_tag("Exif.Image.Make"));
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut metadata = MemoryMetadata::new();
+
+        metadata.write_tag_multiple("Iptc.Application2.Keywords", &["one", "two"]).unwrap();
+        assert_eq!(metadata.read_tag_multiple("Iptc.Application2.Keywords").unwrap(), vec!["one", "two"]);
+        assert_eq!(metadata.read_tag("Iptc.Application2.Keywords").unwrap(), "one, two");
+        metadata.write_tag_multiple("Iptc.Application2.Keywords", &[]).unwrap();
+        assert!(!metadata.has_tag("Iptc.Application2.Keywords"));
+    }
+}
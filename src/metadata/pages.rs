@@ -0,0 +1,393 @@
+use image::{DynamicImage, ImageError, ImageFormat};
+use image::tiff::TIFFDecoder;
+use num_rational::Ratio;
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::result::Result;
+use super::{decoder_to_dynamic_image, DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+pub const NEW_SUBFILE_TYPE: u16 = 254;
+pub const IMAGE_WIDTH: u16 = 256;
+pub const IMAGE_LENGTH: u16 = 257;
+pub const COMPRESSION: u16 = 259;
+pub const DOCUMENT_NAME: u16 = 269;
+pub const IMAGE_DESCRIPTION: u16 = 270;
+pub const X_RESOLUTION: u16 = 282;
+pub const Y_RESOLUTION: u16 = 283;
+pub const PAGE_NAME: u16 = 285;
+pub const RESOLUTION_UNIT: u16 = 296;
+pub const PAGE_NUMBER: u16 = 297;
+
+//Value of a TIFF field, unsigned covers both SHORT and LONG
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TiffValue {
+    Bytes(Vec<u8>),
+    Ascii(String),
+    Unsigned(Vec<u32>),
+    Signed(Vec<i32>),
+    Rational(Vec<Ratio<u32>>),
+    SignedRational(Vec<Ratio<i32>>),
+    //Field type and bytes in the byte order of the file they come from, like floats
+    Other(u16, Vec<u8>),
+}
+
+//Size in bytes of one value of a TIFF field type
+pub(crate) fn type_size(field_type: u16) -> usize {
+    match field_type {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+//Tag, field type and value of an IFD entry
+pub(crate) type IfdEntry = (u16, u16, TiffValue);
+
+pub(crate) struct TiffReader<'a> {
+    data: &'a [u8],
+    pub(crate) big_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Option<TiffReader<'a>> {
+        let big_endian = match data.get(..4)? {
+            b"MM\x00\x2a" => true,
+            b"II\x2a\x00" => false,
+            _ => return None,
+        };
+
+        Some(TiffReader { data, big_endian })
+    }
+
+    pub(crate) fn first_ifd(&self) -> Option<u32> {
+        self.u32_at(4)
+    }
+
+    //Readable entries, and the offset of the next IFD
+    pub(crate) fn ifd(&self, offset: u32) -> Option<(Vec<IfdEntry>, u32)> {
+        let count = self.u16_at(offset as usize)? as usize;
+        let entries = (0..count).map(|i| offset as usize + 2 + i * 12)
+            .filter_map(|entry| Some((self.u16_at(entry)?, self.u16_at(entry + 2)?, self.value(entry)?)))
+            .collect();
+
+        Some((entries, self.u32_at(offset as usize + 2 + count * 12).unwrap_or(0)))
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes(offset, 2)?;
+        let bytes = [bytes[0], bytes[1]];
+
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.bytes(offset, 4)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn value(&self, entry: usize) -> Option<TiffValue> {
+        let field_type = self.u16_at(entry + 2)?;
+        let count = self.u32_at(entry + 4)? as usize;
+        let len = count.checked_mul(type_size(field_type))?;
+        let offset = if len <= 4 { entry + 8 } else { self.u32_at(entry + 8)? as usize };
+        let bytes = self.bytes(offset, len)?;
+        let at = |index: usize| offset + index * type_size(field_type);
+
+        Some(match field_type {
+            1 | 7 => TiffValue::Bytes(bytes.to_vec()),
+            2 => TiffValue::Ascii(String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()),
+            3 => TiffValue::Unsigned((0..count).map(|i| self.u16_at(at(i)).map(u32::from)).collect::<Option<_>>()?),
+            4 => TiffValue::Unsigned((0..count).map(|i| self.u32_at(at(i))).collect::<Option<_>>()?),
+            5 => TiffValue::Rational((0..count).map(|i| Some(Ratio::new_raw(self.u32_at(at(i))?, self.u32_at(at(i) + 4)?)))
+                                      .collect::<Option<_>>()?),
+            6 => TiffValue::Signed(bytes.iter().map(|&byte| byte as i8 as i32).collect()),
+            8 => TiffValue::Signed((0..count).map(|i| self.u16_at(at(i)).map(|value| value as i16 as i32))
+                                   .collect::<Option<_>>()?),
+            9 => TiffValue::Signed((0..count).map(|i| self.u32_at(at(i)).map(|value| value as i32)).collect::<Option<_>>()?),
+            10 => TiffValue::SignedRational((0..count)
+                                            .map(|i| Some(Ratio::new_raw(self.u32_at(at(i))? as i32,
+                                                                         self.u32_at(at(i) + 4)? as i32)))
+                                            .collect::<Option<_>>()?),
+            _ => TiffValue::Other(field_type, bytes.to_vec()),
+        })
+    }
+}
+
+//Tags of one IFD of the main chain, SubIFDs are not followed
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiffPageInfo {
+    pub index: usize,
+    //Offset of the IFD in the file
+    pub offset: u32,
+    pub tags: BTreeMap<u16, TiffValue>,
+}
+
+impl TiffPageInfo {
+    fn unsigned(&self, tag: u16) -> Option<&[u32]> {
+        match self.tags.get(&tag) {
+            Some(TiffValue::Unsigned(values)) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, tag: u16) -> Option<&str> {
+        match self.tags.get(&tag) {
+            Some(TiffValue::Ascii(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn rational(&self, tag: u16) -> Option<Ratio<u32>> {
+        match self.tags.get(&tag) {
+            Some(TiffValue::Rational(values)) => values.first().cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> Option<u32> {
+        self.unsigned(IMAGE_WIDTH)?.first().cloned()
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.unsigned(IMAGE_LENGTH)?.first().cloned()
+    }
+
+    //1 for none, 5 for LZW, 7 for JPEG, 8 for Deflate, 32773 for PackBits
+    pub fn compression(&self) -> Option<u32> {
+        self.unsigned(COMPRESSION)?.first().cloned()
+    }
+
+    //Horizontal and vertical pixels per resolution unit
+    pub fn resolution(&self) -> Option<(Ratio<u32>, Ratio<u32>)> {
+        Some((self.rational(X_RESOLUTION)?, self.rational(Y_RESOLUTION)?))
+    }
+
+    //1 for none, 2 for inch, 3 for centimeter
+    pub fn resolution_unit(&self) -> Option<u32> {
+        self.unsigned(RESOLUTION_UNIT)?.first().cloned()
+    }
+
+    pub fn page_name(&self) -> Option<&str> {
+        self.ascii(PAGE_NAME)
+    }
+
+    pub fn document_name(&self) -> Option<&str> {
+        self.ascii(DOCUMENT_NAME)
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.ascii(IMAGE_DESCRIPTION)
+    }
+
+    //Page number from 0 and total number of pages, the total is 0 when unknown
+    pub fn page_number(&self) -> Option<(u32, u32)> {
+        match self.unsigned(PAGE_NUMBER) {
+            Some(&[page, total]) => Some((page, total)),
+            _ => None,
+        }
+    }
+}
+
+fn format_error(message: String) -> Rexiv2ImageError {
+    Rexiv2ImageError::DecoderError(ImageError::FormatError(message))
+}
+
+//Walks the IFD chain, a loop in the chain ends it
+pub(crate) fn read_pages(data: &[u8]) -> Result<Vec<TiffPageInfo>, Rexiv2ImageError> {
+    let reader = TiffReader::new(data).ok_or_else(|| format_error("TIFF signature not found".to_string()))?;
+    let mut pages = Vec::new();
+    let mut visited = HashSet::new();
+    let mut offset = reader.first_ifd().ok_or_else(|| format_error("Truncated TIFF header".to_string()))?;
+
+    while offset != 0 && visited.insert(offset) {
+        let (entries, next) = reader.ifd(offset)
+            .ok_or_else(|| format_error(format!("Malformed IFD of page {} at offset {}", pages.len(), offset)))?;
+        let tags = entries.into_iter().map(|(tag, _, value)| (tag, value)).collect();
+
+        pages.push(TiffPageInfo { index: pages.len(), offset, tags });
+        offset = next;
+    }
+    Ok(pages)
+}
+
+//The TIFF decoder of the image crate only decodes the first IFD,
+//so it is given a header pointing to the IFD of the page instead
+struct PageReader<'a> {
+    data: &'a [u8],
+    header: [u8; 8],
+    position: u64,
+}
+
+impl<'a> Read for PageReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = cmp::min(self.position, self.data.len() as u64) as usize;
+        let source = if position < self.header.len() { &self.header[position..] } else { &self.data[position..] };
+        let len = cmp::min(buf.len(), source.len());
+
+        buf[..len].copy_from_slice(&source[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a> Seek for PageReader<'a> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => (self.data.len() as u64).checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+//Image and tags of a page to write, the tags describing the pixels are computed by the writer
+#[derive(Clone)]
+pub struct TiffPageImage {
+    pub image: DynamicImage,
+    pub tags: BTreeMap<u16, TiffValue>,
+}
+
+impl TiffPageImage {
+    pub fn new(image: DynamicImage) -> TiffPageImage {
+        TiffPageImage { image, tags: BTreeMap::new() }
+    }
+
+    pub fn tag(mut self, tag: u16, value: TiffValue) -> TiffPageImage {
+        self.tags.insert(tag, value);
+        self
+    }
+
+    pub fn page_name(self, name: &str) -> TiffPageImage {
+        self.tag(PAGE_NAME, TiffValue::Ascii(name.to_string()))
+    }
+
+    pub fn description(self, description: &str) -> TiffPageImage {
+        self.tag(IMAGE_DESCRIPTION, TiffValue::Ascii(description.to_string()))
+    }
+
+    pub fn resolution(self, x: Ratio<u32>, y: Ratio<u32>, unit: u32) -> TiffPageImage {
+        self.tag(X_RESOLUTION, TiffValue::Rational(vec![x]))
+            .tag(Y_RESOLUTION, TiffValue::Rational(vec![y]))
+            .tag(RESOLUTION_UNIT, TiffValue::Unsigned(vec![unit]))
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    fn tiff_data(&self) -> Result<&[u8], Rexiv2ImageError> {
+        match self.content {
+            Some(ref data) if self.format == ImageFormat::TIFF => Ok(data),
+            _ => Err(Rexiv2ImageError::UnsupportedFormat(self.format)),
+        }
+    }
+
+    pub fn pages(&self) -> Result<Vec<TiffPageInfo>, Rexiv2ImageError> {
+        read_pages(self.tiff_data()?)
+    }
+
+    pub fn page_count(&self) -> Result<usize, Rexiv2ImageError> {
+        Ok(self.pages()?.len())
+    }
+
+    //The EXIF orientation is not applied, it belongs to the first page
+    pub fn read_page(&self, index: usize) -> Result<DynamicImage, Rexiv2ImageError> {
+        let page = self.pages()?.into_iter().nth(index).ok_or(Rexiv2ImageError::PageNotFound(index))?;
+        let data = self.tiff_data()?;
+        let mut header = [0u8; 8];
+
+        header[..4].copy_from_slice(&data[..4]);
+        header[4..].copy_from_slice(&if &data[..2] == b"MM" { page.offset.to_be_bytes() } else { page.offset.to_le_bytes() });
+        let mut decoder = TIFFDecoder::new(PageReader { data, header, position: 0 })?;
+
+        decoder_to_dynamic_image(&mut decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageError;
+    use super::read_pages;
+    use super::super::Rexiv2ImageError;
+
+    fn message(result: Result<Vec<super::TiffPageInfo>, Rexiv2ImageError>) -> String {
+        match result {
+            Err(Rexiv2ImageError::DecoderError(ImageError::FormatError(message))) => message,
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(pages) => panic!("{} pages read", pages.len()),
+        }
+    }
+
+    //One IFD holding the width, then a second one past the end of the file
+    #[test]
+    fn malformed_ifd() {
+        let mut data = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(message(read_pages(&data)), "Malformed IFD of page 1 at offset 1000");
+    }
+
+    #[test]
+    fn not_tiff() {
+        assert_eq!(message(read_pages(b"GIF89a")), "TIFF signature not found");
+        assert_eq!(message(read_pages(b"MM\x00\x2a")), "Truncated TIFF header");
+    }
+
+    #[cfg(feature = "exiv2")]
+    #[test]
+    fn round_trip() {
+        use image::{ColorType, DynamicImage, GrayImage, ImageFormat, Luma};
+        use image::png::PNGEncoder;
+        use num_rational::Ratio;
+        use rexiv2::Metadata;
+        use encoder::EncoderWithMetadata;
+        use super::TiffPageImage;
+        use super::super::DecoderWithMetadata;
+
+        let images: Vec<DynamicImage> = (1..4u32)
+            .map(|page| GrayImage::from_fn(page * 2, 3, |x, y| Luma([(page * 40 + x + y) as u8])))
+            .map(DynamicImage::ImageLuma8)
+            .collect();
+        let mut png = Vec::new();
+
+        PNGEncoder::new(&mut png).encode(&images[0].raw_pixels(), 2, 3, ColorType::Gray(8)).unwrap();
+        let metadata = Metadata::new_from_buffer(&png).unwrap();
+        metadata.set_tag_string("Exif.Image.Make", "Scanner").unwrap();
+        let pages: Vec<TiffPageImage> = images.iter().enumerate()
+            .map(|(index, image)| TiffPageImage::new(image.clone())
+                 .page_name(&format!("Page {}", index + 1))
+                 .resolution(Ratio::from_integer(300), Ratio::from_integer(300), 2))
+            .collect();
+        let encoder = EncoderWithMetadata::new(&metadata, ImageFormat::TIFF).unwrap();
+        let data = encoder.encode_pages_to_buffer(&pages).unwrap();
+        let decoder = DecoderWithMetadata::from_buffer(data, ImageFormat::TIFF).unwrap();
+        let infos = decoder.pages().unwrap();
+
+        assert_eq!(decoder.get_tag_string("Exif.Image.Make").unwrap(), "Scanner");
+        assert_eq!(infos.len(), 3);
+        for (index, (info, image)) in infos.iter().zip(images.iter()).enumerate() {
+            assert_eq!(info.index, index);
+            assert_eq!(info.width(), Some(image.to_luma().width()));
+            assert_eq!(info.page_number(), Some((index as u32, 3)));
+            assert_eq!(info.page_name(), Some(format!("Page {}", index + 1).as_str()));
+            assert_eq!(info.resolution(), Some((Ratio::from_integer(300), Ratio::from_integer(300))));
+            assert_eq!(decoder.read_page(index).unwrap().raw_pixels(), image.raw_pixels());
+        }
+        match decoder.read_page(3) {
+            Err(Rexiv2ImageError::PageNotFound(3)) => {},
+            other => panic!("Unexpected result {:?}", other.map(|image| image.raw_pixels().len())),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::result::Result;
+use image::{self, DynamicImage, ImageFormat};
+use encoder;
+use raw::RawMetadata;
+use super::{DecoderWithMetadata, Rexiv2ImageError, guess_format_from_bytes};
+
+//Embedded preview as listed by exiv2, the EXIF thumbnail is usually one of them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreviewInfo {
+    pub index: usize,
+    pub mime_type: String,
+    pub extension: String,
+    //In bytes
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub enum ThumbnailUpdate {
+    Replace(Vec<u8>),
+    Erase,
+}
+
+fn format_from_mime_type(mime_type: &str, data: &[u8]) -> Option<ImageFormat> {
+    match mime_type {
+        "image/jpeg" => Some(ImageFormat::JPEG),
+        "image/png" => Some(ImageFormat::PNG),
+        "image/tiff" => Some(ImageFormat::TIFF),
+        _ => guess_format_from_bytes(data),
+    }
+}
+
+fn decode_preview(mime_type: &str, data: &[u8]) -> Result<DynamicImage, Rexiv2ImageError> {
+    let format = format_from_mime_type(mime_type, data).ok_or(Rexiv2ImageError::UnknownFormat)?;
+
+    Ok(image::load_from_memory_with_format(data, format)?)
+}
+
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    //Sorted by size, smallest first, empty when the metadata was not parsed by the decoder
+    pub fn previews(&self) -> Vec<PreviewInfo> {
+        self.raw.as_ref().map_or_else(Vec::new, |raw| raw.get_previews()).into_iter().enumerate().map(|(index, preview)| PreviewInfo {
+            index,
+            mime_type: preview.mime_type,
+            extension: preview.extension,
+            size: preview.size,
+            width: preview.width,
+            height: preview.height,
+        }).collect()
+    }
+
+    //Encoded bytes of the preview, in the format given by its mime type
+    pub fn preview_data(&self, index: usize) -> Result<Vec<u8>, Rexiv2ImageError> {
+        self.raw.as_ref().and_then(|raw| raw.get_preview_data(index)).ok_or(Rexiv2ImageError::PreviewNotFound(index))
+    }
+
+    pub fn read_preview(&self, index: usize) -> Result<DynamicImage, Rexiv2ImageError> {
+        let mime_type = self.previews().into_iter()
+            .nth(index)
+            .map(|preview| preview.mime_type)
+            .ok_or(Rexiv2ImageError::PreviewNotFound(index))?;
+
+        decode_preview(&mime_type, &self.preview_data(index)?)
+    }
+
+    //Cheapest preview at least as large as the requested size, or the largest one
+    pub fn read_preview_fitting(&self, width: u32, height: u32) -> Result<Option<DynamicImage>, Rexiv2ImageError> {
+        let previews = self.previews();
+        let preview = previews.iter()
+            .find(|preview| preview.width >= width && preview.height >= height)
+            .or_else(|| previews.last());
+
+        match preview {
+            Some(preview) => Ok(Some(self.read_preview(preview.index)?)),
+            None => Ok(None),
+        }
+    }
+
+    //JPEG bytes of the EXIF thumbnail, with a pending replacement taken into account
+    pub fn exif_thumbnail(&self) -> Option<Vec<u8>> {
+        match self.thumbnail_update {
+            Some(ThumbnailUpdate::Replace(ref jpeg)) => Some(jpeg.clone()),
+            Some(ThumbnailUpdate::Erase) => None,
+            None => self.raw.as_ref().and_then(|raw| raw.get_exif_thumbnail()),
+        }
+    }
+
+    pub fn read_exif_thumbnail(&self) -> Result<Option<DynamicImage>, Rexiv2ImageError> {
+        match self.exif_thumbnail() {
+            Some(jpeg) => Ok(Some(image::load_from_memory_with_format(&jpeg, ImageFormat::JPEG)?)),
+            None => Ok(None),
+        }
+    }
+
+    //The new thumbnail is written by save_metadata
+    pub fn set_exif_thumbnail(&mut self, jpeg: Vec<u8>) {
+        self.thumbnail_update = Some(ThumbnailUpdate::Replace(jpeg));
+    }
+
+    pub fn set_exif_thumbnail_from_image(&mut self, image: &DynamicImage) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_thumbnail(encoder::make_exif_thumbnail(image)?);
+        Ok(())
+    }
+
+    //Decodes the whole image to build the thumbnail, so it has to be called before reading the image
+    pub fn regenerate_exif_thumbnail(&mut self) -> Result<DynamicImage, Rexiv2ImageError> {
+        let image = self.read_dynamic_image()?;
+
+        self.set_exif_thumbnail_from_image(&image)?;
+        Ok(image)
+    }
+
+    pub fn erase_exif_thumbnail(&mut self) {
+        self.thumbnail_update = Some(ThumbnailUpdate::Erase);
+    }
+
+    pub(super) fn save_thumbnail_update(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        let update = match self.thumbnail_update {
+            Some(ref update) => update,
+            None => return Ok(()),
+        };
+        let raw = RawMetadata::new_from_path(path)?;
+
+        match *update {
+            ThumbnailUpdate::Replace(ref jpeg) => raw.set_exif_thumbnail(jpeg),
+            ThumbnailUpdate::Erase => raw.erase_exif_thumbnail(),
+        }
+        Ok(raw.save_to_file(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, DynamicImage, GenericImage, GrayImage, ImageFormat, Luma};
+    use image::jpeg::JPEGEncoder;
+    use rexiv2::Metadata;
+    use encoder::{self, EncoderWithMetadata};
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(40, 30, |x, y| Luma([(x * 6 + y) as u8])))
+    }
+
+    fn jpeg(thumbnail: Option<Vec<u8>>) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+
+        JPEGEncoder::new(&mut jpeg).encode(&image().raw_pixels(), 40, 30, ColorType::Gray(8)).unwrap();
+        let metadata = Metadata::new_from_buffer(&jpeg).unwrap();
+        let encoder = EncoderWithMetadata::new(&metadata, ImageFormat::JPEG).unwrap();
+
+        match thumbnail {
+            Some(thumbnail) => encoder.exif_thumbnail(thumbnail).encode_image_to_buffer(&image()).unwrap(),
+            None => encoder.encode_image_to_buffer(&image()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn thumbnail_preview() {
+        let thumbnail = encoder::make_exif_thumbnail(&image()).unwrap();
+        let decoder = DecoderWithMetadata::from_buffer(jpeg(Some(thumbnail.clone())), ImageFormat::JPEG).unwrap();
+        let previews = decoder.previews();
+
+        assert_eq!(previews.len(), 1);
+        assert_eq!(previews[0].index, 0);
+        assert_eq!(previews[0].mime_type, "image/jpeg");
+        assert_eq!((previews[0].width, previews[0].height), (160, 120));
+        assert_eq!(previews[0].size as usize, thumbnail.len());
+        assert_eq!(decoder.preview_data(0).unwrap(), thumbnail);
+        assert_eq!(decoder.read_preview(0).unwrap().dimensions(), (160, 120));
+        assert_eq!(decoder.read_preview_fitting(1000, 1000).unwrap().unwrap().dimensions(), (160, 120));
+        match decoder.preview_data(1) {
+            Err(Rexiv2ImageError::PreviewNotFound(1)) => {},
+            other => panic!("Unexpected result {:?}", other.map(|data| data.len())),
+        }
+    }
+
+    #[test]
+    fn no_preview() {
+        let decoder = DecoderWithMetadata::from_buffer(jpeg(None), ImageFormat::JPEG).unwrap();
+
+        assert!(decoder.previews().is_empty());
+        assert!(decoder.read_preview_fitting(1, 1).unwrap().is_none());
+        assert!(decoder.read_preview(0).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+#[cfg(any(feature = "exiv2", feature = "exif"))]
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::tags::LAYOUT_TAGS;
+
+//Empty packet written before a new or empty sidecar is opened, exiv2 can only open existing packets
+const EMPTY_SIDECAR: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"/>\n\
+</x:xmpmeta>\n\
+<?xpacket end=\"w\"?>\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidecarNaming {
+    //The extension is appended: IMG_0001.CR2.xmp
+    Darktable,
+    //The extension is replaced: IMG_0001.xmp
+    Lightroom,
+}
+
+//Which side wins when a tag exists both in the image and in its sidecar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidecarPrecedence {
+    Embedded,
+    Sidecar,
+}
+
+pub fn sidecar_path(image: &Path, naming: SidecarNaming) -> PathBuf {
+    match naming {
+        SidecarNaming::Darktable => {
+            let mut name = image.as_os_str().to_os_string();
+
+            name.push(".xmp");
+            PathBuf::from(name)
+        },
+        SidecarNaming::Lightroom => image.with_extension("xmp"),
+    }
+}
+
+//Darktable names are tried first since they can not be shared by two images of the same basename
+pub fn find_sidecar(image: &Path) -> Option<PathBuf> {
+    [SidecarNaming::Darktable, SidecarNaming::Lightroom].iter()
+        .map(|&naming| sidecar_path(image, naming))
+        .flat_map(|path| vec![path.with_extension("XMP"), path])
+        .find(|path| path.is_file())
+}
+
+#[cfg(any(feature = "exiv2", feature = "exif"))]
+impl DecoderWithMetadata<File> {
+    //Opens the image and merges the sidecar found next to it, if any
+    pub fn open_with_sidecar(path: &Path, precedence: SidecarPrecedence)
+                             -> Result<DecoderWithMetadata<File>, Rexiv2ImageError> {
+        let mut decoder = DecoderWithMetadata::open(path)?;
+
+        if let Some(sidecar) = find_sidecar(path) {
+            decoder.load_sidecar(&sidecar, precedence)?;
+        }
+        Ok(decoder)
+    }
+}
+
+//The sidecar is parsed by the backend of the decoder, which has to handle XMP packets
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //Sidecar merged by load_sidecar
+    pub fn sidecar(&self) -> Option<&Path> {
+        self.sidecar.as_deref()
+    }
+
+    pub fn load_sidecar(&mut self, path: &Path, precedence: SidecarPrecedence) -> Result<(), Rexiv2ImageError> {
+        self.merge_sidecar(path, precedence).map_err(|err| err.with_path(path))?;
+        self.sidecar = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn merge_sidecar(&mut self, path: &Path, precedence: SidecarPrecedence) -> Result<(), Rexiv2ImageError> {
+        let sidecar = M::from_buffer(&fs::read(path)?)?;
+
+        self.merge_metadata(&sidecar, precedence)
+    }
+
+    fn merge_metadata(&mut self, sidecar: &M, precedence: SidecarPrecedence) -> Result<(), Rexiv2ImageError> {
+        for tag in sidecar.list_tags()? {
+            if precedence == SidecarPrecedence::Embedded && self.metadata.has_tag(&tag) {
+                continue;
+            }
+            self.metadata.copy_tag(sidecar, &tag)?;
+        }
+        Ok(())
+    }
+
+    //Writes the metadata to a sidecar and leaves the image untouched, IPTC is synchronized first as by save_metadata.
+    //The XMP of the sidecar that was loaded is replaced so deletions are kept,
+    //any other sidecar keeps the properties this decoder does not have
+    pub fn save_sidecar(&mut self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        self.sync_iptc().and_then(|_| self.write_sidecar(path)).map_err(|err| err.with_path(path))
+    }
+
+    pub(super) fn write_sidecar(&self, path: &Path) -> Result<(), Rexiv2ImageError> {
+        if fs::metadata(path).map_or(true, |metadata| metadata.len() == 0) {
+            fs::write(path, EMPTY_SIDECAR)?;
+        }
+        let mut sidecar = M::from_buffer(&fs::read(path)?)?;
+
+        if self.sidecar() == Some(path) {
+            for tag in sidecar.list_tags()? {
+                sidecar.remove_tag(&tag);
+            }
+        }
+        //EXIF and IPTC are converted to their XMP equivalents by exiv2 when the sidecar is saved
+        for tag in self.metadata.list_tags()?.iter().filter(|tag| !LAYOUT_TAGS.contains(&tag.as_str())) {
+            sidecar.copy_tag(&self.metadata, tag)?;
+        }
+        sidecar.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use super::{SidecarNaming, SidecarPrecedence, find_sidecar, sidecar_path};
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("rexiv2image-sidecar-{}-{}", name, process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn naming() {
+        let image = Path::new("photos/IMG_0001.CR2");
+
+        assert_eq!(sidecar_path(image, SidecarNaming::Darktable), Path::new("photos/IMG_0001.CR2.xmp"));
+        assert_eq!(sidecar_path(image, SidecarNaming::Lightroom), Path::new("photos/IMG_0001.xmp"));
+        assert_eq!(sidecar_path(Path::new("IMG_0001"), SidecarNaming::Lightroom), Path::new("IMG_0001.xmp"));
+    }
+
+    #[test]
+    fn find() {
+        let directory = directory("find");
+        let image = directory.join("img.jpg");
+        let darktable = directory.join("img.jpg.xmp");
+        let lightroom = directory.join("img.xmp");
+
+        assert_eq!(find_sidecar(&image), None);
+        fs::write(&lightroom, "").unwrap();
+        assert_eq!(find_sidecar(&image), Some(lightroom.clone()));
+        //Preferred when both exist
+        fs::write(&darktable, "").unwrap();
+        assert_eq!(find_sidecar(&image), Some(darktable.clone()));
+        //Directories are not sidecars
+        fs::remove_file(&darktable).unwrap();
+        fs::create_dir(&darktable).unwrap();
+        assert_eq!(find_sidecar(&image), Some(lightroom));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn precedence() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(1, 1, vec![0]).unwrap());
+        let embedded = MemoryMetadata::new()
+            .with_tag("Xmp.dc.subject", "embedded")
+            .with_tag("Xmp.xmp.Rating", "2");
+        let sidecar = MemoryMetadata::new()
+            .with_tag("Xmp.dc.subject", "sidecar")
+            .with_tag("Xmp.xmp.Label", "Red");
+        let merged = |precedence| {
+            let mut decoder: DecoderWithMetadata<Cursor<Vec<u8>>, _> =
+                DecoderWithMetadata::from_image(&image, embedded.clone()).unwrap();
+
+            decoder.merge_metadata(&sidecar, precedence).unwrap();
+            decoder.metadata
+        };
+
+        let metadata = merged(SidecarPrecedence::Embedded);
+        assert_eq!(metadata.read_tag("Xmp.dc.subject").unwrap(), "embedded");
+        assert_eq!(metadata.read_tag("Xmp.xmp.Label").unwrap(), "Red");
+        assert_eq!(metadata.read_tag("Xmp.xmp.Rating").unwrap(), "2");
+
+        let metadata = merged(SidecarPrecedence::Sidecar);
+        assert_eq!(metadata.read_tag("Xmp.dc.subject").unwrap(), "sidecar");
+        assert_eq!(metadata.read_tag("Xmp.xmp.Label").unwrap(), "Red");
+        assert_eq!(metadata.read_tag("Xmp.xmp.Rating").unwrap(), "2");
+    }
+
+    #[test]
+    fn load_and_save() {
+        let directory = directory("save");
+        let path = directory.join("img.png.xmp");
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(1, 1, vec![0]).unwrap());
+        let metadata = MemoryMetadata::new().with_tag("Iptc.Application2.City", "Paris");
+        let mut decoder = DecoderWithMetadata::from_image(&image, metadata).unwrap();
+
+        fs::write(&path, "").unwrap();
+        decoder.load_sidecar(&path, SidecarPrecedence::Embedded).unwrap();
+        assert_eq!(decoder.sidecar(), Some(path.as_path()));
+        decoder.save_sidecar(&path).unwrap();
+        //IPTC was synchronized before the sidecar was written
+        assert_eq!(decoder.metadata.read_tag("Xmp.photoshop.City").unwrap(), "Paris");
+        assert!(fs::read_to_string(&path).unwrap().contains("x:xmpmeta"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+#[cfg(feature = "exiv2")]
+use rexiv2::{self, TagType};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use std::result::Result;
+use super::tags::LAYOUT_TAGS;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::xmp::LangAlt;
+
+//Serializable copy of every EXIF, IPTC and XMP tag, applied back with DecoderWithMetadata::apply_snapshot
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetadataSnapshot {
+    pub tags: BTreeMap<String, TagSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagSnapshot {
+    //Name of the exiv2 type, like UnsignedRational or XmpBag, only informative when applied
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub value: TagValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum TagValue {
+    Text(String),
+    //Repeatable IPTC datasets and XMP arrays
+    Multiple(Vec<String>),
+    LangAlt(LangAlt),
+    //EXIF undefined values, as base64
+    Binary(#[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")] Vec<u8>),
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut data = Vec::with_capacity(encoded.len() * 3 / 4);
+
+    for chunk in encoded.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+
+        for (i, &symbol) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&c| c == symbol)?;
+
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            data.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(data)
+}
+
+fn serialize_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode_base64(data))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+
+    decode_base64(&encoded).ok_or_else(|| D::Error::custom("invalid base64 data"))
+}
+
+//exiv2 prints undefined values as their bytes in decimal, separated by spaces
+#[cfg(feature = "exiv2")]
+fn parse_undefined(value: &str) -> Option<Vec<u8>> {
+    value.split_whitespace().map(|byte| byte.parse().ok()).collect()
+}
+
+pub(crate) fn format_undefined(data: &[u8]) -> String {
+    data.iter().map(|byte| byte.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R> {
+    pub fn snapshot(&self) -> Result<MetadataSnapshot, Rexiv2ImageError> {
+        let mut snapshot = MetadataSnapshot::default();
+        let mut tags = self.metadata.get_exif_tags()?;
+
+        tags.extend(self.metadata.get_iptc_tags()?);
+        tags.extend(self.metadata.get_xmp_tags()?);
+        for tag in tags {
+            let tag_type = rexiv2::get_tag_type(&tag)?;
+            let value = self.snapshot_value(&tag, tag_type)?;
+
+            snapshot.tags.insert(tag, TagSnapshot { type_name: format!("{:?}", tag_type), value });
+        }
+        Ok(snapshot)
+    }
+
+    fn snapshot_value(&self, tag: &str, tag_type: TagType) -> Result<TagValue, Rexiv2ImageError> {
+        if rexiv2::is_iptc_tag(tag) {
+            let mut values = self.get_iim_values(tag)?;
+
+            if values.len() == 1 {
+                return Ok(TagValue::Text(values.remove(0)));
+            }
+            return Ok(TagValue::Multiple(values));
+        }
+        match tag_type {
+            TagType::XmpBag | TagType::XmpSeq => Ok(TagValue::Multiple(self.get_xmp_array(tag)?)),
+            TagType::LangAlt => Ok(TagValue::LangAlt(self.get_xmp_lang_alt(tag)?)),
+            TagType::Undefined => {
+                let value = self.metadata.get_tag_string(tag)?;
+
+                Ok(parse_undefined(&value).map_or(TagValue::Text(value), TagValue::Binary))
+            },
+            _ => Ok(TagValue::Text(self.metadata.get_tag_string(tag)?)),
+        }
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //Tags of the snapshot are written over the current ones, except those describing the pixel layout
+    //of the source. Call metadata.clear() before to get exactly the snapshot
+    pub fn apply_snapshot(&mut self, snapshot: &MetadataSnapshot) -> Result<(), Rexiv2ImageError> {
+        for (tag, tag_snapshot) in snapshot.tags.iter().filter(|&(tag, _)| !LAYOUT_TAGS.contains(&tag.as_str())) {
+            match tag_snapshot.value {
+                //IIM values are encoded in the charset of the file
+                TagValue::Text(ref text) if tag.starts_with("Iptc.") => self.set_iim_values(tag, &[text])?,
+                TagValue::Multiple(ref values) if tag.starts_with("Iptc.") => self.set_iim_values(tag, values)?,
+                TagValue::Text(ref text) => self.metadata.write_tag(tag, text)?,
+                TagValue::Multiple(ref values) => self.set_xmp_array(tag, values)?,
+                TagValue::LangAlt(ref entries) => self.set_xmp_lang_alt(tag, entries)?,
+                TagValue::Binary(ref data) => self.metadata.write_tag(tag, &format_undefined(data))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use serde_json;
+    use super::{MetadataSnapshot, TagSnapshot, TagValue, decode_base64, encode_base64};
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+    use super::super::xmp::LangAlt;
+
+    fn tag(type_name: &str, value: TagValue) -> TagSnapshot {
+        TagSnapshot { type_name: type_name.to_string(), value }
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"M"), "TQ==");
+        assert_eq!(encode_base64(b"Ma"), "TWE=");
+        assert_eq!(encode_base64(b"Man"), "TWFu");
+        assert_eq!(encode_base64(b"Many"), "TWFueQ==");
+        assert_eq!(encode_base64(&[0xff, 0xfe, 0x00, 0x80]), "//4AgA==");
+    }
+
+    #[test]
+    fn base64_lengths() {
+        let data: Vec<u8> = (0..=255).collect();
+
+        //One of each remainder modulo 3
+        for length in 0..10 {
+            let encoded = encode_base64(&data[..length]);
+
+            assert_eq!(encoded.len(), length.div_ceil(3) * 4);
+            assert_eq!(decode_base64(&encoded), Some(data[..length].to_vec()));
+        }
+        assert_eq!(decode_base64(&encode_base64(&data)), Some(data));
+    }
+
+    #[test]
+    fn invalid_base64() {
+        assert_eq!(decode_base64("TQ"), Some(b"M".to_vec()));
+        assert_eq!(decode_base64("TWFuT"), None);
+        assert_eq!(decode_base64("TW?u"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut title = LangAlt::new();
+        let mut snapshot = MetadataSnapshot::default();
+
+        title.insert("x-default".to_string(), "Sunset".to_string());
+        snapshot.tags.insert("Exif.Image.Make".to_string(), tag("Ascii", TagValue::Text("Camera".to_string())));
+        snapshot.tags.insert("Exif.Image.ImageWidth".to_string(), tag("UnsignedLong", TagValue::Text("640".to_string())));
+        snapshot.tags.insert("Exif.Photo.ExifVersion".to_string(), tag("Undefined", TagValue::Binary(b"0230".to_vec())));
+        snapshot.tags.insert("Iptc.Application2.City".to_string(), tag("String", TagValue::Text("Zürich".to_string())));
+        snapshot.tags.insert("Iptc.Application2.Keywords".to_string(),
+                             tag("String", TagValue::Multiple(vec!["sea".to_string(), "été".to_string()])));
+        snapshot.tags.insert("Xmp.dc.title".to_string(), tag("LangAlt", TagValue::LangAlt(title)));
+        snapshot.tags.insert("Xmp.dc.subject".to_string(),
+                             tag("XmpBag", TagValue::Multiple(vec!["sea".to_string(), "été".to_string()])));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains(r#"{"kind":"binary","data":"MDIzMA=="}"#));
+        let deserialized: MetadataSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, snapshot);
+
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![0, 255]).unwrap());
+        let metadata = MemoryMetadata::new().with_tag("Iptc.Envelope.CharacterSet", "\x1b.A");
+        let mut decoder = DecoderWithMetadata::from_image(&image, metadata).unwrap();
+
+        decoder.apply_snapshot(&deserialized).unwrap();
+        assert_eq!(decoder.metadata.read_tag("Exif.Image.Make").unwrap(), "Camera");
+        assert!(!decoder.metadata.has_tag("Exif.Image.ImageWidth"));
+        assert_eq!(decoder.metadata.read_tag("Exif.Photo.ExifVersion").unwrap(), "48 50 51 48");
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.title", "x-default").unwrap(), Some("Sunset".to_string()));
+        assert_eq!(decoder.get_xmp_array("Xmp.dc.subject").unwrap(), vec!["sea", "été"]);
+        //IIM values are written in the Latin-1 charset of the file
+        assert_eq!(decoder.metadata.read_tag_bytes("Iptc.Application2.City").unwrap(), vec![b"Z\xfcrich".to_vec()]);
+        assert_eq!(decoder.get_iim_values("Iptc.Application2.Keywords").unwrap(), vec!["sea", "été"]);
+        assert_eq!(decoder.metadata.read_tag_bytes("Iptc.Application2.Keywords").unwrap(),
+                   vec![b"sea".to_vec(), b"\xe9t\xe9".to_vec()]);
+    }
+}
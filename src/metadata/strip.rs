@@ -0,0 +1,315 @@
+use std::io::{Read, Seek};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+use super::iptc::IPTC_FIELDS;
+use super::tags::LAYOUT_TAGS;
+
+//Patterns match a tag name exactly, or any run of characters where they hold a '*'
+
+//Needed to display the image as intended, kept by every named profile
+const RENDERING_TAGS: [&str; 17] = [
+    "Exif.Image.Orientation",
+    "Exif.Image.InterColorProfile",
+    "Exif.Image.WhitePoint",
+    "Exif.Image.PrimaryChromaticities",
+    "Exif.Image.TransferFunction",
+    "Exif.Image.YCbCrCoefficients",
+    "Exif.Image.YCbCrSubSampling",
+    "Exif.Image.YCbCrPositioning",
+    "Exif.Photo.ColorSpace",
+    "Exif.Photo.Gamma",
+    "Exif.Iop.*",
+    "Iptc.Envelope.CharacterSet",
+    "Xmp.tiff.Orientation",
+    "Xmp.exif.ColorSpace",
+    "Xmp.photoshop.ICCProfile",
+    "Xmp.photoshop.ColorMode",
+    "Xmp.exif.Gamma",
+];
+
+const LOCATION_TAGS: [&str; 16] = [
+    "Exif.GPSInfo.*",
+    "Xmp.exif.GPS*",
+    "Iptc.Application2.City",
+    "Iptc.Application2.SubLocation",
+    "Iptc.Application2.ProvinceState",
+    "Iptc.Application2.CountryName",
+    "Iptc.Application2.CountryCode",
+    "Iptc.Application2.LocationCode",
+    "Iptc.Application2.LocationName",
+    "Xmp.photoshop.City",
+    "Xmp.photoshop.State",
+    "Xmp.photoshop.Country",
+    "Xmp.iptc.Location",
+    "Xmp.iptc.CountryCode",
+    "Xmp.iptcExt.LocationCreated*",
+    "Xmp.iptcExt.LocationShown*",
+];
+
+//Serial numbers, owner names, contact details and face regions, from the standards and the maker notes
+const PERSONAL_TAGS: [&str; 13] = [
+    "*SerialNumber*",
+    "*OwnerName*",
+    "Exif.Photo.ImageUniqueID",
+    "Exif.Canon.ImageUniqueID",
+    "Xmp.xmpMM.*",
+    "Xmp.mwg-rs.Regions*",
+    "Xmp.MP.RegionInfo*",
+    "Xmp.MPRI.*",
+    "Xmp.MPReg.*",
+    "Xmp.iptcExt.PersonInImage*",
+    "Xmp.iptc.CreatorContactInfo*",
+    "Iptc.Application2.Contact",
+    "Xmp.plus.ModelReleaseID*",
+];
+
+const COPYRIGHT_TAGS: [&str; 11] = [
+    "Exif.Image.Copyright",
+    "Exif.Image.Artist",
+    "Iptc.Application2.Copyright",
+    "Iptc.Application2.Byline",
+    "Iptc.Application2.Credit",
+    "Xmp.dc.rights*",
+    "Xmp.dc.creator*",
+    "Xmp.photoshop.Credit",
+    "Xmp.photoshop.Source",
+    "Xmp.xmpRights.*",
+    "Xmp.plus.Licensor*",
+];
+
+pub(crate) fn matches_pattern(pattern: &str, tag: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+
+    if !tag.starts_with(first) {
+        return false;
+    }
+    let mut rest = &tag[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(index) => rest = &rest[index + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        },
+    }
+}
+
+//Tags matching a deny pattern are removed unless they also match an allow pattern
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StripProfile {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl StripProfile {
+    pub fn new() -> StripProfile {
+        StripProfile::default()
+    }
+
+    //Everything except the rendering information
+    pub fn strip_all() -> StripProfile {
+        StripProfile::new().deny("*").allow_all(&RENDERING_TAGS)
+    }
+
+    pub fn strip_location() -> StripProfile {
+        StripProfile::new().deny_all(&LOCATION_TAGS)
+    }
+
+    //Location as well as the tags identifying the owner, the camera or the people in the image
+    pub fn strip_personal() -> StripProfile {
+        StripProfile::strip_location().deny_all(&PERSONAL_TAGS)
+    }
+
+    pub fn keep_copyright_only() -> StripProfile {
+        StripProfile::strip_all().allow_all(&COPYRIGHT_TAGS)
+    }
+
+    pub fn allow(mut self, pattern: &str) -> StripProfile {
+        self.allow.push(pattern.to_string());
+        self
+    }
+
+    pub fn deny(mut self, pattern: &str) -> StripProfile {
+        self.deny.push(pattern.to_string());
+        self
+    }
+
+    pub fn allow_all<S: AsRef<str>>(self, patterns: &[S]) -> StripProfile {
+        patterns.iter().fold(self, |profile, pattern| profile.allow(pattern.as_ref()))
+    }
+
+    pub fn deny_all<S: AsRef<str>>(self, patterns: &[S]) -> StripProfile {
+        patterns.iter().fold(self, |profile, pattern| profile.deny(pattern.as_ref()))
+    }
+
+    pub fn removes(&self, tag: &str) -> bool {
+        self.deny.iter().any(|pattern| matches_pattern(pattern, tag))
+            && !self.allow.iter().any(|pattern| matches_pattern(pattern, tag))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StripReport {
+    pub removed: Vec<String>,
+    //Set when the EXIF thumbnail is erased along with its tags, it is applied by save_metadata
+    pub thumbnail_erased: bool,
+}
+
+impl StripReport {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && !self.thumbnail_erased
+    }
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //Tags describing the layout of the file are never removed.
+    //IPTC fields lose both their IIM and XMP forms so that the sync done by save_metadata does not bring them back
+    pub fn strip_metadata(&mut self, profile: &StripProfile) -> Result<StripReport, Rexiv2ImageError> {
+        let mut report = StripReport::default();
+        for tag in self.metadata.list_tags()? {
+            let is_thumbnail = tag.starts_with("Exif.Thumbnail.");
+
+            if !profile.removes(&tag) || (LAYOUT_TAGS.contains(&tag.as_str()) && !is_thumbnail) {
+                continue;
+            }
+            if is_thumbnail {
+                report.thumbnail_erased = true;
+            } else {
+                self.metadata.remove_tag(&tag);
+            }
+            report.removed.push(tag);
+        }
+
+        for field in IPTC_FIELDS.iter() {
+            let counterparts = [(field.iim_tag(), field.xmp_tag()), (field.xmp_tag(), field.iim_tag())];
+
+            for &(removed, counterpart) in counterparts.iter() {
+                if report.removed.iter().any(|tag| tag == removed) && self.metadata.remove_tag(counterpart) {
+                    report.removed.push(counterpart.to_string());
+                }
+            }
+        }
+        if report.thumbnail_erased {
+            M::erase_thumbnail(self)?;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::io::Cursor;
+    use super::{StripProfile, StripReport, matches_pattern};
+    use super::super::DecoderWithMetadata;
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    fn decoder() -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![0, 255]).unwrap());
+        let metadata = MemoryMetadata::new()
+            .with_tag("Exif.GPSInfo.GPSLatitude", "48/1 51/1 30/1")
+            .with_tag("Exif.Image.Artist", "Ann")
+            .with_tag("Exif.Image.ImageWidth", "2")
+            .with_tag("Exif.Image.Make", "Camera")
+            .with_tag("Exif.Image.Orientation", "1")
+            .with_tag("Exif.Photo.BodySerialNumber", "1234")
+            .with_tag("Exif.Thumbnail.Compression", "6")
+            .with_tag("Iptc.Application2.City", "Paris")
+            .with_tag("Iptc.Envelope.CharacterSet", "\x1b%G")
+            .with_tag("Xmp.dc.rights", "lang=\"x-default\" Ann");
+
+        DecoderWithMetadata::from_image(&image, metadata).unwrap()
+    }
+
+    fn strip(profile: &StripProfile) -> (StripReport, Vec<String>) {
+        let mut decoder = decoder();
+        let report = decoder.strip_metadata(profile).unwrap();
+
+        (report, decoder.metadata.tags().keys().cloned().collect())
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("Exif.Image.Make", "Exif.Image.Make"));
+        assert!(!matches_pattern("Exif.Image.Make", "Exif.Image.Model"));
+        assert!(!matches_pattern("Exif.Image.Make", "Exif.Image.MakerNote"));
+        assert!(matches_pattern("Exif.GPSInfo.*", "Exif.GPSInfo.GPSLatitude"));
+        assert!(matches_pattern("Exif.GPSInfo.*", "Exif.GPSInfo."));
+        assert!(!matches_pattern("Exif.GPSInfo.*", "Exif.GPSInfo"));
+        assert!(matches_pattern("*SerialNumber", "Exif.Photo.BodySerialNumber"));
+        assert!(!matches_pattern("*SerialNumber", "Exif.Photo.SerialNumbers"));
+        assert!(matches_pattern("*SerialNumber*", "Exif.Photo.SerialNumbers"));
+        assert!(matches_pattern("Xmp.*.City", "Xmp.photoshop.City"));
+        assert!(!matches_pattern("Xmp.*.City", "Iptc.Application2.City"));
+        //The parts around a '*' do not overlap
+        assert!(!matches_pattern("Exif*Exif", "Exif"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn allow_overrides_deny() {
+        let profile = StripProfile::new().deny("Exif.*").allow("Exif.Image.Make");
+
+        assert!(profile.removes("Exif.Image.Model"));
+        assert!(!profile.removes("Exif.Image.Make"));
+        assert!(!profile.removes("Xmp.tiff.Model"));
+    }
+
+    #[test]
+    fn strip_location() {
+        let (report, tags) = strip(&StripProfile::strip_location());
+
+        assert_eq!(report.removed, vec!["Exif.GPSInfo.GPSLatitude", "Iptc.Application2.City"]);
+        assert!(!report.thumbnail_erased);
+        assert!(tags.contains(&"Exif.Image.Make".to_string()));
+    }
+
+    #[test]
+    fn strip_personal() {
+        let (report, _) = strip(&StripProfile::strip_personal());
+
+        assert_eq!(report.removed, vec!["Exif.GPSInfo.GPSLatitude", "Exif.Photo.BodySerialNumber",
+                                        "Iptc.Application2.City"]);
+    }
+
+    #[test]
+    fn strip_all() {
+        let (report, tags) = strip(&StripProfile::strip_all());
+
+        assert_eq!(report.removed, vec!["Exif.GPSInfo.GPSLatitude", "Exif.Image.Artist", "Exif.Image.Make",
+                                        "Exif.Photo.BodySerialNumber", "Exif.Thumbnail.Compression",
+                                        "Iptc.Application2.City", "Xmp.dc.rights"]);
+        assert!(report.thumbnail_erased);
+        assert_eq!(tags, vec!["Exif.Image.ImageWidth", "Exif.Image.Orientation", "Iptc.Envelope.CharacterSet"]);
+    }
+
+    #[test]
+    fn keep_copyright_only() {
+        let (report, tags) = strip(&StripProfile::keep_copyright_only());
+
+        assert_eq!(report.removed, vec!["Exif.GPSInfo.GPSLatitude", "Exif.Image.Make", "Exif.Photo.BodySerialNumber",
+                                        "Exif.Thumbnail.Compression", "Iptc.Application2.City"]);
+        assert_eq!(tags, vec!["Exif.Image.Artist", "Exif.Image.ImageWidth", "Exif.Image.Orientation",
+                              "Iptc.Envelope.CharacterSet", "Xmp.dc.rights"]);
+    }
+
+    #[test]
+    fn iptc_counterparts() {
+        let mut decoder = decoder();
+
+        decoder.metadata.write_tag("Xmp.photoshop.City", "Paris").unwrap();
+        let report = decoder.strip_metadata(&StripProfile::new().deny("Iptc.Application2.City")).unwrap();
+
+        assert_eq!(report.removed, vec!["Iptc.Application2.City", "Xmp.photoshop.City"]);
+        assert!(decoder.strip_metadata(&StripProfile::new()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+use std::io::{Read, Seek};
+use std::result::Result;
+use std::str::FromStr;
+pub use num_rational::Ratio;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+//Tags describing the pixel layout of the source, they are rewritten by the encoder and must not be copied
+pub const LAYOUT_TAGS: [&str; 21] = [
+    "Exif.Image.ImageWidth",
+    "Exif.Image.ImageLength",
+    "Exif.Image.BitsPerSample",
+    "Exif.Image.Compression",
+    "Exif.Image.PhotometricInterpretation",
+    "Exif.Image.StripOffsets",
+    "Exif.Image.SamplesPerPixel",
+    "Exif.Image.RowsPerStrip",
+    "Exif.Image.StripByteCounts",
+    "Exif.Image.PlanarConfiguration",
+    "Exif.Image.TileWidth",
+    "Exif.Image.TileLength",
+    "Exif.Image.TileOffsets",
+    "Exif.Image.TileByteCounts",
+    "Exif.Image.ExtraSamples",
+    "Exif.Image.SampleFormat",
+    "Exif.Image.JPEGInterchangeFormat",
+    "Exif.Image.JPEGInterchangeFormatLength",
+    "Exif.Photo.ComponentsConfiguration",
+    "Exif.Thumbnail.JPEGInterchangeFormat",
+    "Exif.Thumbnail.JPEGInterchangeFormatLength",
+];
+
+//Common EXIF tags, so that their keys do not have to be spelled by hand
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExifTag {
+    Make,
+    Model,
+    Software,
+    Artist,
+    Copyright,
+    ImageDescription,
+    CameraOwnerName,
+    BodySerialNumber,
+    LensMake,
+    LensModel,
+    LensSerialNumber,
+    ExposureTime,
+    FNumber,
+    ExposureProgram,
+    IsoSpeedRatings,
+    ExposureBiasValue,
+    MaxApertureValue,
+    MeteringMode,
+    Flash,
+    FocalLength,
+    FocalLengthIn35mmFilm,
+    WhiteBalance,
+    ColorSpace,
+    PixelXDimension,
+    PixelYDimension,
+}
+
+impl ExifTag {
+    pub fn key(&self) -> &'static str {
+        match *self {
+            ExifTag::Make => "Exif.Image.Make",
+            ExifTag::Model => "Exif.Image.Model",
+            ExifTag::Software => "Exif.Image.Software",
+            ExifTag::Artist => "Exif.Image.Artist",
+            ExifTag::Copyright => "Exif.Image.Copyright",
+            ExifTag::ImageDescription => "Exif.Image.ImageDescription",
+            ExifTag::CameraOwnerName => "Exif.Photo.CameraOwnerName",
+            ExifTag::BodySerialNumber => "Exif.Photo.BodySerialNumber",
+            ExifTag::LensMake => "Exif.Photo.LensMake",
+            ExifTag::LensModel => "Exif.Photo.LensModel",
+            ExifTag::LensSerialNumber => "Exif.Photo.LensSerialNumber",
+            ExifTag::ExposureTime => "Exif.Photo.ExposureTime",
+            ExifTag::FNumber => "Exif.Photo.FNumber",
+            ExifTag::ExposureProgram => "Exif.Photo.ExposureProgram",
+            ExifTag::IsoSpeedRatings => "Exif.Photo.ISOSpeedRatings",
+            ExifTag::ExposureBiasValue => "Exif.Photo.ExposureBiasValue",
+            ExifTag::MaxApertureValue => "Exif.Photo.MaxApertureValue",
+            ExifTag::MeteringMode => "Exif.Photo.MeteringMode",
+            ExifTag::Flash => "Exif.Photo.Flash",
+            ExifTag::FocalLength => "Exif.Photo.FocalLength",
+            ExifTag::FocalLengthIn35mmFilm => "Exif.Photo.FocalLengthIn35mmFilm",
+            ExifTag::WhiteBalance => "Exif.Photo.WhiteBalance",
+            ExifTag::ColorSpace => "Exif.Photo.ColorSpace",
+            ExifTag::PixelXDimension => "Exif.Photo.PixelXDimension",
+            ExifTag::PixelYDimension => "Exif.Photo.PixelYDimension",
+        }
+    }
+}
+
+//Rationals are written "numerator/denominator" by exiv2, "0/0" is used by cameras for unknown values and read as 0
+pub(crate) fn parse_ratio(value: &str) -> Option<Ratio<i32>> {
+    let mut parts = value.trim().splitn(2, '/');
+    let numerator = parts.next()?.trim().parse().ok()?;
+    let denominator = match parts.next() {
+        Some(denominator) => denominator.trim().parse().ok()?,
+        None => 1,
+    };
+
+    match (numerator, denominator) {
+        (0, 0) => Some(Ratio::from_integer(0)),
+        (_, 0) => None,
+        _ => Some(Ratio::new(numerator, denominator)),
+    }
+}
+
+//Approximates a decimal with the precision EXIF writers usually use for apertures and focal lengths
+fn ratio_from_f64(value: f64) -> Ratio<i32> {
+    Ratio::new((value * 100.0).round() as i32, 100)
+}
+
+fn ratio_to_f64(ratio: Ratio<i32>) -> f64 {
+    *ratio.numer() as f64 / *ratio.denom() as f64
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //None when the tag is absent
+    pub(crate) fn read_optional_tag(&self, tag: &str) -> Result<Option<String>, Rexiv2ImageError> {
+        match self.metadata.read_tag(tag) {
+            Ok(value) => Ok(Some(value)),
+            Err(Rexiv2ImageError::TagNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_exif_string(&self, tag: ExifTag) -> Result<Option<String>, Rexiv2ImageError> {
+        self.read_optional_tag(tag.key())
+    }
+
+    pub fn set_exif_string(&mut self, tag: ExifTag, value: &str) -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag(tag.key(), value)
+    }
+
+    //Tags holding several components, like ISOSpeedRatings, give their first one
+    pub fn get_exif_number<T: FromStr>(&self, tag: ExifTag) -> Result<Option<T>, Rexiv2ImageError> {
+        match self.get_exif_string(tag)? {
+            None => Ok(None),
+            Some(value) => value.split_whitespace().next()
+                .and_then(|first| first.parse().ok())
+                .map(Some)
+                .ok_or_else(|| Rexiv2ImageError::InvalidTagValue(tag.key().to_string(), value)),
+        }
+    }
+
+    pub fn set_exif_number(&mut self, tag: ExifTag, value: u32) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(tag, &value.to_string())
+    }
+
+    pub fn get_exif_ratio(&self, tag: ExifTag) -> Result<Option<Ratio<i32>>, Rexiv2ImageError> {
+        match self.get_exif_string(tag)? {
+            None => Ok(None),
+            Some(value) => parse_ratio(&value)
+                .map(Some)
+                .ok_or_else(|| Rexiv2ImageError::InvalidTagValue(tag.key().to_string(), value)),
+        }
+    }
+
+    pub fn set_exif_ratio(&mut self, tag: ExifTag, value: Ratio<i32>) -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag(tag.key(), &format!("{}/{}", value.numer(), value.denom()))
+    }
+
+    pub fn clear_exif_tag(&mut self, tag: ExifTag) -> bool {
+        self.metadata.remove_tag(tag.key())
+    }
+
+    pub fn make(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.get_exif_string(ExifTag::Make)
+    }
+
+    pub fn set_make(&mut self, make: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(ExifTag::Make, make)
+    }
+
+    pub fn model(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.get_exif_string(ExifTag::Model)
+    }
+
+    pub fn set_model(&mut self, model: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(ExifTag::Model, model)
+    }
+
+    pub fn lens_model(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.get_exif_string(ExifTag::LensModel)
+    }
+
+    pub fn set_lens_model(&mut self, lens_model: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(ExifTag::LensModel, lens_model)
+    }
+
+    pub fn artist(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.get_exif_string(ExifTag::Artist)
+    }
+
+    pub fn set_artist(&mut self, artist: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(ExifTag::Artist, artist)
+    }
+
+    pub fn copyright(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.get_exif_string(ExifTag::Copyright)
+    }
+
+    pub fn set_copyright(&mut self, copyright: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_string(ExifTag::Copyright, copyright)
+    }
+
+    //In seconds
+    pub fn exposure_time(&self) -> Result<Option<Ratio<i32>>, Rexiv2ImageError> {
+        self.get_exif_ratio(ExifTag::ExposureTime)
+    }
+
+    pub fn set_exposure_time(&mut self, exposure_time: Ratio<i32>) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_ratio(ExifTag::ExposureTime, exposure_time)
+    }
+
+    pub fn f_number(&self) -> Result<Option<f64>, Rexiv2ImageError> {
+        Ok(self.get_exif_ratio(ExifTag::FNumber)?.map(ratio_to_f64))
+    }
+
+    pub fn set_f_number(&mut self, f_number: f64) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_ratio(ExifTag::FNumber, ratio_from_f64(f_number))
+    }
+
+    pub fn iso(&self) -> Result<Option<u32>, Rexiv2ImageError> {
+        self.get_exif_number(ExifTag::IsoSpeedRatings)
+    }
+
+    pub fn set_iso(&mut self, iso: u32) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_number(ExifTag::IsoSpeedRatings, iso)
+    }
+
+    //In millimeters
+    pub fn focal_length(&self) -> Result<Option<f64>, Rexiv2ImageError> {
+        Ok(self.get_exif_ratio(ExifTag::FocalLength)?.map(ratio_to_f64))
+    }
+
+    pub fn set_focal_length(&mut self, focal_length: f64) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_ratio(ExifTag::FocalLength, ratio_from_f64(focal_length))
+    }
+
+    pub fn focal_length_35mm(&self) -> Result<Option<u32>, Rexiv2ImageError> {
+        self.get_exif_number(ExifTag::FocalLengthIn35mmFilm)
+    }
+
+    pub fn set_focal_length_35mm(&mut self, focal_length: u32) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_number(ExifTag::FocalLengthIn35mmFilm, focal_length)
+    }
+
+    //In EV
+    pub fn exposure_bias(&self) -> Result<Option<Ratio<i32>>, Rexiv2ImageError> {
+        self.get_exif_ratio(ExifTag::ExposureBiasValue)
+    }
+
+    pub fn set_exposure_bias(&mut self, exposure_bias: Ratio<i32>) -> Result<(), Rexiv2ImageError> {
+        self.set_exif_ratio(ExifTag::ExposureBiasValue, exposure_bias)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use num_rational::Ratio;
+    use std::io::Cursor;
+    use super::{ExifTag, parse_ratio};
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::super::memory::MemoryMetadata;
+
+    fn decoder(metadata: MemoryMetadata) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_image(&DynamicImage::ImageLuma8(GrayImage::new(1, 1)), metadata).unwrap()
+    }
+
+    fn assert_invalid<T: ::std::fmt::Debug>(result: Result<T, Rexiv2ImageError>, tag: &str, value: &str) {
+        match result {
+            Err(Rexiv2ImageError::InvalidTagValue(ref invalid_tag, ref invalid_value)) => {
+                assert_eq!(invalid_tag, tag);
+                assert_eq!(invalid_value, value);
+            },
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(parse_ratio("1/250"), Some(Ratio::new(1, 250)));
+        assert_eq!(parse_ratio(" 28 / 10 "), Some(Ratio::new(14, 5)));
+        assert_eq!(parse_ratio("-1/3"), Some(Ratio::new(-1, 3)));
+        assert_eq!(parse_ratio("8"), Some(Ratio::from_integer(8)));
+        assert_eq!(parse_ratio("0/0"), Some(Ratio::from_integer(0)));
+        assert_eq!(parse_ratio("1/0"), None);
+        assert_eq!(parse_ratio("1.5"), None);
+        assert_eq!(parse_ratio("f/2"), None);
+        assert_eq!(parse_ratio(""), None);
+    }
+
+    #[test]
+    fn rational_tags() {
+        let mut decoder = decoder(MemoryMetadata::new()
+            .with_tag("Exif.Photo.ExposureTime", "1/250")
+            .with_tag("Exif.Photo.FNumber", "28/10")
+            .with_tag("Exif.Photo.FocalLength", "0/0"));
+
+        assert_eq!(decoder.exposure_time().unwrap(), Some(Ratio::new(1, 250)));
+        assert_eq!(decoder.f_number().unwrap(), Some(2.8));
+        assert_eq!(decoder.focal_length().unwrap(), Some(0.0));
+        assert_eq!(decoder.exposure_bias().unwrap(), None);
+
+        decoder.set_f_number(5.6).unwrap();
+        decoder.set_exposure_bias(Ratio::new(-2, 6)).unwrap();
+        assert_eq!(decoder.metadata.tags()["Exif.Photo.FNumber"], vec!["28/5"]);
+        assert_eq!(decoder.metadata.tags()["Exif.Photo.ExposureBiasValue"], vec!["-1/3"]);
+        assert_eq!(decoder.f_number().unwrap(), Some(5.6));
+    }
+
+    #[test]
+    fn ascii_tags() {
+        let mut decoder = decoder(MemoryMetadata::new().with_tag("Exif.Image.Make", "Camera"));
+
+        assert_eq!(decoder.make().unwrap(), Some("Camera".to_string()));
+        assert_eq!(decoder.model().unwrap(), None);
+        decoder.set_model("X100").unwrap();
+        decoder.set_artist("Ann").unwrap();
+        assert_eq!(decoder.get_exif_string(ExifTag::Model).unwrap(), Some("X100".to_string()));
+        assert_eq!(decoder.artist().unwrap(), Some("Ann".to_string()));
+        assert!(decoder.clear_exif_tag(ExifTag::Artist));
+        assert!(!decoder.clear_exif_tag(ExifTag::Artist));
+        assert_eq!(decoder.artist().unwrap(), None);
+    }
+
+    #[test]
+    fn short_tags() {
+        let mut decoder = decoder(MemoryMetadata::new().with_tag("Exif.Photo.ISOSpeedRatings", "200 400"));
+
+        //The first component
+        assert_eq!(decoder.iso().unwrap(), Some(200));
+        decoder.set_iso(800).unwrap();
+        decoder.set_focal_length_35mm(50).unwrap();
+        assert_eq!(decoder.iso().unwrap(), Some(800));
+        assert_eq!(decoder.focal_length_35mm().unwrap(), Some(50));
+        assert_eq!(decoder.get_exif_number::<u16>(ExifTag::Flash).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let decoder = decoder(MemoryMetadata::new()
+            .with_tag("Exif.Photo.ISOSpeedRatings", "high")
+            .with_tag("Exif.Photo.ExposureTime", "1/0")
+            .with_tag("Exif.Photo.FNumber", "f/2.8")
+            .with_tag("Exif.Photo.FocalLengthIn35mmFilm", ""));
+
+        assert_invalid(decoder.iso(), "Exif.Photo.ISOSpeedRatings", "high");
+        assert_invalid(decoder.exposure_time(), "Exif.Photo.ExposureTime", "1/0");
+        assert_invalid(decoder.f_number(), "Exif.Photo.FNumber", "f/2.8");
+        assert_invalid(decoder.focal_length_35mm(), "Exif.Photo.FocalLengthIn35mmFilm", "");
+    }
+}
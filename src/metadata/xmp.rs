@@ -0,0 +1,340 @@
+#[cfg(feature = "exiv2")]
+use rexiv2::{self, Metadata, TagType};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use std::result::Result;
+use super::{DecoderWithMetadata, Rexiv2ImageError};
+use super::backend::MetadataBackend;
+
+pub const DEFAULT_LANGUAGE: &str = "x-default";
+
+//Language to text, the x-default entry is the one shown when no language matches
+pub type LangAlt = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum XmpValue {
+    Text(String),
+    //Unordered array, like dc:subject
+    Bag(Vec<String>),
+    //Ordered array, like dc:creator
+    Seq(Vec<String>),
+    LangAlt(LangAlt),
+    //Fields of a struct by their qualified name, nested fields are given by their path from the struct
+    Struct(BTreeMap<String, String>),
+}
+
+//Registers a namespace so that its properties can be read and written as Xmp.<prefix>.<property>,
+//exiv2 has no type information for them so they are text or structs
+#[cfg(feature = "exiv2")]
+pub fn register_xmp_namespace(uri: &str, prefix: &str) -> Result<(), Rexiv2ImageError> {
+    Ok(rexiv2::register_xmp_namespace(uri, prefix)?)
+}
+
+#[cfg(feature = "exiv2")]
+pub fn unregister_xmp_namespace(uri: &str) -> Result<(), Rexiv2ImageError> {
+    Ok(rexiv2::unregister_xmp_namespace(uri)?)
+}
+
+//exiv2 writes language alternatives as `lang="x-default" text, lang="fr-FR" texte`
+fn parse_lang_alt(value: &str) -> LangAlt {
+    let mut entries = LangAlt::new();
+
+    for entry in value.split(", lang=\"").map(|entry| entry.trim_start_matches("lang=\"")) {
+        match entry.find("\" ") {
+            Some(end) => entries.insert(entry[..end].to_string(), entry[end + 2..].to_string()),
+            None if entry.ends_with('"') => entries.insert(entry[..entry.len() - 1].to_string(), String::new()),
+            None => entries.insert(DEFAULT_LANGUAGE.to_string(), entry.to_string()),
+        };
+    }
+    entries
+}
+
+impl<R: Read + Seek, M: MetadataBackend> DecoderWithMetadata<R, M> {
+    //Arrays are written with the type exiv2 knows for the property, bags and sequences are only told apart on read
+    pub fn set_xmp_value(&mut self, tag: &str, value: &XmpValue) -> Result<(), Rexiv2ImageError> {
+        match *value {
+            XmpValue::Text(ref text) => self.metadata.write_tag(tag, text),
+            XmpValue::Bag(ref values) | XmpValue::Seq(ref values) => self.set_xmp_array(tag, values),
+            XmpValue::LangAlt(ref entries) => self.set_xmp_lang_alt(tag, entries),
+            XmpValue::Struct(ref fields) => {
+                self.clear_xmp_struct(tag)?;
+                for (field, value) in fields {
+                    self.set_xmp_struct_field(tag, field, value)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    pub fn get_xmp_array(&self, tag: &str) -> Result<Vec<String>, Rexiv2ImageError> {
+        self.metadata.read_tag_multiple(tag)
+    }
+
+    pub fn set_xmp_array<S: AsRef<str>>(&mut self, tag: &str, values: &[S]) -> Result<(), Rexiv2ImageError> {
+        let values: Vec<&str> = values.iter().map(|value| value.as_ref()).collect();
+
+        self.metadata.write_tag_multiple(tag, &values)
+    }
+
+    pub fn get_xmp_lang_alt(&self, tag: &str) -> Result<LangAlt, Rexiv2ImageError> {
+        Ok(self.read_optional_tag(tag)?.map_or_else(LangAlt::new, |value| parse_lang_alt(&value)))
+    }
+
+    //Falls back on the default language, then on any language
+    pub fn get_xmp_lang_alt_entry(&self, tag: &str, language: &str) -> Result<Option<String>, Rexiv2ImageError> {
+        let mut entries = self.get_xmp_lang_alt(tag)?;
+        let text = entries.remove(language)
+            .or_else(|| entries.remove(DEFAULT_LANGUAGE))
+            .or_else(|| entries.into_iter().next().map(|(_, text)| text));
+
+        Ok(text)
+    }
+
+    pub fn set_xmp_lang_alt(&mut self, tag: &str, entries: &LangAlt) -> Result<(), Rexiv2ImageError> {
+        self.metadata.remove_tag(tag);
+        for (language, text) in entries {
+            self.set_xmp_lang_alt_entry(tag, language, text)?;
+        }
+        Ok(())
+    }
+
+    //Other languages already present are kept
+    pub fn set_xmp_lang_alt_entry(&mut self, tag: &str, language: &str, text: &str) -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag(tag, &format!("lang=\"{}\" {}", language, text))
+    }
+
+    //exiv2 flattens structs into one tag per field: Xmp.<prefix>.<struct>/<field prefix>:<field>
+    pub fn get_xmp_struct(&self, tag: &str) -> Result<BTreeMap<String, String>, Rexiv2ImageError> {
+        let prefix = format!("{}/", tag);
+        let mut fields = BTreeMap::new();
+
+        for field_tag in self.metadata.list_tags()?.into_iter().filter(|field_tag| field_tag.starts_with(&prefix)) {
+            let value = self.metadata.read_tag(&field_tag)?;
+
+            fields.insert(field_tag[prefix.len()..].to_string(), value);
+        }
+        Ok(fields)
+    }
+
+    //`field` is qualified by its namespace prefix, like "stDim:w"
+    pub fn set_xmp_struct_field(&mut self, tag: &str, field: &str, value: &str) -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag(&format!("{}/{}", tag, field), value)
+    }
+
+    //Whether the struct was present
+    pub fn clear_xmp_struct(&mut self, tag: &str) -> Result<bool, Rexiv2ImageError> {
+        let prefix = format!("{}/", tag);
+        let fields = self.metadata.list_tags()?;
+        let mut cleared = self.metadata.remove_tag(tag);
+
+        for field_tag in fields.iter().filter(|field_tag| field_tag.starts_with(&prefix)) {
+            cleared |= self.metadata.remove_tag(field_tag);
+        }
+        Ok(cleared)
+    }
+
+    pub fn keywords(&self) -> Result<Vec<String>, Rexiv2ImageError> {
+        self.get_xmp_array("Xmp.dc.subject")
+    }
+
+    pub fn set_keywords<S: AsRef<str>>(&mut self, keywords: &[S]) -> Result<(), Rexiv2ImageError> {
+        self.set_xmp_array("Xmp.dc.subject", keywords)
+    }
+
+    //Lightroom hierarchy, levels separated by '|'
+    pub fn hierarchical_subjects(&self) -> Result<Vec<Vec<String>>, Rexiv2ImageError> {
+        Ok(self.get_xmp_array("Xmp.lr.hierarchicalSubject")?.iter()
+           .map(|subject| subject.split('|').map(|level| level.to_string()).collect())
+           .collect())
+    }
+
+    pub fn set_hierarchical_subjects<S: AsRef<str>>(&mut self, subjects: &[Vec<S>]) -> Result<(), Rexiv2ImageError> {
+        let subjects: Vec<String> = subjects.iter()
+            .map(|levels| levels.iter().map(|level| level.as_ref()).collect::<Vec<&str>>().join("|"))
+            .collect();
+
+        self.set_xmp_array("Xmp.lr.hierarchicalSubject", &subjects)
+    }
+
+    //From -1 (rejected) to 5 stars
+    pub fn rating(&self) -> Result<Option<i32>, Rexiv2ImageError> {
+        let value = match self.read_optional_tag("Xmp.xmp.Rating")? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        value.trim().parse::<f64>()
+            .map(|rating| Some(rating.round() as i32))
+            .map_err(|_| Rexiv2ImageError::InvalidTagValue("Xmp.xmp.Rating".to_string(), value))
+    }
+
+    pub fn set_rating(&mut self, rating: i32) -> Result<(), Rexiv2ImageError> {
+        if !(-1..=5).contains(&rating) {
+            return Err(Rexiv2ImageError::InvalidTagValue("Xmp.xmp.Rating".to_string(), rating.to_string()));
+        }
+        self.metadata.write_tag("Xmp.xmp.Rating", &rating.to_string())
+    }
+
+    //Color label, free text such as "Red"
+    pub fn label(&self) -> Result<Option<String>, Rexiv2ImageError> {
+        self.read_optional_tag("Xmp.xmp.Label")
+    }
+
+    pub fn set_label(&mut self, label: &str) -> Result<(), Rexiv2ImageError> {
+        self.metadata.write_tag("Xmp.xmp.Label", label)
+    }
+
+    pub fn titles(&self) -> Result<LangAlt, Rexiv2ImageError> {
+        self.get_xmp_lang_alt("Xmp.dc.title")
+    }
+
+    pub fn set_title(&mut self, language: &str, title: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_xmp_lang_alt_entry("Xmp.dc.title", language, title)
+    }
+
+    pub fn descriptions(&self) -> Result<LangAlt, Rexiv2ImageError> {
+        self.get_xmp_lang_alt("Xmp.dc.description")
+    }
+
+    pub fn set_description(&mut self, language: &str, description: &str) -> Result<(), Rexiv2ImageError> {
+        self.set_xmp_lang_alt_entry("Xmp.dc.description", language, description)
+    }
+}
+
+#[cfg(feature = "exiv2")]
+impl<R: Read + Seek> DecoderWithMetadata<R, Metadata> {
+    //The kind of value comes from the XMP schemas known by exiv2
+    pub fn get_xmp_value(&self, tag: &str) -> Result<Option<XmpValue>, Rexiv2ImageError> {
+        if !self.metadata.has_tag(tag) {
+            let fields = self.get_xmp_struct(tag)?;
+
+            return Ok(if fields.is_empty() { None } else { Some(XmpValue::Struct(fields)) });
+        }
+        Ok(Some(match rexiv2::get_tag_type(tag)? {
+            TagType::XmpBag => XmpValue::Bag(self.metadata.get_tag_multiple_strings(tag)?),
+            TagType::XmpSeq => XmpValue::Seq(self.metadata.get_tag_multiple_strings(tag)?),
+            TagType::LangAlt => XmpValue::LangAlt(self.get_xmp_lang_alt(tag)?),
+            _ => XmpValue::Text(self.metadata.get_tag_string(tag)?),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+    use super::{LangAlt, XmpValue, parse_lang_alt};
+    use super::super::{DecoderWithMetadata, Rexiv2ImageError};
+    use super::super::backend::MetadataBackend;
+    use super::super::memory::MemoryMetadata;
+
+    fn decoder(metadata: MemoryMetadata) -> DecoderWithMetadata<Cursor<Vec<u8>>, MemoryMetadata> {
+        DecoderWithMetadata::from_image(&DynamicImage::ImageLuma8(GrayImage::from_raw(1, 1, vec![0]).unwrap()),
+                                        metadata).unwrap()
+    }
+
+    fn lang_alt(entries: &[(&str, &str)]) -> LangAlt {
+        entries.iter().map(|&(language, text)| (language.to_string(), text.to_string())).collect()
+    }
+
+    #[test]
+    fn lang_alt_values() {
+        assert_eq!(parse_lang_alt("lang=\"x-default\" Hello, lang=\"fr-FR\" Bonjour"),
+                   lang_alt(&[("x-default", "Hello"), ("fr-FR", "Bonjour")]));
+        assert_eq!(parse_lang_alt("lang=\"en-US\" He said \"hi\""), lang_alt(&[("en-US", "He said \"hi\"")]));
+        assert_eq!(parse_lang_alt("lang=\"fr-FR\""), lang_alt(&[("fr-FR", "")]));
+        //Plain text is the default entry
+        assert_eq!(parse_lang_alt("Hello, world"), lang_alt(&[("x-default", "Hello, world")]));
+    }
+
+    #[test]
+    fn lang_alt_fallback() {
+        let decoder = decoder(MemoryMetadata::new()
+            .with_tag("Xmp.dc.title", "lang=\"fr-FR\" Bonjour, lang=\"x-default\" Hello")
+            .with_tag("Xmp.dc.description", "lang=\"fr-FR\" Soleil"));
+
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.title", "fr-FR").unwrap(), Some("Bonjour".to_string()));
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.title", "de-DE").unwrap(), Some("Hello".to_string()));
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.description", "de-DE").unwrap(), Some("Soleil".to_string()));
+        assert_eq!(decoder.get_xmp_lang_alt_entry("Xmp.dc.rights", "de-DE").unwrap(), None);
+        assert_eq!(decoder.titles().unwrap(), lang_alt(&[("fr-FR", "Bonjour"), ("x-default", "Hello")]));
+        assert!(decoder.descriptions().unwrap().contains_key("fr-FR"));
+    }
+
+    #[test]
+    fn set_lang_alt() {
+        let mut decoder = decoder(MemoryMetadata::new());
+
+        decoder.set_title("x-default", "Sunset \"golden\"").unwrap();
+        assert_eq!(decoder.metadata.read_tag("Xmp.dc.title").unwrap(), "lang=\"x-default\" Sunset \"golden\"");
+        assert_eq!(decoder.titles().unwrap(), lang_alt(&[("x-default", "Sunset \"golden\"")]));
+        decoder.set_xmp_lang_alt("Xmp.dc.title", &LangAlt::new()).unwrap();
+        assert!(!decoder.metadata.has_tag("Xmp.dc.title"));
+    }
+
+    #[test]
+    fn arrays() {
+        let mut decoder = decoder(MemoryMetadata::new());
+
+        assert!(decoder.keywords().unwrap().is_empty());
+        decoder.set_keywords(&["sea", "sky"]).unwrap();
+        assert_eq!(decoder.keywords().unwrap(), vec!["sea", "sky"]);
+        decoder.set_hierarchical_subjects(&[vec!["Places", "France"], vec!["Sea"]]).unwrap();
+        assert_eq!(decoder.get_xmp_array("Xmp.lr.hierarchicalSubject").unwrap(), vec!["Places|France", "Sea"]);
+        assert_eq!(decoder.hierarchical_subjects().unwrap(),
+                   vec![vec!["Places".to_string(), "France".to_string()], vec!["Sea".to_string()]]);
+        //An empty array removes the property
+        decoder.set_keywords::<&str>(&[]).unwrap();
+        assert!(!decoder.metadata.has_tag("Xmp.dc.subject"));
+        decoder.set_xmp_value("Xmp.dc.creator", &XmpValue::Seq(vec!["Ann".to_string()])).unwrap();
+        assert_eq!(decoder.get_xmp_array("Xmp.dc.creator").unwrap(), vec!["Ann"]);
+    }
+
+    #[test]
+    fn structs() {
+        let mut decoder = decoder(MemoryMetadata::new().with_tag("Xmp.xmpTPg.MaxPageSizeOther/stDim:w", "1"));
+        let fields: BTreeMap<String, String> = vec![("stDim:w", "210"), ("stDim:h", "297"), ("stDim:unit", "mm")]
+            .into_iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect();
+
+        decoder.set_xmp_value("Xmp.xmpTPg.MaxPageSize", &XmpValue::Struct(fields.clone())).unwrap();
+        assert_eq!(decoder.get_xmp_struct("Xmp.xmpTPg.MaxPageSize").unwrap(), fields);
+        assert_eq!(decoder.metadata.read_tag("Xmp.xmpTPg.MaxPageSize/stDim:unit").unwrap(), "mm");
+        //Only the fields of this struct, not those of a struct whose name starts the same
+        assert!(decoder.clear_xmp_struct("Xmp.xmpTPg.MaxPageSize").unwrap());
+        assert!(decoder.get_xmp_struct("Xmp.xmpTPg.MaxPageSize").unwrap().is_empty());
+        assert!(decoder.metadata.has_tag("Xmp.xmpTPg.MaxPageSizeOther/stDim:w"));
+        assert!(!decoder.clear_xmp_struct("Xmp.xmpTPg.MaxPageSize").unwrap());
+    }
+
+    #[test]
+    fn rating() {
+        let mut decoder = decoder(MemoryMetadata::new().with_tag("Xmp.xmp.Rating", "3.6"));
+
+        assert_eq!(decoder.rating().unwrap(), Some(4));
+        decoder.set_rating(-1).unwrap();
+        assert_eq!(decoder.rating().unwrap(), Some(-1));
+        match decoder.set_rating(6) {
+            Err(Rexiv2ImageError::InvalidTagValue(ref tag, ref value)) => {
+                assert_eq!(tag, "Xmp.xmp.Rating");
+                assert_eq!(value, "6");
+            },
+            result => panic!("unexpected {:?}", result),
+        }
+        decoder.metadata.write_tag("Xmp.xmp.Rating", "high").unwrap();
+        assert!(decoder.rating().is_err());
+        decoder.metadata.remove_tag("Xmp.xmp.Rating");
+        assert_eq!(decoder.rating().unwrap(), None);
+    }
+
+    #[test]
+    fn label() {
+        let mut decoder = decoder(MemoryMetadata::new());
+
+        assert_eq!(decoder.label().unwrap(), None);
+        decoder.set_label("Red").unwrap();
+        assert_eq!(decoder.label().unwrap(), Some("Red".to_string()));
+    }
+}
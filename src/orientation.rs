@@ -0,0 +1,236 @@
+#[cfg(feature = "exiv2")]
+pub use rexiv2::Orientation;
+use image::DynamicImage;
+
+//Same as the rexiv2 enumeration, whose values are the ones of the EXIF tag
+#[cfg(not(feature = "exiv2"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Orientation {
+    Unspecified,
+    Normal,
+    HorizontalFlip,
+    Rotate180,
+    VerticalFlip,
+    Rotate90HorizontalFlip,
+    Rotate90,
+    Rotate90VerticalFlip,
+    Rotate270,
+}
+
+//The eight EXIF orientations form the symmetry group of the rectangle,
+//every one of them is a horizontal flip (or not) followed by a number of clockwise quarter turns.
+
+fn decompose(orientation: Orientation) -> (bool, u8) {
+    match orientation {
+        Orientation::Unspecified | Orientation::Normal => (false, 0),
+        Orientation::HorizontalFlip => (true, 0),
+        Orientation::Rotate180 => (false, 2),
+        Orientation::VerticalFlip => (true, 2),
+        Orientation::Rotate90HorizontalFlip => (true, 3),
+        Orientation::Rotate90 => (false, 1),
+        Orientation::Rotate90VerticalFlip => (true, 1),
+        Orientation::Rotate270 => (false, 3),
+    }
+}
+
+fn recompose(flip: bool, quarter_turns: u8) -> Orientation {
+    match (flip, quarter_turns % 4) {
+        (false, 0) => Orientation::Normal,
+        (true, 0) => Orientation::HorizontalFlip,
+        (false, 2) => Orientation::Rotate180,
+        (true, 2) => Orientation::VerticalFlip,
+        (true, 3) => Orientation::Rotate90HorizontalFlip,
+        (false, 1) => Orientation::Rotate90,
+        (true, 1) => Orientation::Rotate90VerticalFlip,
+        _ => Orientation::Rotate270,
+    }
+}
+
+//Orientation stored as the value of the EXIF tag, anything out of range is unspecified
+pub fn from_exif(value: u16) -> Orientation {
+    match value {
+        1 => Orientation::Normal,
+        2 => Orientation::HorizontalFlip,
+        3 => Orientation::Rotate180,
+        4 => Orientation::VerticalFlip,
+        5 => Orientation::Rotate90HorizontalFlip,
+        6 => Orientation::Rotate90,
+        7 => Orientation::Rotate90VerticalFlip,
+        8 => Orientation::Rotate270,
+        _ => Orientation::Unspecified,
+    }
+}
+
+//The transformation equivalent to applying `first` and then `then`
+pub fn compose(first: Orientation, then: Orientation) -> Orientation {
+    let (first_flip, first_turns) = decompose(first);
+    let (then_flip, then_turns) = decompose(then);
+
+    //A flip reverses the direction of the quarter turns done before it
+    if then_flip {
+        recompose(!first_flip, then_turns + 4 - first_turns)
+    } else {
+        recompose(first_flip, then_turns + first_turns)
+    }
+}
+
+//Flipped orientations are their own inverse
+pub fn inverse(orientation: Orientation) -> Orientation {
+    match decompose(orientation) {
+        (true, quarter_turns) => recompose(true, quarter_turns),
+        (false, quarter_turns) => recompose(false, 4 - quarter_turns),
+    }
+}
+
+//Orientation of the same image once its pixels have been transformed by `transform`,
+//so that it is still displayed as the old view transformed the same way
+pub fn conjugate(orientation: Orientation, transform: Orientation) -> Orientation {
+    compose(compose(inverse(transform), orientation), transform)
+}
+
+//Transforms stored pixels into the upright view described by `orientation`
+pub fn apply(image: &DynamicImage, orientation: Orientation) -> DynamicImage {
+    let (flip, quarter_turns) = decompose(orientation);
+    let flipped;
+    let image = if flip {
+        flipped = image.fliph();
+        &flipped
+    } else {
+        image
+    };
+
+    match quarter_turns {
+        1 => image.rotate90(),
+        2 => image.rotate180(),
+        3 => image.rotate270(),
+        _ => image.clone(),
+    }
+}
+
+//Quarter and three quarter turns exchange the width and the height
+pub fn swaps_dimensions(orientation: Orientation) -> bool {
+    decompose(orientation).1 % 2 == 1
+}
+
+pub fn oriented_dimensions(width: u32, height: u32, orientation: Orientation) -> (u32, u32) {
+    if swaps_dimensions(orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+//Same as `apply` on a raw interleaved buffer holding `channels` samples per pixel
+pub fn apply_to_buffer<T: Copy>(samples: &[T], width: u32, height: u32, channels: usize,
+                                orientation: Orientation) -> Vec<T> {
+    let (flip, quarter_turns) = decompose(orientation);
+    let (oriented_width, _) = oriented_dimensions(width, height, orientation);
+    let mut oriented = samples.to_vec();
+
+    for y in 0..height {
+        for x in 0..width {
+            let (mut dx, mut dy, mut w, mut h) = (x, y, width, height);
+
+            if flip {
+                dx = w - 1 - dx;
+            }
+            for _ in 0..quarter_turns {
+                let rotated = (h - 1 - dy, dx, h, w);
+
+                dx = rotated.0;
+                dy = rotated.1;
+                w = rotated.2;
+                h = rotated.3;
+            }
+            let from = (y as usize * width as usize + x as usize) * channels;
+            let to = (dy as usize * oriented_width as usize + dx as usize) * channels;
+
+            oriented[to..to + channels].copy_from_slice(&samples[from..from + channels]);
+        }
+    }
+    oriented
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImage, GrayImage};
+    use super::{apply, apply_to_buffer, compose, decompose, from_exif, inverse, oriented_dimensions, recompose,
+                Orientation};
+
+    const WIDTH: u32 = 2;
+    const HEIGHT: u32 = 3;
+    //1 2
+    //3 4
+    //5 6
+    const SAMPLES: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    fn orientations() -> Vec<Orientation> {
+        (1..9).map(from_exif).collect()
+    }
+
+    fn oriented(orientation: Orientation) -> Vec<u8> {
+        apply_to_buffer(&SAMPLES, WIDTH, HEIGHT, 1, orientation)
+    }
+
+    #[test]
+    fn decompose_recompose() {
+        for orientation in orientations() {
+            let (flip, quarter_turns) = decompose(orientation);
+
+            assert_eq!(recompose(flip, quarter_turns), orientation);
+        }
+        assert_eq!(decompose(Orientation::Unspecified), (false, 0));
+    }
+
+    #[test]
+    fn buffer() {
+        let expected: [(Orientation, [u8; 6]); 8] = [
+            (Orientation::Normal, [1, 2, 3, 4, 5, 6]),
+            (Orientation::HorizontalFlip, [2, 1, 4, 3, 6, 5]),
+            (Orientation::Rotate180, [6, 5, 4, 3, 2, 1]),
+            (Orientation::VerticalFlip, [5, 6, 3, 4, 1, 2]),
+            (Orientation::Rotate90HorizontalFlip, [1, 3, 5, 2, 4, 6]),
+            (Orientation::Rotate90, [5, 3, 1, 6, 4, 2]),
+            (Orientation::Rotate90VerticalFlip, [6, 4, 2, 5, 3, 1]),
+            (Orientation::Rotate270, [2, 4, 6, 1, 3, 5]),
+        ];
+
+        for &(orientation, pixels) in &expected {
+            assert_eq!(oriented(orientation), pixels.to_vec(), "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn buffer_matches_image() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(WIDTH, HEIGHT, SAMPLES.to_vec()).unwrap());
+
+        for orientation in orientations() {
+            let (width, height) = oriented_dimensions(WIDTH, HEIGHT, orientation);
+            let applied = apply(&image, orientation);
+
+            assert_eq!(applied.dimensions(), (width, height), "{:?}", orientation);
+            assert_eq!(applied.raw_pixels(), oriented(orientation), "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn compose_applies_in_order() {
+        for first in orientations() {
+            let (width, height) = oriented_dimensions(WIDTH, HEIGHT, first);
+
+            for then in orientations() {
+                let twice = apply_to_buffer(&oriented(first), width, height, 1, then);
+
+                assert_eq!(twice, oriented(compose(first, then)), "{:?} then {:?}", first, then);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes() {
+        for orientation in orientations() {
+            assert_eq!(compose(orientation, inverse(orientation)), Orientation::Normal, "{:?}", orientation);
+            assert_eq!(compose(inverse(orientation), orientation), Orientation::Normal, "{:?}", orientation);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use gexiv2_sys as gexiv2;
+use libc::{self, c_char, c_int, c_void};
+use rexiv2::Rexiv2Error;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+//rexiv2 does not expose its gexiv2 handle nor the thumbnail functions,
+//so this is a second, minimal handle opened on the same file or buffer.
+
+pub enum GExiv2PreviewProperties {}
+pub enum GExiv2PreviewImage {}
+
+//Preview functions are part of gexiv2 but not declared by gexiv2-sys 0.7
+#[link(name = "gexiv2")]
+extern "C" {
+    fn gexiv2_metadata_get_preview_properties(this: *mut gexiv2::GExiv2Metadata) -> *mut *mut GExiv2PreviewProperties;
+    fn gexiv2_metadata_get_preview_image(this: *mut gexiv2::GExiv2Metadata,
+                                         props: *mut GExiv2PreviewProperties) -> *mut GExiv2PreviewImage;
+    fn gexiv2_preview_properties_get_mime_type(this: *mut GExiv2PreviewProperties) -> *const c_char;
+    fn gexiv2_preview_properties_get_extension(this: *mut GExiv2PreviewProperties) -> *const c_char;
+    fn gexiv2_preview_properties_get_size(this: *mut GExiv2PreviewProperties) -> u32;
+    fn gexiv2_preview_properties_get_width(this: *mut GExiv2PreviewProperties) -> u32;
+    fn gexiv2_preview_properties_get_height(this: *mut GExiv2PreviewProperties) -> u32;
+    fn gexiv2_preview_image_get_data(this: *mut GExiv2PreviewImage, size: *mut u32) -> *const u8;
+    fn gexiv2_preview_image_free(this: *mut GExiv2PreviewImage);
+}
+
+#[link(name = "glib-2.0")]
+extern "C" {
+    fn g_free(mem: *mut c_void);
+    fn g_error_free(error: *mut gexiv2::GError);
+}
+
+pub struct RawPreview {
+    pub mime_type: String,
+    pub extension: String,
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct RawMetadata {
+    raw: *mut gexiv2::GExiv2Metadata,
+}
+
+unsafe fn take_error(err: *mut gexiv2::GError) -> Rexiv2Error {
+    if err.is_null() {
+        return Rexiv2Error::Internal(None);
+    }
+    let err_msg = CStr::from_ptr((*err).message).to_str().ok().map(|msg| msg.to_string());
+
+    g_error_free(err);
+    Rexiv2Error::Internal(err_msg)
+}
+
+impl RawMetadata {
+    pub fn new_from_path(path: &Path) -> Result<RawMetadata, Rexiv2Error> {
+        let mut err: *mut gexiv2::GError = ptr::null_mut();
+        let c_str_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Rexiv2Error::Internal(Some("Path contains a nul byte".to_string())))?;
+
+        unsafe {
+            let metadata = RawMetadata { raw: gexiv2::gexiv2_metadata_new() };
+
+            if gexiv2::gexiv2_metadata_open_path(metadata.raw, c_str_path.as_ptr(), &mut err) != 1 {
+                return Err(take_error(err));
+            }
+            Ok(metadata)
+        }
+    }
+
+    pub fn new_from_buffer(data: &[u8]) -> Result<RawMetadata, Rexiv2Error> {
+        let mut err: *mut gexiv2::GError = ptr::null_mut();
+
+        unsafe {
+            let metadata = RawMetadata { raw: gexiv2::gexiv2_metadata_new() };
+
+            if gexiv2::gexiv2_metadata_open_buf(metadata.raw, data.as_ptr(), data.len() as _, &mut err) != 1 {
+                return Err(take_error(err));
+            }
+            Ok(metadata)
+        }
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<(), Rexiv2Error> {
+        let mut err: *mut gexiv2::GError = ptr::null_mut();
+        let c_str_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Rexiv2Error::Internal(Some("Path contains a nul byte".to_string())))?;
+
+        unsafe {
+            if gexiv2::gexiv2_metadata_save_file(self.raw, c_str_path.as_ptr(), &mut err) != 1 {
+                return Err(take_error(err));
+            }
+        }
+        Ok(())
+    }
+
+    //Bytes of a string tag without any charset conversion, for legacy IPTC datasets which are not UTF-8
+    pub fn get_tag_multiple_bytes(&self, tag: &str) -> Vec<Vec<u8>> {
+        let mut values = Vec::new();
+        let c_str_tag = match CString::new(tag) {
+            Ok(c_str_tag) => c_str_tag,
+            Err(_) => return values,
+        };
+
+        unsafe {
+            let c_vals = gexiv2::gexiv2_metadata_get_tag_multiple(self.raw, c_str_tag.as_ptr());
+            if c_vals.is_null() {
+                return values;
+            }
+            let mut cursor = c_vals;
+            while !(*cursor).is_null() {
+                values.push(CStr::from_ptr(*cursor).to_bytes().to_vec());
+                libc::free(*cursor as *mut c_void);
+                cursor = cursor.offset(1);
+            }
+            libc::free(c_vals as *mut c_void);
+        }
+        values
+    }
+
+    pub fn get_exif_thumbnail(&self) -> Option<Vec<u8>> {
+        let mut buffer: *mut u8 = ptr::null_mut();
+        let mut size: c_int = 0;
+
+        unsafe {
+            if gexiv2::gexiv2_metadata_get_exif_thumbnail(self.raw, &mut buffer, &mut size) != 1 || buffer.is_null() {
+                return None;
+            }
+            let thumbnail = slice::from_raw_parts(buffer, size as usize).to_vec();
+
+            g_free(buffer as *mut c_void);
+            Some(thumbnail)
+        }
+    }
+
+    pub fn set_exif_thumbnail(&self, jpeg: &[u8]) {
+        unsafe { gexiv2::gexiv2_metadata_set_exif_thumbnail_from_buffer(self.raw, jpeg.as_ptr(), jpeg.len() as c_int) }
+    }
+
+    pub fn erase_exif_thumbnail(&self) {
+        unsafe { gexiv2::gexiv2_metadata_erase_exif_thumbnail(self.raw) }
+    }
+
+    //The properties array is owned by the gexiv2 handle
+    fn preview_properties(&self) -> Vec<*mut GExiv2PreviewProperties> {
+        let mut properties = Vec::new();
+
+        unsafe {
+            let mut cursor = gexiv2_metadata_get_preview_properties(self.raw);
+
+            while !cursor.is_null() && !(*cursor).is_null() {
+                properties.push(*cursor);
+                cursor = cursor.offset(1);
+            }
+        }
+        properties
+    }
+
+    pub fn get_previews(&self) -> Vec<RawPreview> {
+        let to_string = |value: *const c_char| unsafe {
+            if value.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(value).to_string_lossy().into_owned()
+            }
+        };
+
+        self.preview_properties().into_iter().map(|properties| unsafe {
+            RawPreview {
+                mime_type: to_string(gexiv2_preview_properties_get_mime_type(properties)),
+                extension: to_string(gexiv2_preview_properties_get_extension(properties)),
+                size: gexiv2_preview_properties_get_size(properties),
+                width: gexiv2_preview_properties_get_width(properties),
+                height: gexiv2_preview_properties_get_height(properties),
+            }
+        }).collect()
+    }
+
+    pub fn get_preview_data(&self, index: usize) -> Option<Vec<u8>> {
+        let properties = *self.preview_properties().get(index)?;
+
+        unsafe {
+            let image = gexiv2_metadata_get_preview_image(self.raw, properties);
+            if image.is_null() {
+                return None;
+            }
+            let mut size: u32 = 0;
+            let data = gexiv2_preview_image_get_data(image, &mut size);
+            let preview = if data.is_null() {
+                None
+            } else {
+                Some(slice::from_raw_parts(data, size as usize).to_vec())
+            };
+
+            gexiv2_preview_image_free(image);
+            preview
+        }
+    }
+}
+
+impl Drop for RawMetadata {
+    fn drop(&mut self) {
+        unsafe { gexiv2::gexiv2_metadata_free(self.raw) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rexiv2::Rexiv2Error;
+    use super::RawMetadata;
+
+    //The message is copied out of the GError before it is freed
+    #[test]
+    fn error_message() {
+        match RawMetadata::new_from_buffer(b"not an image") {
+            Err(Rexiv2Error::Internal(Some(message))) => assert!(!message.is_empty()),
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Garbage was parsed"),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind, Write};
+use image::ColorType;
+use metadata::pages::{self, TiffValue};
+
+//Baseline uncompressed big-endian TIFF writer, the image crate only provides a decoder for this format.
+//Samples wider than 8 bits are expected in big-endian order, as for the PNG encoder.
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const SLONG: u16 = 9;
+const SRATIONAL: u16 = 10;
+
+//Tags computed from the pixels, they can not be given with the page tags
+const LAYOUT_TAGS: [u16; 11] = [256, 257, 258, 259, 262, 273, 277, 278, 279, 284, 338];
+
+pub struct TiffPage<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    pub color: ColorType,
+    //Added to the IFD of the page, replacing the default resolution
+    pub tags: &'a BTreeMap<u16, TiffValue>,
+}
+
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    //Big-endian encoded values, stored inline when they fit in 4 bytes
+    value: Vec<u8>,
+}
+
+impl Entry {
+    fn shorts(tag: u16, values: &[u16]) -> Entry {
+        let mut value = Vec::with_capacity(values.len() * 2);
+
+        for short in values {
+            value.extend_from_slice(&short.to_be_bytes());
+        }
+        Entry { tag, field_type: SHORT, count: values.len() as u32, value }
+    }
+
+    fn long(tag: u16, long: u32) -> Entry {
+        Entry { tag, field_type: LONG, count: 1, value: long.to_be_bytes().to_vec() }
+    }
+
+    fn rational(tag: u16, numerator: u32, denominator: u32) -> Entry {
+        let mut value = numerator.to_be_bytes().to_vec();
+
+        value.extend_from_slice(&denominator.to_be_bytes());
+        Entry { tag, field_type: RATIONAL, count: 1, value }
+    }
+
+    //Unsigned values are written as SHORT when they all fit
+    fn from_value(tag: u16, tiff_value: &TiffValue) -> Entry {
+        let (field_type, value) = match *tiff_value {
+            TiffValue::Bytes(ref bytes) => (BYTE, bytes.clone()),
+            TiffValue::Ascii(ref text) => {
+                let mut value = text.as_bytes().to_vec();
+
+                value.push(0);
+                (ASCII, value)
+            },
+            TiffValue::Unsigned(ref values) if values.iter().all(|&value| value <= u16::MAX as u32) =>
+                (SHORT, values.iter().flat_map(|&value| (value as u16).to_be_bytes()).collect()),
+            TiffValue::Unsigned(ref values) => (LONG, values.iter().flat_map(|value| value.to_be_bytes()).collect()),
+            TiffValue::Signed(ref values) => (SLONG, values.iter().flat_map(|value| value.to_be_bytes()).collect()),
+            TiffValue::Rational(ref values) => (RATIONAL, values.iter()
+                .flat_map(|ratio| IntoIterator::into_iter(ratio.numer().to_be_bytes()).chain(ratio.denom().to_be_bytes()))
+                .collect()),
+            TiffValue::SignedRational(ref values) => (SRATIONAL, values.iter()
+                .flat_map(|ratio| IntoIterator::into_iter(ratio.numer().to_be_bytes()).chain(ratio.denom().to_be_bytes()))
+                .collect()),
+            TiffValue::Other(field_type, ref bytes) => (field_type, bytes.clone()),
+        };
+
+        Entry { tag, field_type, count: (value.len() / pages::type_size(field_type)) as u32, value }
+    }
+}
+
+//Returns (samples per pixel, bits per sample, photometric interpretation, has alpha)
+fn sample_layout(color: ColorType) -> IoResult<(u16, u16, u16, bool)> {
+    let layout = match color {
+        ColorType::Gray(bits) => (1, bits, 1, false),
+        ColorType::GrayA(bits) => (2, bits, 1, true),
+        ColorType::RGB(bits) => (3, bits, 2, false),
+        ColorType::RGBA(bits) => (4, bits, 2, true),
+        ColorType::Palette(_) => return Err(IoError::new(ErrorKind::InvalidInput,
+                                                         "Palette images can not be written as TIFF")),
+    };
+
+    match layout.1 {
+        8 | 16 => Ok((layout.0, layout.1 as u16, layout.2, layout.3)),
+        _ => Err(IoError::new(ErrorKind::InvalidInput, "Only 8 and 16 bits samples can be written as TIFF")),
+    }
+}
+
+fn page_entries(page: &TiffPage, strip_offset: u32) -> IoResult<Vec<Entry>> {
+    let (samples, bits, photometric, alpha) = sample_layout(page.color)?;
+    let strip_len = page.width as u64 * page.height as u64 * samples as u64 * (bits as u64 / 8);
+
+    if strip_len != page.data.len() as u64 {
+        return Err(IoError::new(ErrorKind::InvalidInput, "Pixel buffer does not match the image dimensions"));
+    }
+
+    let mut entries = vec![
+        Entry::long(256, page.width),
+        Entry::long(257, page.height),
+        Entry::shorts(258, &vec![bits; samples as usize]),
+        Entry::shorts(259, &[1]),
+        Entry::shorts(262, &[photometric]),
+        Entry::long(273, strip_offset),
+        Entry::shorts(277, &[samples]),
+        Entry::long(278, page.height),
+        Entry::long(279, strip_len as u32),
+        Entry::rational(282, 72, 1),
+        Entry::rational(283, 72, 1),
+        Entry::shorts(284, &[1]),
+        Entry::shorts(296, &[2]),
+    ];
+    if alpha {
+        entries.push(Entry::shorts(338, &[2]));
+    }
+    for (&tag, value) in page.tags.iter().filter(|&(tag, _)| !LAYOUT_TAGS.contains(tag)) {
+        entries.retain(|entry| entry.tag != tag);
+        entries.push(Entry::from_value(tag, value));
+    }
+    entries.sort_by_key(|entry| entry.tag);
+    Ok(entries)
+}
+
+//Layout: header, then for every page its pixel strip, its IFD and the out-of-line IFD values
+pub fn write_tiff<W: Write>(writer: &mut W, pages: &[TiffPage]) -> IoResult<()> {
+    if pages.is_empty() {
+        return Err(IoError::new(ErrorKind::InvalidInput, "A TIFF file needs at least one page"));
+    }
+
+    let mut output = b"MM\x00\x2a".to_vec();
+    output.extend_from_slice(&[0; 4]);
+    write_pages(&mut output, 4, pages)?;
+
+    writer.write_all(&output)
+}
+
+//Links the pages after the last IFD of a big-endian TIFF file
+pub fn append_pages(output: &mut Vec<u8>, pages: &[TiffPage]) -> IoResult<()> {
+    let invalid = || IoError::new(ErrorKind::InvalidData, "Pages can only be added to a big-endian TIFF file");
+
+    if !output.starts_with(b"MM") {
+        return Err(invalid());
+    }
+    let last = pages::read_pages(output).ok().and_then(|pages| pages.last().cloned()).ok_or_else(invalid)?;
+    let count = u16::from_be_bytes([output[last.offset as usize], output[last.offset as usize + 1]]) as usize;
+
+    if output.len() % 2 == 1 {
+        output.push(0);
+    }
+    write_pages(output, last.offset as usize + 2 + count * 12, pages)
+}
+
+//`next_ifd_pointer` is the position of the offset to update with the first written IFD
+fn write_pages(output: &mut Vec<u8>, mut next_ifd_pointer: usize, pages: &[TiffPage]) -> IoResult<()> {
+    for page in pages {
+        let strip_offset = output.len() as u32;
+        let entries = page_entries(page, strip_offset)?;
+
+        output.extend_from_slice(page.data);
+        if output.len() % 2 == 1 {
+            output.push(0);
+        }
+
+        let ifd_offset = output.len() as u32;
+        output[next_ifd_pointer..next_ifd_pointer + 4].copy_from_slice(&ifd_offset.to_be_bytes());
+
+        let ifd_len = 2 + entries.len() * 12 + 4;
+        let mut extra_offset = ifd_offset as usize + ifd_len;
+        let mut extra = Vec::new();
+
+        output.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for entry in &entries {
+            output.extend_from_slice(&entry.tag.to_be_bytes());
+            output.extend_from_slice(&entry.field_type.to_be_bytes());
+            output.extend_from_slice(&entry.count.to_be_bytes());
+            if entry.value.len() <= 4 {
+                let mut inline = [0u8; 4];
+
+                inline[..entry.value.len()].copy_from_slice(&entry.value);
+                output.extend_from_slice(&inline);
+            } else {
+                output.extend_from_slice(&(extra_offset as u32).to_be_bytes());
+                extra.extend_from_slice(&entry.value);
+                extra_offset += entry.value.len();
+                //Values must start on a word boundary
+                if entry.value.len() % 2 == 1 {
+                    extra.push(0);
+                    extra_offset += 1;
+                }
+            }
+        }
+        next_ifd_pointer = output.len();
+        output.extend_from_slice(&[0; 4]);
+        output.extend_from_slice(&extra);
+        if output.len() % 2 == 1 {
+            output.push(0);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use rexiv2::*;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::result::Result;
+use image::*;
+use metadata::{DecoderWithMetadata, Rexiv2ImageError};
+use encoder::{self, EncoderWithMetadata};
+use orientation;
+
+//Tags holding the pixel dimensions, kept in sync with the wrapped image when they exist
+const WIDTH_TAGS: [&str; 2] = ["Exif.Photo.PixelXDimension", "Exif.Image.ImageWidth"];
+const HEIGHT_TAGS: [&str; 2] = ["Exif.Photo.PixelYDimension", "Exif.Image.ImageLength"];
+
+pub struct ImageWithMetadata {
+    pub image: DynamicImage,
+    pub metadata: Metadata,
+    //Set when the source carried an EXIF thumbnail, a fresh one is generated from the pixels when saving
+    thumbnail: bool,
+}
+
+impl ImageWithMetadata {
+    pub fn new(image: DynamicImage, metadata: Metadata) -> ImageWithMetadata {
+        let thumbnail = metadata.has_tag("Exif.Thumbnail.JPEGInterchangeFormat");
+
+        ImageWithMetadata { image, metadata, thumbnail }
+    }
+
+    pub fn from_decoder<R: Read + Seek>(mut decoder: DecoderWithMetadata<R>)
+                                        -> Result<ImageWithMetadata, Rexiv2ImageError> {
+        let image = decoder.read_dynamic_image()?;
+
+        Ok(ImageWithMetadata::new(image, decoder.metadata))
+    }
+
+    pub fn open(path: &Path) -> Result<ImageWithMetadata, Rexiv2ImageError> {
+        ImageWithMetadata::from_decoder(DecoderWithMetadata::open(path)?)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, filter: FilterType) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.resize(width, height, filter);
+        self.update_dimensions()
+    }
+
+    pub fn resize_exact(&mut self, width: u32, height: u32, filter: FilterType) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.resize_exact(width, height, filter);
+        self.update_dimensions()
+    }
+
+    //Coordinates are in stored pixels, before the orientation is applied
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.crop(x, y, width, height);
+        self.update_dimensions()
+    }
+
+    pub fn rotate90(&mut self) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.rotate90();
+        self.transformed(Orientation::Rotate90)
+    }
+
+    pub fn rotate180(&mut self) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.rotate180();
+        self.transformed(Orientation::Rotate180)
+    }
+
+    pub fn rotate270(&mut self) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.rotate270();
+        self.transformed(Orientation::Rotate270)
+    }
+
+    pub fn fliph(&mut self) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.fliph();
+        self.transformed(Orientation::HorizontalFlip)
+    }
+
+    pub fn flipv(&mut self) -> Result<(), Rexiv2ImageError> {
+        self.image = self.image.flipv();
+        self.transformed(Orientation::VerticalFlip)
+    }
+
+    pub fn save(&self, path: &Path, format: ImageFormat) -> Result<(), Rexiv2ImageError> {
+        self.encoder(format)?.encode_image_to_file(&self.image, path)
+    }
+
+    pub fn to_buffer(&self, format: ImageFormat) -> Result<Vec<u8>, Rexiv2ImageError> {
+        self.encoder(format)?.encode_image_to_buffer(&self.image)
+    }
+
+    fn encoder<'a>(&'a self, format: ImageFormat) -> Result<EncoderWithMetadata<'a>, Rexiv2ImageError> {
+        let encoder = EncoderWithMetadata::new(&self.metadata, format)?;
+
+        if self.thumbnail && format != ImageFormat::PNG {
+            return Ok(encoder.exif_thumbnail(encoder::make_exif_thumbnail(&self.image)?));
+        }
+        Ok(encoder)
+    }
+
+    //The orientation is updated so that the image is still displayed as the old view, transformed the same way
+    fn transformed(&mut self, transform: Orientation) -> Result<(), Rexiv2ImageError> {
+        let current = self.metadata.get_orientation();
+
+        if current != Orientation::Unspecified {
+            self.metadata.set_orientation(orientation::conjugate(current, transform));
+        }
+        self.update_dimensions()
+    }
+
+    fn update_dimensions(&self) -> Result<(), Rexiv2ImageError> {
+        let (width, height) = self.image.dimensions();
+
+        for tag in WIDTH_TAGS.iter().filter(|tag| self.metadata.has_tag(tag)) {
+            self.metadata.set_tag_numeric(tag, width as i32)?;
+        }
+        for tag in HEIGHT_TAGS.iter().filter(|tag| self.metadata.has_tag(tag)) {
+            self.metadata.set_tag_numeric(tag, height as i32)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, DynamicImage, GenericImage, GrayImage, ImageFormat, Luma};
+    use image::jpeg::JPEGEncoder;
+    use rexiv2::{Metadata, Orientation};
+    use metadata::DecoderWithMetadata;
+    use encoder::{self, EncoderWithMetadata};
+    use raw::RawMetadata;
+    use super::ImageWithMetadata;
+
+    //JPEG with an EXIF thumbnail, displayed mirrored
+    fn source() -> Vec<u8> {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(40, 30, |x, y| Luma([(x * 6 + y) as u8])));
+        let mut jpeg = Vec::new();
+
+        JPEGEncoder::new(&mut jpeg).encode(&image.raw_pixels(), 40, 30, ColorType::Gray(8)).unwrap();
+        let metadata = Metadata::new_from_buffer(&jpeg).unwrap();
+
+        metadata.set_tag_numeric("Exif.Photo.PixelXDimension", 40).unwrap();
+        metadata.set_tag_numeric("Exif.Photo.PixelYDimension", 30).unwrap();
+        metadata.set_orientation(Orientation::HorizontalFlip);
+        EncoderWithMetadata::new(&metadata, ImageFormat::JPEG).unwrap()
+            .exif_thumbnail(encoder::make_exif_thumbnail(&image).unwrap())
+            .encode_image_to_buffer(&image).unwrap()
+    }
+
+    #[test]
+    fn rotate_and_save() {
+        let decoder = DecoderWithMetadata::from_buffer(source(), ImageFormat::JPEG).unwrap();
+        let mut image = ImageWithMetadata::from_decoder(decoder).unwrap();
+
+        image.rotate90().unwrap();
+        let saved = image.to_buffer(ImageFormat::JPEG).unwrap();
+        let metadata = Metadata::new_from_buffer(&saved).unwrap();
+        let thumbnail = RawMetadata::new_from_buffer(&saved).unwrap().get_exif_thumbnail().unwrap();
+
+        assert_eq!(metadata.get_tag_numeric("Exif.Photo.PixelXDimension"), 30);
+        assert_eq!(metadata.get_tag_numeric("Exif.Photo.PixelYDimension"), 40);
+        //The mirror is now along the other axis of the stored pixels
+        assert_eq!(metadata.get_orientation(), Orientation::VerticalFlip);
+        assert_eq!(::image::load_from_memory(&thumbnail).unwrap().dimensions(), (90, 120));
+    }
+
+    #[test]
+    fn thumbnail_tags_need_a_thumbnail() {
+        let decoder = DecoderWithMetadata::from_buffer(source(), ImageFormat::JPEG).unwrap();
+        let image = ImageWithMetadata::from_decoder(decoder).unwrap();
+        let encoder = EncoderWithMetadata::new(&image.metadata, ImageFormat::JPEG).unwrap();
+        let saved = Metadata::new_from_buffer(&encoder.encode_image_to_buffer(&image.image).unwrap()).unwrap();
+
+        assert!(image.metadata.get_exif_tags().unwrap().iter().any(|tag| tag.starts_with("Exif.Thumbnail.")));
+        assert!(!saved.get_exif_tags().unwrap().iter().any(|tag| tag.starts_with("Exif.Thumbnail.")));
+    }
+}